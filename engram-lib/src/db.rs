@@ -1,6 +1,9 @@
 use std::path::Path;
 
 use crate::EngramResult;
+use crate::subtitles::words::{words_from_json, words_to_json};
+use crate::sync::SyncCorrection;
+use crate::transcribe::TranscribeOptions;
 use rusqlite::{Connection, OptionalExtension, params};
 
 pub struct Database {
@@ -14,6 +17,8 @@ pub struct IndexedFile {
     pub file_size: i64,
     pub has_subtitles: bool,
     pub transcription_model: Option<String>,
+    // How Whisper was run, for transcribed files.
+    pub transcription_options: Option<TranscribeOptions>,
 }
 
 impl Database {
@@ -55,9 +60,75 @@ impl Database {
             ); 
 
             CREATE INDEX IF NOT EXISTS idx_transcriptions_file_path ON transcriptions(file_path);
+
+            CREATE TABLE IF NOT EXISTS encoding_overrides (
+                path TEXT PRIMARY KEY,
+                encoding TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS generated_sidecars (
+                path TEXT PRIMARY KEY,
+                media_path TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS sync_corrections (
+                path TEXT PRIMARY KEY,
+                offset_ms INTEGER NOT NULL,
+                scale REAL NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS root_transcribe_options (
+                root TEXT PRIMARY KEY,
+                options TEXT NOT NULL
+            );
             ",
         )?;
 
+        let version: i64 =
+            self.conn
+                .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        // Style and actor names from ASS/SSA subtitles.
+        if version < 1 {
+            self.conn.execute_batch(
+                "ALTER TABLE transcriptions ADD COLUMN style TEXT;
+                 ALTER TABLE transcriptions ADD COLUMN actor TEXT;
+                 PRAGMA user_version = 1;",
+            )?;
+        }
+
+        // Subtitle text as it was before markup was stripped.
+        if version < 2 {
+            self.conn.execute_batch(
+                "ALTER TABLE transcriptions ADD COLUMN raw_text TEXT;
+                 PRAGMA user_version = 2;",
+            )?;
+        }
+
+        // Language of the subtitle track each segment came from.
+        if version < 3 {
+            self.conn.execute_batch(
+                "ALTER TABLE transcriptions ADD COLUMN language TEXT;
+                 PRAGMA user_version = 3;",
+            )?;
+        }
+
+        // Whisper options used for each transcription, as JSON.
+        if version < 4 {
+            self.conn.execute_batch(
+                "ALTER TABLE indexed_files ADD COLUMN transcription_options TEXT;
+                 PRAGMA user_version = 4;",
+            )?;
+        }
+
+        // Timing of each word in transcribed segments, as JSON.
+        if version < 5 {
+            self.conn.execute_batch(
+                "ALTER TABLE transcriptions ADD COLUMN words TEXT;
+                 PRAGMA user_version = 5;",
+            )?;
+        }
+
         Ok(())
     }
 
@@ -65,20 +136,25 @@ impl Database {
         self.conn
             .execute(
                 "INSERT INTO indexed_files
-                    (path, modified_at, file_size, has_subtitles, transcription_model, indexed_at)
-                 VALUES (?1, ?2, ?3, ?4, ?5, unixepoch())
+                    (path, modified_at, file_size, has_subtitles, transcription_model, transcription_options, indexed_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, unixepoch())
                  ON CONFLICT(path) DO UPDATE SET
-                    modified_at         = excluded.modified_at,
-                    file_size           = excluded.file_size,
-                    has_subtitles       = excluded.has_subtitles,
-                    transcription_model = excluded.transcription_model,
-                    indexed_at          = excluded.indexed_at",
+                    modified_at           = excluded.modified_at,
+                    file_size             = excluded.file_size,
+                    has_subtitles         = excluded.has_subtitles,
+                    transcription_model   = excluded.transcription_model,
+                    transcription_options = excluded.transcription_options,
+                    indexed_at            = excluded.indexed_at",
                 params![
                     entry.path,
                     entry.modified_at,
                     entry.file_size,
                     entry.has_subtitles,
                     entry.transcription_model,
+                    entry
+                        .transcription_options
+                        .as_ref()
+                        .map(|options| options.to_json().to_string()),
                 ],
             )?;
 
@@ -88,7 +164,7 @@ impl Database {
     pub fn get_file(&self, path: &str) -> EngramResult<Option<IndexedFile>> {
         Ok(self.conn
             .query_row(
-                "SELECT path, modified_at, file_size, has_subtitles, transcription_model
+                "SELECT path, modified_at, file_size, has_subtitles, transcription_model, transcription_options
                  FROM indexed_files WHERE path = ?1",
                 params![path],
                 |row| {
@@ -98,6 +174,9 @@ impl Database {
                         file_size: row.get(2)?,
                         has_subtitles: row.get::<_, bool>(3)?,
                         transcription_model: row.get(4)?,
+                        transcription_options: row
+                            .get::<_, Option<String>>(5)?
+                            .and_then(|json| parse_options(&json)),
                     })
                 },
             ).optional()?)
@@ -143,19 +222,24 @@ impl Database {
         file_path: &str,
         segments: &[crate::subtitles::Segment],
     ) -> EngramResult<()> {
-        self.conn.execute(
-            "DELETE FROM transcriptions WHERE file_path = ?1",
-            params![file_path],
-        )?;
+        let tx = self.conn.unchecked_transaction()?;
+        replace_segments(&tx, file_path, segments)?;
+        tx.commit()?;
 
-        let mut stmt = self.conn.prepare(
-            "INSERT INTO transcriptions (file_path, start_ms, end_ms, text)
-                 VALUES (?1, ?2, ?3, ?4)",
-        )?;
+        Ok(())
+    }
 
-        for seg in segments {
-            stmt.execute(params![file_path, seg.start, seg.end, seg.text])?;
-        }
+    // The file and its segments together, so a failure keeps whatever was
+    // stored for it before.
+    pub fn store_file(
+        &self,
+        entry: &IndexedFile,
+        segments: &[crate::subtitles::Segment],
+    ) -> EngramResult<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.upsert_file(entry)?;
+        replace_segments(&tx, &entry.path, segments)?;
+        tx.commit()?;
 
         Ok(())
     }
@@ -165,7 +249,8 @@ impl Database {
         file_path: &str,
     ) -> EngramResult<Option<Vec<crate::subtitles::Segment>>> {
         let mut stmt = self.conn.prepare(
-            "SELECT start_ms, end_ms, text
+            "SELECT start_ms, end_ms, text, style, actor, raw_text, language,
+                    words
                  FROM transcriptions
                  WHERE file_path = ?1
                  ORDER BY start_ms",
@@ -177,6 +262,15 @@ impl Database {
                     start: row.get(0)?,
                     end: row.get(1)?,
                     text: row.get(2)?,
+                    style: row.get(3)?,
+                    actor: row.get(4)?,
+                    raw: row.get(5)?,
+                    language: row.get(6)?,
+                    words: row
+                        .get::<_, Option<String>>(7)?
+                        .and_then(|json| serde_json::from_str(&json).ok())
+                        .map(|value| words_from_json(&value))
+                        .unwrap_or_default(),
                 })
             })?
             .collect::<Result<_, _>>()?;
@@ -188,17 +282,206 @@ impl Database {
         }
     }
 
-    pub fn prune_missing(&self) -> EngramResult<usize> {
+    pub fn encoding_override(
+        &self,
+        path: &str,
+    ) -> EngramResult<Option<String>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT encoding FROM encoding_overrides WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    // None goes back to detecting the encoding.
+    pub fn set_encoding_override(
+        &self,
+        path: &str,
+        encoding: Option<&str>,
+    ) -> EngramResult<()> {
+        match encoding {
+            Some(encoding) => self.conn.execute(
+                "INSERT INTO encoding_overrides (path, encoding)
+                 VALUES (?1, ?2)
+                 ON CONFLICT(path) DO UPDATE SET encoding = excluded.encoding",
+                params![path, encoding],
+            )?,
+            None => self.conn.execute(
+                "DELETE FROM encoding_overrides WHERE path = ?1",
+                params![path],
+            )?,
+        };
+
+        Ok(())
+    }
+
+    pub fn sync_correction(
+        &self,
+        path: &str,
+    ) -> EngramResult<Option<SyncCorrection>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT offset_ms, scale FROM sync_corrections WHERE path = ?1",
+                params![path],
+                |row| {
+                    Ok(SyncCorrection {
+                        offset: row.get(0)?,
+                        scale: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    // None drops the correction and keeps the original timing.
+    pub fn set_sync_correction(
+        &self,
+        path: &str,
+        correction: Option<&SyncCorrection>,
+    ) -> EngramResult<()> {
+        match correction {
+            Some(correction) => self.conn.execute(
+                "INSERT INTO sync_corrections (path, offset_ms, scale)
+                 VALUES (?1, ?2, ?3)
+                 ON CONFLICT(path) DO UPDATE SET
+                    offset_ms = excluded.offset_ms,
+                    scale     = excluded.scale",
+                params![path, correction.offset, correction.scale],
+            )?,
+            None => self.conn.execute(
+                "DELETE FROM sync_corrections WHERE path = ?1",
+                params![path],
+            )?,
+        };
+
+        Ok(())
+    }
+
+    // Whisper options for files under each library root that has its own.
+    pub fn root_transcribe_options(
+        &self,
+    ) -> EngramResult<Vec<(String, TranscribeOptions)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT root, options FROM root_transcribe_options")?;
+
+        let roots = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter_map(|(root, json)| Some((root, parse_options(&json)?)))
+            .collect();
+
+        Ok(roots)
+    }
+
+    // None goes back to the options the indexer was configured with.
+    pub fn set_root_transcribe_options(
+        &self,
+        root: &str,
+        options: Option<&TranscribeOptions>,
+    ) -> EngramResult<()> {
+        match options {
+            Some(options) => self.conn.execute(
+                "INSERT INTO root_transcribe_options (root, options)
+                 VALUES (?1, ?2)
+                 ON CONFLICT(root) DO UPDATE SET options = excluded.options",
+                params![root, options.to_json().to_string()],
+            )?,
+            None => self.conn.execute(
+                "DELETE FROM root_transcribe_options WHERE root = ?1",
+                params![root],
+            )?,
+        };
+
+        Ok(())
+    }
+
+    // Subtitle files we wrote ourselves and are free to overwrite.
+    pub fn is_generated_sidecar(&self, path: &str) -> EngramResult<bool> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT 1 FROM generated_sidecars WHERE path = ?1",
+                params![path],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    pub fn add_generated_sidecar(
+        &self,
+        path: &str,
+        media_path: &str,
+    ) -> EngramResult<()> {
+        self.conn.execute(
+            "INSERT INTO generated_sidecars (path, media_path) VALUES (?1, ?2)
+             ON CONFLICT(path) DO UPDATE SET media_path = excluded.media_path",
+            params![path, media_path],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn prune_missing(&self) -> EngramResult<Vec<String>> {
         let paths = self.all_paths()?;
-        let mut removed = 0usize;
+        let mut removed = Vec::new();
 
         for path in paths {
             if !std::path::Path::new(&path).exists() {
                 self.remove_file(&path)?;
-                removed += 1;
+                removed.push(path);
             }
         }
 
         Ok(removed)
     }
 }
+
+fn parse_options(json: &str) -> Option<TranscribeOptions> {
+    serde_json::from_str(json)
+        .ok()
+        .map(|value| TranscribeOptions::from_json(&value))
+}
+
+fn replace_segments(
+    conn: &Connection,
+    file_path: &str,
+    segments: &[crate::subtitles::Segment],
+) -> EngramResult<()> {
+    conn.execute(
+        "DELETE FROM transcriptions WHERE file_path = ?1",
+        params![file_path],
+    )?;
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO transcriptions
+            (file_path, start_ms, end_ms, text, style, actor, raw_text,
+             language, words)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+
+    for seg in segments {
+        stmt.execute(params![
+            file_path,
+            seg.start,
+            seg.end,
+            seg.text,
+            seg.style,
+            seg.actor,
+            seg.raw,
+            seg.language,
+            (!seg.words.is_empty())
+                .then(|| words_to_json(&seg.words).to_string()),
+        ])?;
+    }
+
+    Ok(())
+}
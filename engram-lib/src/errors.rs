@@ -19,6 +19,10 @@ pub enum EngramError {
     WalkDirError(#[from] walkdir::Error),
     #[error("Media error: {0}")]
     MediaError(String),
+    #[error("OCR error: {0}")]
+    OcrError(String),
+    #[error("Model error: {0}")]
+    ModelError(String),
     #[error("FFmpeg error: {0}")]
     FFmpegError(#[from] ffmpeg_next::Error),
     #[error("Tantivy error: {0}")]
@@ -29,4 +33,6 @@ pub enum EngramError {
     HttpError(#[from] ureq::Error),
     #[error("Database error: {0}")]
     DatabaseError(#[from] rusqlite::Error),
+    #[error("Operation cancelled")]
+    Cancelled,
 }
@@ -0,0 +1,184 @@
+use std::path::Path;
+
+use ffmpeg_next::{Dictionary, Rational, codec, encoder, format, frame};
+
+use crate::export::caption::{CaptionRenderer, CaptionStyle};
+use crate::export::transcode::{FrameFilter, VideoOutput};
+use crate::media::{self, FrameSize, RawFrame};
+use crate::search::SearchResult;
+use crate::subtitles::Segment;
+use crate::{EngramResult, errors::EngramError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    WebP,
+}
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::WebP => "webp",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationOptions {
+    pub format: AnimationFormat,
+    pub width: u32,
+    pub fps: u32,
+    // Palette size for GIF, between 2 and 256.
+    pub colors: u16,
+    // Lossy quality for WebP, between 0 and 100.
+    pub quality: u8,
+    pub captions: bool,
+    pub caption_style: CaptionStyle,
+    // Padding around the segment, in milliseconds.
+    pub lead_in: i64,
+    pub lead_out: i64,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        Self {
+            format: AnimationFormat::Gif,
+            width: 480,
+            fps: 12,
+            colors: 256,
+            quality: 75,
+            captions: true,
+            caption_style: CaptionStyle::default(),
+            lead_in: 0,
+            lead_out: 0,
+        }
+    }
+}
+
+pub fn export_animation(
+    result: &SearchResult,
+    output: &Path,
+    options: &AnimationOptions,
+) -> EngramResult<()> {
+    export_segment_animation(
+        &result.file,
+        &result.matched_segment(),
+        output,
+        options,
+    )
+}
+
+pub fn export_segment_animation(
+    input: &Path,
+    segment: &Segment,
+    output: &Path,
+    options: &AnimationOptions,
+) -> EngramResult<()> {
+    let start = (segment.start - options.lead_in).max(0);
+    let end = segment.end + options.lead_out;
+
+    let mut frames = media::sample_frames(
+        input,
+        start,
+        end,
+        options.fps,
+        FrameSize::Width(options.width),
+    )?;
+
+    if options.captions
+        && let Some(first) = frames.first()
+    {
+        let renderer = CaptionRenderer::new(&options.caption_style)?;
+        let caption = renderer.render(&segment.text, first.width, first.height);
+        for frame in frames.iter_mut() {
+            caption.apply(frame);
+        }
+    }
+
+    encode_animation(&frames, output, options)
+}
+
+// Encodes RGBA frames, all of the same size, as a looping animation.
+pub fn encode_animation(
+    frames: &[RawFrame],
+    output: &Path,
+    options: &AnimationOptions,
+) -> EngramResult<()> {
+    let Some(first) = frames.first() else {
+        return Err(EngramError::MediaError("No frames to encode".into()));
+    };
+    let size = (first.width, first.height);
+    let fps = options.fps.max(1) as i32;
+    let time_base = Rational(1, fps);
+
+    ffmpeg_next::init()?;
+
+    let mut octx = format::output_as(output, options.format.extension())?;
+
+    let (codec, pixel, spec, codec_options) = match options.format {
+        AnimationFormat::Gif => {
+            let codec = encoder::find(codec::Id::GIF).ok_or_else(|| {
+                EngramError::MediaError("No GIF encoder available".into())
+            })?;
+            // A palette computed from the whole clip looks far better than
+            // the fixed one the encoder falls back to.
+            let spec = format!(
+                "split[a][b];[a]palettegen=max_colors={}:stats_mode=full[p];\
+                 [b][p]paletteuse=dither=sierra2_4a",
+                options.colors.clamp(2, 256)
+            );
+            (codec, format::Pixel::PAL8, spec, Dictionary::new())
+        }
+        AnimationFormat::WebP => {
+            let codec = encoder::find_by_name("libwebp_anim")
+                .or_else(|| encoder::find_by_name("libwebp"))
+                .ok_or_else(|| {
+                    EngramError::MediaError("No WebP encoder available".into())
+                })?;
+            let mut codec_options = Dictionary::new();
+            codec_options.set("quality", &options.quality.min(100).to_string());
+            (codec, format::Pixel::YUV420P, "null".into(), codec_options)
+        }
+    };
+
+    let mut filter = FrameFilter::new(size, time_base, &spec, pixel)?;
+    let mut encoder = VideoOutput::with_codec(
+        &mut octx,
+        codec,
+        pixel,
+        size,
+        time_base,
+        Rational(fps, 1),
+        codec_options,
+    )?;
+
+    let mut muxer_options = Dictionary::new();
+    muxer_options.set("loop", "0");
+    octx.write_header_with(muxer_options)?;
+
+    let mut filtered = frame::Video::empty();
+    for (pts, raw) in frames.iter().enumerate() {
+        if (raw.width, raw.height) != size {
+            continue;
+        }
+        filter.send(&raw.data, pts as i64)?;
+
+        let filter_time_base = filter.time_base();
+        while filter.receive_frame(&mut filtered) {
+            encoder.send_frame(&mut filtered, filter_time_base, &mut octx)?;
+        }
+    }
+
+    // The palette is only known once every frame has been seen.
+    filter.send_eof()?;
+    let filter_time_base = filter.time_base();
+    while filter.receive_frame(&mut filtered) {
+        encoder.send_frame(&mut filtered, filter_time_base, &mut octx)?;
+    }
+
+    encoder.finish(&mut octx)?;
+    octx.write_trailer()?;
+
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use std::path::Path;
+
+use ffmpeg_next::media::Type::Audio;
+use ffmpeg_next::{
+    Dictionary, Rescale, codec, encoder, format, frame, rescale,
+};
+
+use crate::export::transcode::{
+    AudioInput, AudioOutput, file_start_us, ms_to_seconds, seek_to, start_pts,
+};
+use crate::search::SearchResult;
+use crate::{EngramResult, errors::EngramError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Opus,
+    Wav,
+    Flac,
+}
+
+impl AudioFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Opus => "ogg",
+            AudioFormat::Wav => "wav",
+            AudioFormat::Flac => "flac",
+        }
+    }
+
+    fn encoder(&self) -> Option<ffmpeg_next::Codec> {
+        match self {
+            AudioFormat::Opus => encoder::find_by_name("libopus")
+                .or_else(|| encoder::find(codec::Id::OPUS)),
+            AudioFormat::Wav => encoder::find(codec::Id::PCM_S16LE),
+            AudioFormat::Flac => encoder::find(codec::Id::FLAC),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioExportOptions {
+    pub format: AudioFormat,
+    // Index of the stream in the container. Picks the best audio stream when
+    // None.
+    pub stream: Option<usize>,
+    // EBU R128 loudness normalization to -16 LUFS.
+    pub normalize: bool,
+    // Padding around the segment, in milliseconds.
+    pub lead_in: i64,
+    pub lead_out: i64,
+}
+
+impl Default for AudioExportOptions {
+    fn default() -> Self {
+        Self {
+            format: AudioFormat::Opus,
+            stream: None,
+            normalize: false,
+            lead_in: 250,
+            lead_out: 250,
+        }
+    }
+}
+
+pub fn export_audio(
+    result: &SearchResult,
+    output: &Path,
+    options: &AudioExportOptions,
+) -> EngramResult<()> {
+    let (start, end) = result.span();
+    let start = (start - options.lead_in).max(0);
+    let end = end + options.lead_out;
+
+    export_audio_span(&result.file, start, end, output, options)
+}
+
+pub fn export_audio_span(
+    input: &Path,
+    start: i64,
+    end: i64,
+    output: &Path,
+    options: &AudioExportOptions,
+) -> EngramResult<()> {
+    if end <= start {
+        return Err(EngramError::MediaError(format!(
+            "Invalid clip span {start}..{end}"
+        )));
+    }
+
+    ffmpeg_next::init()?;
+
+    let mut ictx = format::input(input)?;
+
+    let stream_idx = match options.stream {
+        Some(index) => {
+            let stream = ictx.stream(index).ok_or_else(|| {
+                EngramError::MediaError(format!("No stream with index {index}"))
+            })?;
+            if stream.parameters().medium() != Audio {
+                return Err(EngramError::MediaError(format!(
+                    "Stream {index} is not an audio stream"
+                )));
+            }
+            index
+        }
+        None => ictx
+            .streams()
+            .best(Audio)
+            .ok_or_else(|| {
+                EngramError::MediaError("No audio stream found".into())
+            })?
+            .index(),
+    };
+
+    let codec = options.format.encoder().ok_or_else(|| {
+        EngramError::MediaError(format!(
+            "No {} encoder available",
+            options.format.extension()
+        ))
+    })?;
+
+    let mut octx = format::output_as(output, options.format.extension())?;
+
+    let ist = ictx.stream(stream_idx).unwrap();
+    let decoder = codec::context::Context::from_parameters(ist.parameters())?
+        .decoder()
+        .audio()?;
+
+    // Clips are downmixed to stereo at most.
+    let mut audio_output = AudioOutput::new(
+        &mut octx,
+        codec,
+        decoder.rate(),
+        (decoder.channels() as i32).clamp(1, 2),
+        Dictionary::new(),
+    )?;
+
+    // Shifted to the start of the file first, as the span counts from there.
+    let mut spec = format!(
+        "asetpts=PTS-{},atrim=start={}:end={},asetpts=PTS-STARTPTS",
+        start_pts(&ist),
+        ms_to_seconds(start),
+        ms_to_seconds(end)
+    );
+    if options.normalize {
+        spec.push_str(",loudnorm=I=-16:TP=-1.5:LRA=11");
+    }
+
+    let mut audio_input = AudioInput::new(&ist, &spec, &audio_output)?;
+
+    seek_to(&mut ictx, start)?;
+
+    octx.write_header()?;
+
+    let end_us = file_start_us(&ictx) + end * 1000;
+    let mut filtered = frame::Audio::empty();
+
+    for (stream, packet) in ictx.packets() {
+        if stream.index() != stream_idx {
+            continue;
+        }
+
+        if let Some(pts) = packet.pts()
+            && pts.rescale(stream.time_base(), rescale::TIME_BASE) > end_us
+        {
+            break;
+        }
+
+        audio_input.send_packet(&packet)?;
+        let time_base = audio_input.time_base();
+        while audio_input.receive_frame(&mut filtered) {
+            audio_output.send_frame(&mut filtered, time_base, &mut octx)?;
+        }
+    }
+
+    audio_input.send_eof()?;
+    let time_base = audio_input.time_base();
+    while audio_input.receive_frame(&mut filtered) {
+        audio_output.send_frame(&mut filtered, time_base, &mut octx)?;
+    }
+
+    audio_output.finish(&mut octx)?;
+    octx.write_trailer()?;
+
+    Ok(())
+}
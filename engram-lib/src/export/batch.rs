@@ -0,0 +1,390 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rayon::prelude::*;
+use serde_json::json;
+
+use crate::cancel::CancellationToken;
+use crate::export::animation::{self, AnimationOptions};
+use crate::export::audio::{self, AudioExportOptions};
+use crate::export::clip::{self, ClipOptions};
+use crate::search::SearchResult;
+use crate::{EngramResult, errors::EngramError, subtitles};
+
+pub const DEFAULT_TEMPLATE: &str = "{file_stem}_{start}_{text}";
+pub const DEFAULT_THREADS: usize = 4;
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+// Longest `{text}` that ends up in a file name.
+const MAX_TEXT_LEN: usize = 40;
+
+#[derive(Debug, Clone)]
+pub enum ExportPreset {
+    Clip(ClipOptions),
+    Animation(AnimationOptions),
+    Audio(AudioExportOptions),
+}
+
+impl ExportPreset {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportPreset::Clip(_) => "clip",
+            ExportPreset::Animation(_) => "animation",
+            ExportPreset::Audio(_) => "audio",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportPreset::Clip(options) => options.container.extension(),
+            ExportPreset::Animation(options) => options.format.extension(),
+            ExportPreset::Audio(options) => options.format.extension(),
+        }
+    }
+
+    // The padded span of `result` in milliseconds.
+    pub fn span(&self, result: &SearchResult) -> (i64, i64) {
+        let (lead_in, lead_out) = match self {
+            ExportPreset::Clip(options) => (options.lead_in, options.lead_out),
+            ExportPreset::Animation(options) => {
+                (options.lead_in, options.lead_out)
+            }
+            ExportPreset::Audio(options) => (options.lead_in, options.lead_out),
+        };
+
+        let (start, end) = result.span();
+        ((start - lead_in).max(0), end + lead_out)
+    }
+
+    pub fn export(
+        &self,
+        result: &SearchResult,
+        output: &Path,
+    ) -> EngramResult<()> {
+        match self {
+            ExportPreset::Clip(options) => {
+                clip::export_clip(result, output, options).map(|_| ())
+            }
+            ExportPreset::Animation(options) => {
+                animation::export_animation(result, output, options)
+            }
+            ExportPreset::Audio(options) => {
+                audio::export_audio(result, output, options)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    pub preset: ExportPreset,
+    pub directory: PathBuf,
+    // Supports {file_stem}, {start}, {end}, {text} and {index}.
+    pub template: String,
+    pub threads: usize,
+}
+
+impl BatchOptions {
+    pub fn new(preset: ExportPreset, directory: PathBuf) -> Self {
+        Self {
+            preset,
+            directory,
+            template: DEFAULT_TEMPLATE.to_string(),
+            threads: DEFAULT_THREADS,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchItem {
+    pub source: PathBuf,
+    pub start: i64,
+    pub end: i64,
+    pub text: String,
+    pub output: PathBuf,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchReport {
+    pub items: Vec<BatchItem>,
+    pub manifest: PathBuf,
+    pub cancelled: bool,
+}
+
+impl BatchReport {
+    pub fn succeeded(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.error.is_none())
+            .count()
+    }
+}
+
+// Where exports go when the user has not picked a directory.
+pub fn default_export_dir() -> EngramResult<PathBuf> {
+    dirs::video_dir()
+        .or_else(dirs::home_dir)
+        .map(|dir| dir.join("Engram Exports"))
+        .ok_or_else(|| {
+            EngramError::IoError(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Export directory not found",
+            ))
+        })
+}
+
+pub fn export_batch(
+    results: &[SearchResult],
+    options: &BatchOptions,
+) -> EngramResult<BatchReport> {
+    export_batch_cancellable(results, options, &CancellationToken::new())
+}
+
+pub fn export_batch_cancellable(
+    results: &[SearchResult],
+    options: &BatchOptions,
+    cancel: &CancellationToken,
+) -> EngramResult<BatchReport> {
+    std::fs::create_dir_all(&options.directory)?;
+
+    // Names are picked up front so parallel exports never collide.
+    let mut taken = HashSet::new();
+    let mut items: Vec<BatchItem> = results
+        .iter()
+        .enumerate()
+        .map(|(index, result)| {
+            let (start, end) = options.preset.span(result);
+            let name =
+                render_template(&options.template, result, start, end, index);
+            let output = unique_path(
+                &options.directory,
+                &name,
+                options.preset.extension(),
+                &mut taken,
+            );
+
+            BatchItem {
+                source: result.file.clone(),
+                start,
+                end,
+                text: result.segment.text.clone(),
+                output,
+                error: None,
+            }
+        })
+        .collect();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads.max(1))
+        .build()
+        .map_err(|e| EngramError::MediaError(e.to_string()))?;
+
+    pool.install(|| {
+        items.par_iter_mut().zip(results.par_iter()).for_each(
+            |(item, result)| {
+                let outcome = cancel
+                    .check()
+                    .and_then(|_| options.preset.export(result, &item.output));
+                if let Err(e) = outcome {
+                    item.error = Some(e.to_string());
+                }
+            },
+        );
+    });
+
+    let manifest = options.directory.join(MANIFEST_NAME);
+    std::fs::write(&manifest, manifest_json(&options.preset, &items))?;
+
+    Ok(BatchReport {
+        items,
+        manifest,
+        cancelled: cancel.is_cancelled(),
+    })
+}
+
+fn manifest_json(preset: &ExportPreset, items: &[BatchItem]) -> String {
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let items: Vec<serde_json::Value> = items
+        .iter()
+        .map(|item| {
+            json!({
+                "source": item.source.to_string_lossy(),
+                "start_ms": item.start,
+                "end_ms": item.end,
+                "text": item.text,
+                "output": item.output.file_name().map(|n| n.to_string_lossy()),
+                "error": item.error,
+            })
+        })
+        .collect();
+
+    let manifest = json!({
+        "preset": preset.name(),
+        "created_at": created_at,
+        "items": items,
+    });
+
+    serde_json::to_string_pretty(&manifest).unwrap_or_default()
+}
+
+pub fn render_template(
+    template: &str,
+    result: &SearchResult,
+    start: i64,
+    end: i64,
+    index: usize,
+) -> String {
+    let stem = result
+        .file
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let name = template
+        .replace("{file_stem}", &stem)
+        .replace("{start}", &file_timestamp(start))
+        .replace("{end}", &file_timestamp(end))
+        .replace("{text}", &slug(&result.segment.text, MAX_TEXT_LEN))
+        .replace("{index}", &(index + 1).to_string());
+
+    // The template itself may not introduce directories.
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+// 00:01:02,345 becomes 00-01-02-345, which is safe on every file system.
+fn file_timestamp(ms: i64) -> String {
+    subtitles::format_timestamp(ms).replace([':', ','], "-")
+}
+
+fn slug(text: &str, max_len: usize) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('_') {
+            slug.push('_');
+        }
+
+        if slug.chars().count() >= max_len {
+            break;
+        }
+    }
+
+    slug.trim_end_matches('_').to_string()
+}
+
+fn unique_path(
+    directory: &Path,
+    name: &str,
+    extension: &str,
+    taken: &mut HashSet<PathBuf>,
+) -> PathBuf {
+    let name = if name.is_empty() { "clip" } else { name };
+
+    let mut candidate = directory.join(format!("{name}.{extension}"));
+    let mut counter = 2;
+    while taken.contains(&candidate) || candidate.exists() {
+        candidate = directory.join(format!("{name}_{counter}.{extension}"));
+        counter += 1;
+    }
+
+    taken.insert(candidate.clone());
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(file: &str, text: &str) -> SearchResult {
+        SearchResult {
+            file: PathBuf::from(file),
+            segment: subtitles::Segment {
+                start: 61_000,
+                end: 62_500,
+                text: text.to_string(),
+                ..Default::default()
+            },
+            score: 1.0,
+            matched: None,
+        }
+    }
+
+    #[test]
+    fn slugs_text() {
+        assert_eq!(slug("  Hello, world!  ", 40), "Hello_world");
+        assert_eq!(slug("Ça va? Très bien.", 40), "Ça_va_Très_bien");
+        assert_eq!(slug("abc def ghi", 5), "abc_d");
+        assert_eq!(slug("abc def", 4), "abc");
+        assert_eq!(slug("?!", 40), "");
+    }
+
+    #[test]
+    fn renders_templates() {
+        let result = result("/media/movie.mkv", "Where: is it?");
+        assert_eq!(
+            render_template(DEFAULT_TEMPLATE, &result, 60_750, 62_750, 0),
+            "movie_00-01-00-750_Where_is_it"
+        );
+        // Separators from the template never make it into the name.
+        assert_eq!(
+            render_template("{index}/{end}", &result, 0, 62_750, 2),
+            "3_00-01-02-750"
+        );
+    }
+
+    #[test]
+    fn picks_unique_paths() {
+        let dir = std::env::temp_dir()
+            .join(format!("engram-batch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("existing.mp4"), b"").unwrap();
+
+        let mut taken = HashSet::new();
+        let mut path = |name| unique_path(&dir, name, "mp4", &mut taken);
+        assert_eq!(path("a"), dir.join("a.mp4"));
+        assert_eq!(path("a"), dir.join("a_2.mp4"));
+        assert_eq!(path("a"), dir.join("a_3.mp4"));
+        assert_eq!(path("existing"), dir.join("existing_2.mp4"));
+        assert_eq!(path(""), dir.join("clip.mp4"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lists_outputs_by_name_in_the_manifest() {
+        let preset = ExportPreset::Clip(ClipOptions::default());
+        let items = [BatchItem {
+            source: PathBuf::from("/media/movie.mkv"),
+            start: 1000,
+            end: 2000,
+            text: "Hello".to_string(),
+            output: PathBuf::from("/exports/movie_hello.mp4"),
+            error: Some("failed".to_string()),
+        }];
+
+        let manifest: serde_json::Value =
+            serde_json::from_str(&manifest_json(&preset, &items)).unwrap();
+        assert_eq!(manifest["preset"], "clip");
+        let item = &manifest["items"][0];
+        assert_eq!(item["source"], "/media/movie.mkv");
+        assert_eq!(item["output"], "movie_hello.mp4");
+        assert_eq!(
+            (item["start_ms"].as_i64(), item["end_ms"].as_i64()),
+            (Some(1000), Some(2000))
+        );
+        assert_eq!(item["error"], "failed");
+    }
+}
@@ -0,0 +1,262 @@
+use std::path::PathBuf;
+
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont, point};
+
+use crate::media::RawFrame;
+use crate::{EngramResult, errors::EngramError};
+
+const FONT_BYTES: &[u8] =
+    include_bytes!("../../../assets/fonts/Courier New.ttf");
+const BOLD_FONT_BYTES: &[u8] =
+    include_bytes!("../../../assets/fonts/Courier New Bold.ttf");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptionFont {
+    Regular,
+    Bold,
+    // Any TrueType or OpenType font on disk.
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptionPosition {
+    Top,
+    Center,
+    Bottom,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptionStyle {
+    pub font: CaptionFont,
+    // Font size as a fraction of the frame height.
+    pub size: f32,
+    // Outline width in pixels, 0 disables it.
+    pub outline: u32,
+    pub position: CaptionPosition,
+}
+
+impl Default for CaptionStyle {
+    fn default() -> Self {
+        Self {
+            font: CaptionFont::Bold,
+            size: 1.0 / 12.0,
+            outline: 2,
+            position: CaptionPosition::Bottom,
+        }
+    }
+}
+
+// Loads the font once so many captions can be rendered with it.
+pub struct CaptionRenderer {
+    font: FontArc,
+    style: CaptionStyle,
+}
+
+impl CaptionRenderer {
+    pub fn new(style: &CaptionStyle) -> EngramResult<Self> {
+        let font = match &style.font {
+            CaptionFont::Regular => FontArc::try_from_slice(FONT_BYTES),
+            CaptionFont::Bold => FontArc::try_from_slice(BOLD_FONT_BYTES),
+            CaptionFont::File(path) => {
+                FontArc::try_from_vec(std::fs::read(path)?)
+            }
+        }
+        .map_err(|e| EngramError::MediaError(e.to_string()))?;
+
+        Ok(Self {
+            font,
+            style: style.clone(),
+        })
+    }
+
+    pub fn render(&self, text: &str, width: u32, height: u32) -> Caption {
+        let font = &self.font;
+        let px = (height as f32 * self.style.size).max(8.0);
+        let scaled = font.as_scaled(PxScale::from(px));
+
+        let margin = (px / 2.0).max(4.0) + self.style.outline as f32;
+        let max_width = (width as f32 - margin * 2.0).max(px);
+        let lines = wrap(text, max_width, |line| measure(&scaled, line));
+
+        let mut fill = vec![0.0; (width * height) as usize];
+        let line_height = scaled.height() + scaled.line_gap();
+        let mut baseline = match self.style.position {
+            CaptionPosition::Top => margin + scaled.ascent(),
+            CaptionPosition::Center => {
+                let block = scaled.height()
+                    + line_height * lines.len().saturating_sub(1) as f32;
+                (height as f32 - block) / 2.0 + scaled.ascent()
+            }
+            CaptionPosition::Bottom => {
+                height as f32
+                    - margin
+                    - scaled.descent().abs()
+                    - line_height * lines.len().saturating_sub(1) as f32
+            }
+        };
+
+        for line in &lines {
+            let mut x = (width as f32 - measure(&scaled, line)) / 2.0;
+            let mut previous = None;
+
+            for c in line.chars() {
+                let id = font.glyph_id(c);
+                if let Some(previous) = previous {
+                    x += scaled.kern(previous, id);
+                }
+                previous = Some(id);
+
+                let glyph = id.with_scale_and_position(px, point(x, baseline));
+                x += scaled.h_advance(id);
+
+                let Some(outlined) = font.outline_glyph(glyph) else {
+                    continue;
+                };
+                let bounds = outlined.px_bounds();
+                outlined.draw(|gx, gy, coverage| {
+                    let cx = bounds.min.x as i32 + gx as i32;
+                    let cy = bounds.min.y as i32 + gy as i32;
+                    if cx >= 0
+                        && cy >= 0
+                        && (cx as u32) < width
+                        && (cy as u32) < height
+                    {
+                        let i = (cy as u32 * width + cx as u32) as usize;
+                        fill[i] = (fill[i] + coverage).min(1.0);
+                    }
+                });
+            }
+
+            baseline += line_height;
+        }
+
+        let outline = dilate(&fill, width, height, self.style.outline as i32);
+
+        Caption {
+            width,
+            height,
+            fill,
+            outline,
+        }
+    }
+}
+
+// Coverage masks for a caption, rendered once and blended onto every frame.
+pub struct Caption {
+    width: u32,
+    height: u32,
+    fill: Vec<f32>,
+    outline: Vec<f32>,
+}
+
+impl Caption {
+    pub fn apply(&self, frame: &mut RawFrame) {
+        if frame.width != self.width || frame.height != self.height {
+            return;
+        }
+
+        self.apply_rgba(&mut frame.data, self.width as usize * 4);
+    }
+
+    // White text with a black outline, so it stays readable on any frame.
+    // `data` holds RGBA rows `stride` bytes apart.
+    pub fn apply_rgba(&self, data: &mut [u8], stride: usize) {
+        let width = self.width as usize;
+
+        for (y, line) in data
+            .chunks_mut(stride)
+            .take(self.height as usize)
+            .enumerate()
+        {
+            for (x, pixel) in line[..width * 4].chunks_exact_mut(4).enumerate()
+            {
+                let i = y * width + x;
+                let outline = self.outline[i];
+                let fill = self.fill[i];
+                if outline == 0.0 && fill == 0.0 {
+                    continue;
+                }
+
+                for channel in &mut pixel[..3] {
+                    let value = *channel as f32 * (1.0 - outline);
+                    *channel = (value * (1.0 - fill) + 255.0 * fill) as u8;
+                }
+            }
+        }
+    }
+}
+
+fn measure<F: Font, S: ScaleFont<F>>(font: &S, line: &str) -> f32 {
+    let mut width = 0.0;
+    let mut previous = None;
+
+    for c in line.chars() {
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, id);
+        }
+        width += font.h_advance(id);
+        previous = Some(id);
+    }
+
+    width
+}
+
+// Greedy word wrap. Words wider than a whole line are left to overflow.
+fn wrap(
+    text: &str,
+    max_width: f32,
+    measure: impl Fn(&str) -> f32,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut current = String::new();
+
+        for word in paragraph.split_whitespace() {
+            let candidate = if current.is_empty() {
+                word.to_string()
+            } else {
+                format!("{current} {word}")
+            };
+
+            if measure(&candidate) > max_width && !current.is_empty() {
+                lines.push(std::mem::replace(&mut current, word.to_string()));
+            } else {
+                current = candidate;
+            }
+        }
+
+        if !current.is_empty() {
+            lines.push(current);
+        }
+    }
+
+    lines
+}
+
+fn dilate(mask: &[f32], width: u32, height: u32, radius: i32) -> Vec<f32> {
+    if radius <= 0 {
+        return mask.to_vec();
+    }
+
+    let (w, h) = (width as i32, height as i32);
+    let mut out = vec![0.0; mask.len()];
+
+    for y in 0..h {
+        for x in 0..w {
+            let mut value: f32 = 0.0;
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx >= 0 && ny >= 0 && nx < w && ny < h {
+                        value = value.max(mask[(ny * w + nx) as usize]);
+                    }
+                }
+            }
+            out[(y * w + x) as usize] = value;
+        }
+    }
+
+    out
+}
@@ -0,0 +1,578 @@
+use std::path::{Path, PathBuf};
+
+use ffmpeg_next::media::Type::{Audio, Video};
+use ffmpeg_next::software::scaling::{self, flag::Flags};
+use ffmpeg_next::{
+    Dictionary, Rational, Rescale, codec, encoder, format, frame, rescale,
+};
+
+use crate::db::Database;
+use crate::export::caption::{Caption, CaptionRenderer, CaptionStyle};
+use crate::export::snippet;
+use crate::export::transcode::{
+    AudioInput, AudioOutput, VideoInput, VideoOutput, file_start_us,
+    ms_to_seconds, seek_to, start_pts,
+};
+use crate::search::SearchResult;
+use crate::subtitles::{self, Segment};
+use crate::{EngramResult, errors::EngramError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipContainer {
+    Mp4,
+    Mkv,
+}
+
+impl ClipContainer {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ClipContainer::Mp4 => "mp4",
+            ClipContainer::Mkv => "mkv",
+        }
+    }
+
+    pub(crate) fn format_name(&self) -> &'static str {
+        match self {
+            ClipContainer::Mp4 => "mp4",
+            ClipContainer::Mkv => "matroska",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipMode {
+    // Copies the compressed packets. Fast and lossless, but the clip starts on
+    // the keyframe before the requested start.
+    StreamCopy,
+    // Decodes and encodes the span so it starts and ends exactly where asked.
+    Reencode,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClipOptions {
+    pub container: ClipContainer,
+    pub mode: ClipMode,
+    // Padding around the segment, in milliseconds.
+    pub lead_in: i64,
+    pub lead_out: i64,
+    // Renders the subtitles into the frames. Implies re-encoding.
+    pub burn_subtitles: Option<CaptionStyle>,
+}
+
+impl Default for ClipOptions {
+    fn default() -> Self {
+        Self {
+            container: ClipContainer::Mp4,
+            mode: ClipMode::StreamCopy,
+            lead_in: 250,
+            lead_out: 250,
+            burn_subtitles: None,
+        }
+    }
+}
+
+impl ClipOptions {
+    // Returns the padded (start, end) span in milliseconds.
+    pub fn span(&self, start: i64, end: i64) -> (i64, i64) {
+        ((start - self.lead_in).max(0), end + self.lead_out)
+    }
+}
+
+pub fn export_clip(
+    result: &SearchResult,
+    output: &Path,
+    options: &ClipOptions,
+) -> EngramResult<i64> {
+    let (start, end) = result.span();
+    let (start, end) = options.span(start, end);
+    export_span(
+        &result.file,
+        start,
+        end,
+        &[result.matched_segment()],
+        output,
+        options,
+    )
+}
+
+// Exports the clip along with `.srt` and `.vtt` snippets of the subtitles
+// around it. Returns the paths of the snippets.
+pub fn export_clip_with_snippets(
+    db: &Database,
+    result: &SearchResult,
+    output: &Path,
+    options: &ClipOptions,
+) -> EngramResult<(PathBuf, PathBuf)> {
+    let (start, end) = result.span();
+    let (start, end) = options.span(start, end);
+    let segments = snippet::stored_segments(db, result)?;
+
+    let start =
+        export_span(&result.file, start, end, &segments, output, options)?;
+
+    let retimed = subtitles::retime(&segments, start, end);
+    snippet::write_snippets(&retimed, output)
+}
+
+// `subtitles` are in source time and are only used when burning them in.
+// Returns where the clip really starts in the source, in milliseconds, which
+// is the keyframe before `start` for stream copies.
+pub fn export_span(
+    input: &Path,
+    start: i64,
+    end: i64,
+    subtitles: &[Segment],
+    output: &Path,
+    options: &ClipOptions,
+) -> EngramResult<i64> {
+    if end <= start {
+        return Err(EngramError::MediaError(format!(
+            "Invalid clip span {start}..{end}"
+        )));
+    }
+
+    ffmpeg_next::init()?;
+
+    let mut ictx = format::input(input)?;
+    let mut octx = format::output_as(output, options.container.format_name())?;
+
+    seek_to(&mut ictx, start)?;
+
+    match (&options.burn_subtitles, options.mode) {
+        (None, ClipMode::StreamCopy) => {
+            copy_span(&mut ictx, &mut octx, start, end)
+        }
+        (None, ClipMode::Reencode) => {
+            reencode_span(&mut ictx, &mut octx, start, end, None)
+        }
+        (Some(style), _) => {
+            let burner = Burner {
+                renderer: CaptionRenderer::new(style)?,
+                segments: subtitles::retime(subtitles, start, end),
+                captions: Vec::new(),
+                scaler: None,
+            };
+            reencode_span(&mut ictx, &mut octx, start, end, Some(burner))
+        }
+    }
+}
+
+fn copy_span(
+    ictx: &mut format::context::Input,
+    octx: &mut format::context::Output,
+    start: i64,
+    end: i64,
+) -> EngramResult<i64> {
+    let video = ictx.streams().best(Video).map(|s| s.index());
+    let audio = ictx.streams().best(Audio).map(|s| s.index());
+
+    let mut mapping = vec![None; ictx.nb_streams() as usize];
+    for index in [video, audio].into_iter().flatten() {
+        let ist = ictx.stream(index).unwrap();
+        let mut ost = octx.add_stream(encoder::find(codec::Id::None))?;
+        ost.set_parameters(ist.parameters());
+        // Clear the codec tag so the packets can move between containers.
+        unsafe {
+            (*ost.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+        mapping[index] = Some(ost.index());
+    }
+
+    octx.write_header()?;
+
+    let ost_time_bases: Vec<Rational> =
+        octx.streams().map(|s| s.time_base()).collect();
+
+    let mut window =
+        CopyWindow::new(file_start_us(ictx), start, end, video.is_some());
+    let mut finished = vec![false; mapping.len()];
+
+    for (stream, mut packet) in ictx.packets() {
+        let index = stream.index();
+        let Some(ost_index) = mapping[index] else {
+            continue;
+        };
+
+        let time_base = stream.time_base();
+        let Some(pts) = packet.pts().or(packet.dts()) else {
+            continue;
+        };
+        let pts_us = pts.rescale(time_base, rescale::TIME_BASE);
+        let dts_us = packet
+            .dts()
+            .map(|dts| dts.rescale(time_base, rescale::TIME_BASE))
+            .unwrap_or(pts_us);
+
+        let is_video = Some(index) == video;
+        let origin =
+            match window.packet(is_video, packet.is_key(), pts_us, dts_us) {
+                CopyAction::Skip => continue,
+                CopyAction::Finish => {
+                    finished[index] = true;
+                    if mapping
+                        .iter()
+                        .enumerate()
+                        .all(|(i, m)| m.is_none() || finished[i])
+                    {
+                        break;
+                    }
+                    continue;
+                }
+                CopyAction::Write { origin } => origin,
+            };
+
+        let offset = origin.rescale(rescale::TIME_BASE, time_base);
+        packet.set_pts(packet.pts().map(|pts| pts - offset));
+        packet.set_dts(packet.dts().map(|dts| dts - offset));
+        packet.rescale_ts(time_base, ost_time_bases[ost_index]);
+        packet.set_position(-1);
+        packet.set_stream(ost_index);
+        packet.write_interleaved(octx)?;
+    }
+
+    octx.write_trailer()?;
+
+    Ok(window.clip_start(start))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum CopyAction {
+    Skip,
+    // The stream has reached the end of the clip.
+    Finish,
+    // Shifted back by `origin` so the clip starts at zero.
+    Write { origin: i64 },
+}
+
+// Picks the packets of a stream copy. Packet times are in microseconds as
+// stored, which is `file_start` later than the hit times.
+struct CopyWindow {
+    file_start: i64,
+    end: i64,
+    // Everything is shifted so the clip starts at the first video keyframe.
+    origin: Option<i64>,
+}
+
+impl CopyWindow {
+    fn new(file_start: i64, start: i64, end: i64, has_video: bool) -> Self {
+        Self {
+            file_start,
+            end: file_start + end * 1000,
+            origin: (!has_video).then_some(file_start + start * 1000),
+        }
+    }
+
+    fn packet(
+        &mut self,
+        is_video: bool,
+        is_key: bool,
+        pts: i64,
+        dts: i64,
+    ) -> CopyAction {
+        if dts >= self.end {
+            return CopyAction::Finish;
+        }
+
+        if is_video && self.origin.is_none() {
+            if !is_key {
+                return CopyAction::Skip;
+            }
+            self.origin = Some(pts);
+        }
+
+        match self.origin {
+            Some(origin) if pts >= origin && pts < self.end => {
+                CopyAction::Write { origin }
+            }
+            _ => CopyAction::Skip,
+        }
+    }
+
+    // Where the clip starts in milliseconds from the start of the file.
+    fn clip_start(&self, start: i64) -> i64 {
+        self.origin
+            .map(|origin| (origin - self.file_start) / 1000)
+            .unwrap_or(start)
+    }
+}
+
+fn reencode_span(
+    ictx: &mut format::context::Input,
+    octx: &mut format::context::Output,
+    start: i64,
+    end: i64,
+    mut burner: Option<Burner>,
+) -> EngramResult<i64> {
+    let video_index = ictx.streams().best(Video).map(|s| s.index());
+    let audio_index = ictx.streams().best(Audio).map(|s| s.index());
+
+    let (from, to) = (ms_to_seconds(start), ms_to_seconds(end));
+
+    let mut video = match video_index {
+        Some(index) => {
+            let ist = ictx.stream(index).unwrap();
+            // Captions are drawn on RGBA frames and converted back after.
+            let pixel = match burner {
+                Some(_) => format::Pixel::RGBA,
+                None => format::Pixel::YUV420P,
+            };
+            let mut input = VideoInput::new(
+                &ist,
+                &format!(
+                    "setpts=PTS-{},trim=start={from}:end={to},\
+                     setpts=PTS-STARTPTS",
+                    start_pts(&ist)
+                ),
+                pixel,
+            )?;
+            let time_base = input.time_base();
+            let frame_rate = match ist.avg_frame_rate() {
+                rate if rate.numerator() > 0 => rate,
+                _ => Rational(25, 1),
+            };
+            let output = VideoOutput::new(
+                octx,
+                input.width(),
+                input.height(),
+                time_base,
+                frame_rate,
+            )?;
+            Some((index, input, output))
+        }
+        None => None,
+    };
+
+    let mut audio = match audio_index {
+        Some(index) => {
+            let ist = ictx.stream(index).unwrap();
+            let decoder =
+                codec::context::Context::from_parameters(ist.parameters())?
+                    .decoder()
+                    .audio()?;
+            let codec = encoder::find(codec::Id::AAC).ok_or_else(|| {
+                EngramError::MediaError("No AAC encoder available".into())
+            })?;
+            let output = AudioOutput::new(
+                octx,
+                codec,
+                decoder.rate(),
+                (decoder.channels() as i32).clamp(1, 2),
+                Dictionary::new(),
+            )?;
+            let input = AudioInput::new(
+                &ist,
+                &format!(
+                    "asetpts=PTS-{},atrim=start={from}:end={to},\
+                     asetpts=PTS-STARTPTS",
+                    start_pts(&ist)
+                ),
+                &output,
+            )?;
+            Some((index, input, output))
+        }
+        None => None,
+    };
+
+    if video.is_none() && audio.is_none() {
+        return Err(EngramError::MediaError(
+            "No audio or video stream found".into(),
+        ));
+    }
+
+    octx.write_header()?;
+
+    // Give decoders with reordering delay a second past the end.
+    let stop_us = file_start_us(ictx) + (end + 1000) * 1000;
+
+    for (stream, packet) in ictx.packets() {
+        let index = stream.index();
+
+        if let Some(dts) = packet.dts()
+            && dts.rescale(stream.time_base(), rescale::TIME_BASE) > stop_us
+        {
+            break;
+        }
+
+        if let Some((video_index, input, output)) = video.as_mut()
+            && *video_index == index
+        {
+            input.send_packet(&packet)?;
+            drain_video(input, output, burner.as_mut(), octx)?;
+        } else if let Some((audio_index, input, output)) = audio.as_mut()
+            && *audio_index == index
+        {
+            input.send_packet(&packet)?;
+            drain_audio(input, output, octx)?;
+        }
+    }
+
+    if let Some((_, input, output)) = video.as_mut() {
+        input.send_eof()?;
+        drain_video(input, output, burner.as_mut(), octx)?;
+        output.finish(octx)?;
+    }
+
+    if let Some((_, input, output)) = audio.as_mut() {
+        input.send_eof()?;
+        drain_audio(input, output, octx)?;
+        output.finish(octx)?;
+    }
+
+    octx.write_trailer()?;
+
+    Ok(start)
+}
+
+fn drain_video(
+    input: &mut VideoInput,
+    output: &mut VideoOutput,
+    mut burner: Option<&mut Burner>,
+    octx: &mut format::context::Output,
+) -> EngramResult<()> {
+    let time_base = input.time_base();
+    let mut filtered = frame::Video::empty();
+    while input.receive_frame(&mut filtered) {
+        match burner.as_deref_mut() {
+            Some(burner) => {
+                let mut converted = burner.burn(&mut filtered, time_base)?;
+                output.send_frame(&mut converted, time_base, octx)?;
+            }
+            None => output.send_frame(&mut filtered, time_base, octx)?,
+        }
+    }
+
+    Ok(())
+}
+
+struct Burner {
+    renderer: CaptionRenderer,
+    // Relative to the start of the clip.
+    segments: Vec<Segment>,
+    // Rendered lazily, one per segment.
+    captions: Vec<Option<Caption>>,
+    scaler: Option<scaling::Context>,
+}
+
+impl Burner {
+    // Draws the active caption onto an RGBA frame and returns it as YUV.
+    fn burn(
+        &mut self,
+        frame: &mut frame::Video,
+        time_base: Rational,
+    ) -> EngramResult<frame::Video> {
+        let (width, height) = (frame.width(), frame.height());
+        let time = frame
+            .pts()
+            .map(|pts| pts.rescale(time_base, Rational(1, 1000)))
+            .unwrap_or(0);
+
+        if let Some(index) = self
+            .segments
+            .iter()
+            .position(|seg| seg.start <= time && time < seg.end)
+        {
+            self.captions.resize_with(self.segments.len(), || None);
+            let caption = self.captions[index].get_or_insert_with(|| {
+                self.renderer
+                    .render(&self.segments[index].text, width, height)
+            });
+            let stride = frame.stride(0);
+            caption.apply_rgba(frame.data_mut(0), stride);
+        }
+
+        if self.scaler.is_none() {
+            self.scaler = Some(scaling::Context::get(
+                format::Pixel::RGBA,
+                width,
+                height,
+                format::Pixel::YUV420P,
+                width,
+                height,
+                Flags::BILINEAR,
+            )?);
+        }
+
+        let mut converted = frame::Video::empty();
+        self.scaler.as_mut().unwrap().run(frame, &mut converted)?;
+        converted.set_pts(frame.pts());
+
+        Ok(converted)
+    }
+}
+
+fn drain_audio(
+    input: &mut AudioInput,
+    output: &mut AudioOutput,
+    octx: &mut format::context::Output,
+) -> EngramResult<()> {
+    let time_base = input.time_base();
+    let mut filtered = frame::Audio::empty();
+    while input.receive_frame(&mut filtered) {
+        output.send_frame(&mut filtered, time_base, octx)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Packets of a file whose timestamps start at 1.4s, as MPEG-TS often
+    // does, with a keyframe every second.
+    const FILE_START: i64 = 1_400_000;
+
+    fn video(window: &mut CopyWindow, ms: i64) -> CopyAction {
+        let pts = FILE_START + ms * 1000;
+        window.packet(true, ms % 1000 == 0, pts, pts)
+    }
+
+    #[test]
+    fn starts_stream_copies_at_the_keyframe_before_the_hit() {
+        let mut window = CopyWindow::new(FILE_START, 2500, 4000, true);
+
+        // The seek lands on the keyframe at 2s.
+        let origin = FILE_START + 2_000_000;
+        assert_eq!(video(&mut window, 2000), CopyAction::Write { origin });
+        assert_eq!(video(&mut window, 3960), CopyAction::Write { origin });
+        assert_eq!(video(&mut window, 4000), CopyAction::Finish);
+        assert_eq!(window.clip_start(2500), 2000);
+    }
+
+    #[test]
+    fn waits_for_a_keyframe() {
+        let mut window = CopyWindow::new(FILE_START, 2500, 4000, true);
+
+        assert_eq!(video(&mut window, 1960), CopyAction::Skip);
+        // Audio before the first keyframe has nothing to line up with.
+        let pts = FILE_START + 1_980_000;
+        assert_eq!(window.packet(false, true, pts, pts), CopyAction::Skip);
+        assert_eq!(
+            video(&mut window, 3000),
+            CopyAction::Write {
+                origin: FILE_START + 3_000_000
+            }
+        );
+    }
+
+    #[test]
+    fn starts_audio_only_copies_at_the_hit() {
+        let mut window = CopyWindow::new(FILE_START, 2500, 4000, false);
+        let at = |ms: i64| FILE_START + ms * 1000;
+
+        let origin = at(2500);
+        assert_eq!(
+            window.packet(false, true, at(2480), at(2480)),
+            CopyAction::Skip
+        );
+        assert_eq!(
+            window.packet(false, true, at(2500), at(2500)),
+            CopyAction::Write { origin }
+        );
+        assert_eq!(
+            window.packet(false, true, at(4000), at(4000)),
+            CopyAction::Finish
+        );
+        assert_eq!(window.clip_start(2500), 2500);
+    }
+}
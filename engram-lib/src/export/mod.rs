@@ -0,0 +1,26 @@
+pub mod animation;
+pub mod audio;
+pub mod batch;
+pub mod caption;
+pub mod clip;
+pub mod snippet;
+pub mod supercut;
+mod transcode;
+
+pub use animation::{
+    AnimationFormat, AnimationOptions, export_animation,
+    export_segment_animation,
+};
+pub use audio::{
+    AudioExportOptions, AudioFormat, export_audio, export_audio_span,
+};
+pub use batch::{
+    BatchItem, BatchOptions, BatchReport, ExportPreset, export_batch,
+    export_batch_cancellable,
+};
+pub use caption::{CaptionFont, CaptionPosition, CaptionStyle};
+pub use clip::{
+    ClipContainer, ClipMode, ClipOptions, export_clip,
+    export_clip_with_snippets, export_span,
+};
+pub use supercut::{SupercutOptions, SupercutOrder, export_supercut};
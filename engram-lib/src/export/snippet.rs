@@ -0,0 +1,49 @@
+use std::path::{Path, PathBuf};
+
+use crate::EngramResult;
+use crate::db::Database;
+use crate::search::SearchResult;
+use crate::subtitles::{self, Segment};
+
+// Every stored segment of the hit's track, so a multi-line exchange around
+// the hit stays intact. Files that are not stored fall back to the hit alone.
+pub fn stored_segments(
+    db: &Database,
+    result: &SearchResult,
+) -> EngramResult<Vec<Segment>> {
+    let Some(segments) = db.load_segments(&result.file.to_string_lossy())?
+    else {
+        return Ok(vec![result.segment.clone()]);
+    };
+
+    // Other tracks hold the same lines in another language.
+    Ok(segments
+        .into_iter()
+        .filter(|segment| segment.language == result.segment.language)
+        .collect())
+}
+
+// Segments of the hit's file that overlap `start..end`, relative to `start`.
+pub fn clip_segments(
+    db: &Database,
+    result: &SearchResult,
+    start: i64,
+    end: i64,
+) -> EngramResult<Vec<Segment>> {
+    Ok(subtitles::retime(&stored_segments(db, result)?, start, end))
+}
+
+// Writes `<clip>.srt` and `<clip>.vtt` next to the clip and returns their
+// paths.
+pub fn write_snippets(
+    segments: &[Segment],
+    clip: &Path,
+) -> EngramResult<(PathBuf, PathBuf)> {
+    let srt = clip.with_extension("srt");
+    let vtt = clip.with_extension("vtt");
+
+    std::fs::write(&srt, subtitles::generate_srt(segments))?;
+    std::fs::write(&vtt, subtitles::generate_vtt(segments))?;
+
+    Ok((srt, vtt))
+}
@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::SystemTime;
+
+use ffmpeg_next::media::Type::{Audio, Video};
+use ffmpeg_next::{
+    Dictionary, Rational, Rescale, codec, encoder, format, frame, rescale,
+};
+
+use crate::export::caption::{CaptionPosition, CaptionRenderer, CaptionStyle};
+use crate::export::clip::ClipContainer;
+use crate::export::transcode::{
+    AudioInput, AudioOutput, FrameFilter, VideoInput, VideoOutput,
+    file_start_us, ms_to_seconds, seek_to, start_pts,
+};
+use crate::search::SearchResult;
+use crate::{EngramResult, errors::EngramError, subtitles};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupercutOrder {
+    // Best hits first, as returned by the search.
+    Score,
+    // Grouped by file path, each file from start to end.
+    File,
+    // Oldest file first by modification time, each file from start to end.
+    Chronological,
+}
+
+#[derive(Debug, Clone)]
+pub struct SupercutOptions {
+    pub container: ClipContainer,
+    pub order: SupercutOrder,
+    // Every hit is scaled and letterboxed to this size.
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub sample_rate: u32,
+    // Padding around each hit, in milliseconds.
+    pub lead_in: i64,
+    pub lead_out: i64,
+    // Shows the file name and timestamp before each hit.
+    pub title_cards: bool,
+    pub title_duration: i64,
+}
+
+impl Default for SupercutOptions {
+    fn default() -> Self {
+        Self {
+            container: ClipContainer::Mp4,
+            order: SupercutOrder::Score,
+            width: 1280,
+            height: 720,
+            fps: 25,
+            sample_rate: 48_000,
+            lead_in: 250,
+            lead_out: 250,
+            title_cards: false,
+            title_duration: 1500,
+        }
+    }
+}
+
+pub fn order_results(
+    results: &[SearchResult],
+    order: SupercutOrder,
+) -> Vec<&SearchResult> {
+    let mut ordered: Vec<&SearchResult> = results.iter().collect();
+
+    match order {
+        SupercutOrder::Score => {
+            ordered.sort_by(|a, b| b.score.total_cmp(&a.score));
+        }
+        SupercutOrder::File => {
+            ordered.sort_by(|a, b| {
+                a.file
+                    .cmp(&b.file)
+                    .then(a.segment.start.cmp(&b.segment.start))
+            });
+        }
+        SupercutOrder::Chronological => {
+            let mut modified: HashMap<&Path, SystemTime> = HashMap::new();
+            for result in &ordered {
+                modified.entry(&result.file).or_insert_with(|| {
+                    std::fs::metadata(&result.file)
+                        .and_then(|m| m.modified())
+                        .unwrap_or(SystemTime::UNIX_EPOCH)
+                });
+            }
+
+            ordered.sort_by(|a, b| {
+                modified[a.file.as_path()]
+                    .cmp(&modified[b.file.as_path()])
+                    .then(a.file.cmp(&b.file))
+                    .then(a.segment.start.cmp(&b.segment.start))
+            });
+        }
+    }
+
+    ordered
+}
+
+pub fn export_supercut(
+    results: &[SearchResult],
+    output: &Path,
+    options: &SupercutOptions,
+) -> EngramResult<()> {
+    if results.is_empty() {
+        return Err(EngramError::MediaError("No hits to concatenate".into()));
+    }
+
+    ffmpeg_next::init()?;
+
+    let mut octx = format::output_as(output, options.container.format_name())?;
+
+    let mut writer = SupercutWriter::new(&mut octx, options)?;
+    octx.write_header()?;
+
+    let titles = if options.title_cards {
+        Some(CaptionRenderer::new(&CaptionStyle {
+            position: CaptionPosition::Center,
+            ..Default::default()
+        })?)
+    } else {
+        None
+    };
+
+    for result in order_results(results, options.order) {
+        let (start, end) = result.span();
+        let start = (start - options.lead_in).max(0);
+        let end = end + options.lead_out;
+
+        if let Some(renderer) = &titles {
+            let name = result
+                .file
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let text =
+                format!("{name}\n{}", subtitles::format_timestamp(start));
+            writer.title_card(renderer, &text, &mut octx)?;
+        }
+
+        writer.piece(&result.file, start, end, &mut octx)?;
+    }
+
+    writer.finish(&mut octx)?;
+    octx.write_trailer()?;
+
+    Ok(())
+}
+
+// Appends pieces one after the other. Every piece gets a slot of its own
+// length, audio is padded with silence so both streams stay aligned even
+// when a source has no audio or ends early.
+struct SupercutWriter {
+    width: u32,
+    height: u32,
+    fps: i32,
+    title_duration: i64,
+    video: VideoOutput,
+    audio: AudioOutput,
+    // First frame of the next slot.
+    next_frame: i64,
+}
+
+impl SupercutWriter {
+    fn new(
+        octx: &mut format::context::Output,
+        options: &SupercutOptions,
+    ) -> EngramResult<Self> {
+        // Encoders want even dimensions.
+        let width = options.width.max(2) & !1;
+        let height = options.height.max(2) & !1;
+        let fps = options.fps.max(1) as i32;
+
+        let video = VideoOutput::new(
+            octx,
+            width,
+            height,
+            Rational(1, fps),
+            Rational(fps, 1),
+        )?;
+
+        let aac = encoder::find(codec::Id::AAC).ok_or_else(|| {
+            EngramError::MediaError("No AAC encoder available".into())
+        })?;
+        let audio = AudioOutput::new(
+            octx,
+            aac,
+            options.sample_rate,
+            2,
+            Dictionary::new(),
+        )?;
+
+        Ok(Self {
+            width,
+            height,
+            fps,
+            title_duration: options.title_duration,
+            video,
+            audio,
+            next_frame: 0,
+        })
+    }
+
+    fn frame_time_base(&self) -> Rational {
+        Rational(1, self.fps)
+    }
+
+    fn frames_for(&self, ms: i64) -> i64 {
+        (ms * self.fps as i64 + 500) / 1000
+    }
+
+    fn piece(
+        &mut self,
+        file: &Path,
+        start: i64,
+        end: i64,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        let slot = self.frames_for(end - start);
+        self.align_audio(octx)?;
+
+        let mut ictx = format::input(file)?;
+        let (from, to) = (ms_to_seconds(start), ms_to_seconds(end));
+
+        // Each stream is shifted to the start of the file first, as the span
+        // counts from there.
+        let mut video = match ictx.streams().best(Video) {
+            Some(ist) => {
+                let spec = format!(
+                    "setpts=PTS-{shift},trim=start={from}:end={to},\
+                     setpts=PTS-STARTPTS,\
+                     scale={w}:{h}:force_original_aspect_ratio=decrease,\
+                     pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps={fps}",
+                    shift = start_pts(&ist),
+                    w = self.width,
+                    h = self.height,
+                    fps = self.fps,
+                );
+                let input =
+                    VideoInput::new(&ist, &spec, format::Pixel::YUV420P)?;
+                Some((ist.index(), input))
+            }
+            None => None,
+        };
+
+        let mut audio = match ictx.streams().best(Audio) {
+            Some(ist) => {
+                let spec = format!(
+                    "asetpts=PTS-{},atrim=start={from}:end={to},\
+                     asetpts=PTS-STARTPTS",
+                    start_pts(&ist)
+                );
+                let input = AudioInput::new(&ist, &spec, &self.audio)?;
+                Some((ist.index(), input))
+            }
+            None => None,
+        };
+
+        seek_to(&mut ictx, start)?;
+
+        let first = self.next_frame;
+        let audio_end = self.audio_position(first + slot);
+        let stop = file_start_us(&ictx) + (end + 1000) * 1000;
+
+        for (stream, packet) in ictx.packets() {
+            if let Some(dts) = packet.dts()
+                && dts.rescale(stream.time_base(), rescale::TIME_BASE) > stop
+            {
+                break;
+            }
+
+            if let Some((index, input)) = video.as_mut()
+                && *index == stream.index()
+            {
+                input.send_packet(&packet)?;
+                self.drain_video(input, first, slot, octx)?;
+            } else if let Some((index, input)) = audio.as_mut()
+                && *index == stream.index()
+            {
+                input.send_packet(&packet)?;
+                self.drain_audio(input, audio_end, octx)?;
+            }
+        }
+
+        if let Some((_, input)) = video.as_mut() {
+            input.send_eof()?;
+            self.drain_video(input, first, slot, octx)?;
+        }
+        if let Some((_, input)) = audio.as_mut() {
+            input.send_eof()?;
+            self.drain_audio(input, audio_end, octx)?;
+        }
+
+        self.next_frame = first + slot;
+
+        Ok(())
+    }
+
+    fn title_card(
+        &mut self,
+        renderer: &CaptionRenderer,
+        text: &str,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        let slot = self.frames_for(self.title_duration);
+        self.align_audio(octx)?;
+
+        let (width, height) = (self.width, self.height);
+        let mut card = vec![0u8; (width * height * 4) as usize];
+        for pixel in card.chunks_exact_mut(4) {
+            pixel[3] = 255;
+        }
+        renderer
+            .render(text, width, height)
+            .apply_rgba(&mut card, width as usize * 4);
+
+        let time_base = self.frame_time_base();
+        let mut filter = FrameFilter::new(
+            (width, height),
+            time_base,
+            "null",
+            format::Pixel::YUV420P,
+        )?;
+
+        let first = self.next_frame;
+        let mut converted = frame::Video::empty();
+        for i in 0..slot {
+            filter.send(&card, i)?;
+            while filter.receive_frame(&mut converted) {
+                let pts = converted.pts().unwrap_or(0);
+                converted.set_pts(Some(first + pts));
+                self.video.send_frame(&mut converted, time_base, octx)?;
+            }
+        }
+        filter.send_eof()?;
+        while filter.receive_frame(&mut converted) {
+            let pts = converted.pts().unwrap_or(0);
+            converted.set_pts(Some(first + pts));
+            self.video.send_frame(&mut converted, time_base, octx)?;
+        }
+
+        self.next_frame = first + slot;
+
+        Ok(())
+    }
+
+    fn finish(
+        &mut self,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        self.align_audio(octx)?;
+        self.video.finish(octx)?;
+        self.audio.finish(octx)
+    }
+
+    // Frames are placed at their offset in the slot and dropped past its end.
+    fn drain_video(
+        &mut self,
+        input: &mut VideoInput,
+        first: i64,
+        slot: i64,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        let time_base = input.time_base();
+        let frame_time_base = self.frame_time_base();
+        let mut filtered = frame::Video::empty();
+
+        while input.receive_frame(&mut filtered) {
+            let offset = filtered
+                .pts()
+                .map(|pts| pts.rescale(time_base, frame_time_base))
+                .unwrap_or(0);
+            if offset < 0 || offset >= slot {
+                continue;
+            }
+
+            filtered.set_pts(Some(first + offset));
+            self.video
+                .send_frame(&mut filtered, frame_time_base, octx)?;
+        }
+
+        Ok(())
+    }
+
+    fn drain_audio(
+        &mut self,
+        input: &mut AudioInput,
+        end: i64,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        let mut filtered = frame::Audio::empty();
+
+        // The last frame is cut at `end` so the slot doesn't push the audio
+        // of the following ones out of sync.
+        while input.receive_frame(&mut filtered) {
+            let remaining = end - self.audio.written();
+            if remaining > 0 {
+                self.audio.append_samples(
+                    &filtered,
+                    remaining as usize,
+                    octx,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Pads the audio with silence up to the start of the next slot.
+    fn align_audio(
+        &mut self,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        let target = self.audio_position(self.next_frame);
+        let missing = target - self.audio.written();
+        if missing > 0 {
+            self.audio.append_silence(missing, octx)?;
+        }
+
+        Ok(())
+    }
+
+    fn audio_position(&self, frame: i64) -> i64 {
+        frame * self.audio.rate() as i64 / self.fps as i64
+    }
+}
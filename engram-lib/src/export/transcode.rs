@@ -0,0 +1,656 @@
+use ffmpeg_next::{
+    ChannelLayout, Dictionary, Packet, Rational, Rescale, codec, decoder,
+    encoder, filter, format, frame, picture,
+};
+
+use crate::{EngramResult, errors::EngramError};
+
+// Decodes a video stream and runs the frames through a filter graph.
+pub(crate) struct VideoInput {
+    decoder: decoder::Video,
+    graph: filter::Graph,
+}
+
+impl VideoInput {
+    pub fn new(
+        ist: &format::stream::Stream,
+        spec: &str,
+        pixel: format::Pixel,
+    ) -> EngramResult<Self> {
+        let decoder =
+            codec::context::Context::from_parameters(ist.parameters())?
+                .decoder()
+                .video()?;
+
+        let time_base = ist.time_base();
+        let aspect = decoder.aspect_ratio();
+        let args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base={}/{}:pixel_aspect={}/{}",
+            decoder.width(),
+            decoder.height(),
+            decoder
+                .format()
+                .descriptor()
+                .map(|d| d.name())
+                .unwrap_or("yuv420p"),
+            time_base.numerator(),
+            time_base.denominator(),
+            aspect.numerator(),
+            aspect.denominator().max(1),
+        );
+
+        let mut graph = filter::Graph::new();
+        graph.add(&find_filter("buffer")?, "in", &args)?;
+        graph.add(&find_filter("buffersink")?, "out", "")?;
+        graph.get("out").unwrap().set_pixel_format(pixel);
+        graph.output("in", 0)?.input("out", 0)?.parse(spec)?;
+        graph.validate()?;
+
+        Ok(Self { decoder, graph })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.decoder.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.decoder.height()
+    }
+
+    pub fn time_base(&mut self) -> Rational {
+        self.graph.get("out").unwrap().sink().time_base()
+    }
+
+    pub fn send_packet(&mut self, packet: &Packet) -> EngramResult<()> {
+        self.decoder.send_packet(packet)?;
+        self.drain_decoder()
+    }
+
+    pub fn send_eof(&mut self) -> EngramResult<()> {
+        self.decoder.send_eof()?;
+        self.drain_decoder()?;
+        self.graph.get("in").unwrap().source().flush()?;
+
+        Ok(())
+    }
+
+    pub fn receive_frame(&mut self, frame: &mut frame::Video) -> bool {
+        self.graph.get("out").unwrap().sink().frame(frame).is_ok()
+    }
+
+    fn drain_decoder(&mut self) -> EngramResult<()> {
+        let mut decoded = frame::Video::empty();
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            let timestamp = decoded.timestamp();
+            decoded.set_pts(timestamp);
+            self.graph.get("in").unwrap().source().add(&decoded)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) struct VideoOutput {
+    encoder: encoder::Video,
+    ost_index: usize,
+    time_base: Rational,
+}
+
+impl VideoOutput {
+    // H.264 when available, MPEG-4 Part 2 otherwise.
+    pub fn new(
+        octx: &mut format::context::Output,
+        width: u32,
+        height: u32,
+        time_base: Rational,
+        frame_rate: Rational,
+    ) -> EngramResult<Self> {
+        let codec = encoder::find_by_name("libx264")
+            .or_else(|| encoder::find(codec::Id::H264))
+            .or_else(|| encoder::find(codec::Id::MPEG4))
+            .ok_or_else(|| {
+                EngramError::MediaError("No video encoder available".into())
+            })?;
+
+        let mut options = Dictionary::new();
+        options.set("preset", "veryfast");
+        options.set("crf", "20");
+
+        Self::with_codec(
+            octx,
+            codec,
+            format::Pixel::YUV420P,
+            (width, height),
+            time_base,
+            frame_rate,
+            options,
+        )
+    }
+
+    pub fn with_codec(
+        octx: &mut format::context::Output,
+        codec: ffmpeg_next::Codec,
+        pixel: format::Pixel,
+        (width, height): (u32, u32),
+        time_base: Rational,
+        frame_rate: Rational,
+        options: Dictionary,
+    ) -> EngramResult<Self> {
+        let global_header = octx
+            .format()
+            .flags()
+            .contains(format::flag::Flags::GLOBAL_HEADER);
+
+        let mut ost = octx.add_stream(codec)?;
+        let ost_index = ost.index();
+
+        let mut encoder = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()?;
+        encoder.set_width(width);
+        encoder.set_height(height);
+        encoder.set_format(pixel);
+        encoder.set_time_base(time_base);
+        encoder.set_frame_rate(Some(frame_rate));
+
+        if global_header {
+            encoder.set_flags(codec::flag::Flags::GLOBAL_HEADER);
+        }
+
+        let encoder = encoder.open_with(options)?;
+        ost.set_parameters(&encoder);
+        ost.set_time_base(time_base);
+
+        Ok(Self {
+            encoder,
+            ost_index,
+            time_base,
+        })
+    }
+
+    // `frame` must be timestamped in `time_base`.
+    pub fn send_frame(
+        &mut self,
+        frame: &mut frame::Video,
+        time_base: Rational,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        frame.set_pts(
+            frame
+                .pts()
+                .map(|pts| pts.rescale(time_base, self.time_base)),
+        );
+        frame.set_kind(picture::Type::None);
+        self.encoder.send_frame(frame)?;
+        self.write_packets(octx)
+    }
+
+    pub fn finish(
+        &mut self,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        self.encoder.send_eof()?;
+        self.write_packets(octx)
+    }
+
+    fn write_packets(
+        &mut self,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        write_encoded(&mut self.encoder, self.ost_index, self.time_base, octx)
+    }
+}
+
+// Runs already decoded RGBA frames through a filter graph.
+pub(crate) struct FrameFilter {
+    graph: filter::Graph,
+    width: u32,
+    height: u32,
+}
+
+impl FrameFilter {
+    pub fn new(
+        (width, height): (u32, u32),
+        time_base: Rational,
+        spec: &str,
+        pixel: format::Pixel,
+    ) -> EngramResult<Self> {
+        let args = format!(
+            "video_size={width}x{height}:pix_fmt=rgba:time_base={}/{}:pixel_aspect=1/1",
+            time_base.numerator(),
+            time_base.denominator(),
+        );
+
+        let mut graph = filter::Graph::new();
+        graph.add(&find_filter("buffer")?, "in", &args)?;
+        graph.add(&find_filter("buffersink")?, "out", "")?;
+        graph.get("out").unwrap().set_pixel_format(pixel);
+        graph.output("in", 0)?.input("out", 0)?.parse(spec)?;
+        graph.validate()?;
+
+        Ok(Self {
+            graph,
+            width,
+            height,
+        })
+    }
+
+    pub fn time_base(&mut self) -> Rational {
+        self.graph.get("out").unwrap().sink().time_base()
+    }
+
+    // `data` is tightly packed RGBA, `pts` is in the graph's input time base.
+    pub fn send(&mut self, data: &[u8], pts: i64) -> EngramResult<()> {
+        let mut frame =
+            frame::Video::new(format::Pixel::RGBA, self.width, self.height);
+        let row = self.width as usize * 4;
+        let stride = frame.stride(0);
+        for (line, src) in
+            frame.data_mut(0).chunks_mut(stride).zip(data.chunks(row))
+        {
+            line[..row].copy_from_slice(src);
+        }
+        frame.set_pts(Some(pts));
+
+        self.graph.get("in").unwrap().source().add(&frame)?;
+
+        Ok(())
+    }
+
+    pub fn send_eof(&mut self) -> EngramResult<()> {
+        self.graph.get("in").unwrap().source().flush()?;
+
+        Ok(())
+    }
+
+    pub fn receive_frame(&mut self, frame: &mut frame::Video) -> bool {
+        self.graph.get("out").unwrap().sink().frame(frame).is_ok()
+    }
+}
+
+// Decodes an audio stream and runs the samples through a filter graph whose
+// output matches what the paired `AudioOutput` expects.
+pub(crate) struct AudioInput {
+    decoder: decoder::Audio,
+    graph: filter::Graph,
+}
+
+impl AudioInput {
+    pub fn new(
+        ist: &format::stream::Stream,
+        spec: &str,
+        output: &AudioOutput,
+    ) -> EngramResult<Self> {
+        let decoder =
+            codec::context::Context::from_parameters(ist.parameters())?
+                .decoder()
+                .audio()?;
+
+        let time_base = ist.time_base();
+        let layout = if decoder.channel_layout().bits() == 0 {
+            ChannelLayout::default(decoder.channels() as i32)
+        } else {
+            decoder.channel_layout()
+        };
+        let args = format!(
+            "time_base={}/{}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+            time_base.numerator(),
+            time_base.denominator(),
+            decoder.rate(),
+            decoder.format().name(),
+            layout.bits()
+        );
+
+        let mut graph = filter::Graph::new();
+        graph.add(&find_filter("abuffer")?, "in", &args)?;
+        graph.add(&find_filter("abuffersink")?, "out", "")?;
+
+        {
+            let mut out = graph.get("out").unwrap();
+            out.set_sample_format(output.encoder.format());
+            out.set_channel_layout(output.encoder.channel_layout());
+            out.set_sample_rate(output.encoder.rate());
+        }
+
+        graph.output("in", 0)?.input("out", 0)?.parse(spec)?;
+        graph.validate()?;
+
+        // Most encoders only accept frames of exactly `frame_size` samples.
+        if output.encoder.frame_size() > 0 {
+            graph
+                .get("out")
+                .unwrap()
+                .sink()
+                .set_frame_size(output.encoder.frame_size());
+        }
+
+        Ok(Self { decoder, graph })
+    }
+
+    pub fn time_base(&mut self) -> Rational {
+        self.graph.get("out").unwrap().sink().time_base()
+    }
+
+    pub fn send_packet(&mut self, packet: &Packet) -> EngramResult<()> {
+        self.decoder.send_packet(packet)?;
+        self.drain_decoder()
+    }
+
+    pub fn send_eof(&mut self) -> EngramResult<()> {
+        self.decoder.send_eof()?;
+        self.drain_decoder()?;
+        self.graph.get("in").unwrap().source().flush()?;
+
+        Ok(())
+    }
+
+    pub fn receive_frame(&mut self, frame: &mut frame::Audio) -> bool {
+        self.graph.get("out").unwrap().sink().frame(frame).is_ok()
+    }
+
+    fn drain_decoder(&mut self) -> EngramResult<()> {
+        let mut decoded = frame::Audio::empty();
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            let timestamp = decoded.timestamp();
+            decoded.set_pts(timestamp);
+            self.graph.get("in").unwrap().source().add(&decoded)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) struct AudioOutput {
+    encoder: encoder::Audio,
+    ost_index: usize,
+    time_base: Rational,
+    // Samples queued through `append` and `append_silence`.
+    written: i64,
+    // Samples waiting for a full `frame_size` frame.
+    pending: Option<frame::Audio>,
+    filled: usize,
+}
+
+impl AudioOutput {
+    pub fn new(
+        octx: &mut format::context::Output,
+        codec: ffmpeg_next::Codec,
+        rate: u32,
+        channels: i32,
+        options: Dictionary,
+    ) -> EngramResult<Self> {
+        let audio_codec = codec.audio()?;
+
+        let global_header = octx
+            .format()
+            .flags()
+            .contains(format::flag::Flags::GLOBAL_HEADER);
+
+        let mut ost = octx.add_stream(codec)?;
+        let ost_index = ost.index();
+
+        let mut encoder = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .audio()?;
+
+        let layout = audio_codec
+            .channel_layouts()
+            .map(|layouts| layouts.best(channels))
+            .unwrap_or_else(|| ChannelLayout::default(channels));
+        let format = audio_codec
+            .formats()
+            .and_then(|mut formats| formats.next())
+            .ok_or_else(|| {
+                EngramError::MediaError(format!(
+                    "No sample format supported by {}",
+                    codec.name()
+                ))
+            })?;
+        // Some codecs (Opus) only support a handful of sample rates.
+        let rate = match audio_codec.rates() {
+            Some(rates) => {
+                let rates: Vec<i32> = rates.collect();
+                if rates.contains(&(rate as i32)) {
+                    rate
+                } else {
+                    rates.into_iter().max().unwrap_or(48_000) as u32
+                }
+            }
+            None => rate,
+        };
+        let time_base = Rational(1, rate as i32);
+
+        encoder.set_rate(rate as i32);
+        encoder.set_channel_layout(layout);
+        encoder.set_format(format);
+        encoder.set_time_base(time_base);
+
+        if global_header {
+            encoder.set_flags(codec::flag::Flags::GLOBAL_HEADER);
+        }
+
+        let encoder = encoder.open_as_with(codec, options)?;
+        ost.set_parameters(&encoder);
+        ost.set_time_base(time_base);
+
+        Ok(Self {
+            encoder,
+            ost_index,
+            time_base,
+            written: 0,
+            pending: None,
+            filled: 0,
+        })
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.encoder.rate()
+    }
+
+    // Samples appended so far, in the encoder time base, including the ones
+    // still waiting in a partly filled frame.
+    pub fn written(&self) -> i64 {
+        self.written + self.filled as i64
+    }
+
+    // Queues `frame` right after the previously appended samples, ignoring
+    // its own timestamp. Frames can have any size, they are regrouped into
+    // the frame size the encoder expects.
+    pub fn append(
+        &mut self,
+        frame: &frame::Audio,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        self.append_samples(frame, frame.samples(), octx)
+    }
+
+    // Like `append`, but only queues the first `samples` samples of `frame`.
+    pub fn append_samples(
+        &mut self,
+        frame: &frame::Audio,
+        samples: usize,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        let samples = samples.min(frame.samples());
+        let frame_size = match self.encoder.frame_size() {
+            0 => frame.samples().max(1),
+            size => size as usize,
+        };
+        let unit = sample_bytes(frame);
+
+        let mut offset = 0;
+        while offset < samples {
+            let pending = self.pending.get_or_insert_with(|| {
+                let mut pending = frame::Audio::new(
+                    self.encoder.format(),
+                    frame_size,
+                    self.encoder.channel_layout(),
+                );
+                pending.set_rate(self.encoder.rate());
+                pending
+            });
+
+            let count = (frame_size - self.filled).min(samples - offset);
+            for plane in 0..frame.planes() {
+                let src = &frame.data(plane)[offset * unit..][..count * unit];
+                pending.data_mut(plane)[self.filled * unit..][..count * unit]
+                    .copy_from_slice(src);
+            }
+            offset += count;
+            self.filled += count;
+
+            if self.filled == frame_size {
+                self.send_pending(octx)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn append_silence(
+        &mut self,
+        samples: i64,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        let mut remaining = samples;
+        while remaining > 0 {
+            let count = remaining.min(4096);
+            let mut silence = frame::Audio::new(
+                self.encoder.format(),
+                count as usize,
+                self.encoder.channel_layout(),
+            );
+            silence.set_rate(self.encoder.rate());
+            for plane in 0..silence.planes() {
+                silence.data_mut(plane).fill(0);
+            }
+
+            self.append(&silence, octx)?;
+            remaining -= count;
+        }
+
+        Ok(())
+    }
+
+    fn send_pending(
+        &mut self,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        let Some(mut pending) = self.pending.take() else {
+            return Ok(());
+        };
+
+        // Only the last frame may be shorter than `frame_size`.
+        pending.set_samples(self.filled);
+        pending.set_pts(Some(self.written));
+        self.written += self.filled as i64;
+        self.filled = 0;
+
+        self.encoder.send_frame(&pending)?;
+        self.write_packets(octx)
+    }
+
+    pub fn send_frame(
+        &mut self,
+        frame: &mut frame::Audio,
+        time_base: Rational,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        frame.set_pts(
+            frame
+                .pts()
+                .map(|pts| pts.rescale(time_base, self.time_base)),
+        );
+        self.encoder.send_frame(frame)?;
+        self.write_packets(octx)
+    }
+
+    pub fn finish(
+        &mut self,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        if self.filled > 0 {
+            self.send_pending(octx)?;
+        }
+        self.encoder.send_eof()?;
+        self.write_packets(octx)
+    }
+
+    fn write_packets(
+        &mut self,
+        octx: &mut format::context::Output,
+    ) -> EngramResult<()> {
+        write_encoded(&mut self.encoder, self.ost_index, self.time_base, octx)
+    }
+}
+
+// Bytes one sample takes up in each plane.
+fn sample_bytes(frame: &frame::Audio) -> usize {
+    let bytes = frame.format().bytes();
+    if frame.is_planar() {
+        bytes
+    } else {
+        bytes * frame.channels() as usize
+    }
+}
+
+fn write_encoded(
+    encoder: &mut encoder::Encoder,
+    ost_index: usize,
+    time_base: Rational,
+    octx: &mut format::context::Output,
+) -> EngramResult<()> {
+    // The muxer is free to pick its own time base in `write_header`.
+    let ost_time_base = octx
+        .stream(ost_index)
+        .map(|stream| stream.time_base())
+        .unwrap_or(time_base);
+
+    let mut encoded = Packet::empty();
+    while encoder.receive_packet(&mut encoded).is_ok() {
+        encoded.set_stream(ost_index);
+        encoded.rescale_ts(time_base, ost_time_base);
+        encoded.write_interleaved(octx)?;
+    }
+
+    Ok(())
+}
+
+fn find_filter(name: &str) -> EngramResult<filter::Filter> {
+    filter::find(name).ok_or_else(|| {
+        EngramError::MediaError(format!("FFmpeg filter {name} not available"))
+    })
+}
+
+pub(crate) fn ms_to_seconds(ms: i64) -> f64 {
+    ms as f64 / 1000.0
+}
+
+// Where the timestamps of `ist` begin. Hit times count from the start of the
+// file while the filters see the timestamps as stored, so they are shifted by
+// this first.
+pub(crate) fn start_pts(ist: &format::stream::Stream) -> i64 {
+    match ist.start_time() {
+        ffmpeg_next::ffi::AV_NOPTS_VALUE => 0,
+        start => start,
+    }
+}
+
+// Where the file's timestamps begin, in microseconds. Often not zero in
+// MPEG-TS recordings.
+pub(crate) fn file_start_us(ictx: &format::context::Input) -> i64 {
+    match unsafe { (*ictx.as_ptr()).start_time } {
+        ffmpeg_next::ffi::AV_NOPTS_VALUE => 0,
+        start => start,
+    }
+}
+
+// Seeks to the keyframe before `ms`, counted from the start of the file.
+pub(crate) fn seek_to(
+    ictx: &mut format::context::Input,
+    ms: i64,
+) -> EngramResult<()> {
+    let position = file_start_us(ictx) + ms * 1000;
+    ictx.seek(position, ..position)?;
+    Ok(())
+}
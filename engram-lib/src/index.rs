@@ -1,26 +1,196 @@
 use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
 use walkdir::WalkDir;
 
-use crate::EngramResult;
+use crate::{EngramResult, subtitles};
 
 #[derive(Debug, Clone)]
 pub struct MediaFile {
     pub media: PathBuf,
-    pub subtitles: Option<PathBuf>,
+    // Best first, see `SidecarRules`.
+    pub subtitles: Vec<Sidecar>,
+}
+
+impl MediaFile {
+    // One sidecar per language, the full non-SDH one when there is a choice.
+    pub fn preferred_subtitles(&self) -> Vec<&Sidecar> {
+        let mut languages = HashSet::new();
+        self.subtitles
+            .iter()
+            .filter(|sidecar| languages.insert(sidecar.language.as_deref()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sidecar {
+    pub path: PathBuf,
+    // ISO 639-1 code, when the name carries a language we know.
+    pub language: Option<String>,
+    // Only covers foreign dialogue and on-screen text.
+    pub forced: bool,
+    // For the deaf and hard of hearing, also tagged "cc" or "hi".
+    pub sdh: bool,
+}
+
+impl Sidecar {
+    fn new(path: PathBuf, tags: SubtitleTags) -> Self {
+        Self {
+            path,
+            language: tags.language,
+            forced: tags.forced,
+            sdh: tags.sdh,
+        }
+    }
+}
+
+// Language and flags spelled out in a sidecar name, such as "en.forced", or
+// in the title of an embedded track, such as "English (SDH)".
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubtitleTags {
+    pub language: Option<String>,
+    pub forced: bool,
+    pub sdh: bool,
+}
+
+const TAG_SEPARATORS: [char; 7] = ['_', '-', ' ', '[', ']', '(', ')'];
+
+impl SubtitleTags {
+    // What follows the media name in a sidecar, such as "en.forced" or
+    // "pt-BR.sdh". Each part between dots starts with a tag, which may be a
+    // two-letter code.
+    pub fn parse(tags: &str) -> Self {
+        let mut parsed = Self::default();
+
+        for part in tags.split('.') {
+            let tokens = part.split(TAG_SEPARATORS).filter(|t| !t.is_empty());
+            for (i, token) in tokens.enumerate() {
+                parsed.add(token, i == 0);
+            }
+        }
+
+        parsed
+    }
+
+    // Free text such as "English (SDH)" in a track title or a file name not
+    // named after the media. Two-letter codes are also words ("as", "so"),
+    // so they only count when they are the whole text.
+    pub fn parse_title(title: &str) -> Self {
+        let mut parsed = Self::default();
+
+        let tokens: Vec<&str> = title
+            .split(TAG_SEPARATORS)
+            .flat_map(|token| token.split('.'))
+            .filter(|token| !token.is_empty())
+            .collect();
+        for token in &tokens {
+            parsed.add(token, tokens.len() == 1);
+        }
+
+        parsed
+    }
+
+    fn add(&mut self, token: &str, is_tag: bool) {
+        match token.to_lowercase().as_str() {
+            "forced" | "foreign" => self.forced = true,
+            "sdh" | "cc" => self.sdh = true,
+            // "hi" is Hindi unless a language came first.
+            "hi" if self.language.is_some() => self.sdh = true,
+            token if self.language.is_none() => {
+                let language = if is_tag {
+                    subtitles::normalize_language(token)
+                } else {
+                    subtitles::language_in_text(token)
+                };
+                self.language = language.map(String::from);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SidecarRules {
+    // movie.srt, movie.en.srt or movie.eng.forced.srt next to movie.mkv.
+    pub same_directory: bool,
+    // Folders next to the media that hold its subtitles, as in
+    // Subs/movie.en.srt and Subs/movie/2_English.srt. When the media is
+    // alone in its folder every file in Subs/ belongs to it.
+    pub folders: Vec<String>,
+    pub case_insensitive: bool,
+}
+
+impl Default for SidecarRules {
+    fn default() -> Self {
+        Self {
+            same_directory: true,
+            folders: ["Subs", "Subtitles", "Sub"]
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+            case_insensitive: true,
+        }
+    }
+}
+
+impl SidecarRules {
+    fn is_folder(&self, dir: &Path) -> bool {
+        let Some(name) = dir.file_name().and_then(|n| n.to_str()) else {
+            return false;
+        };
+
+        self.folders.iter().any(|folder| {
+            if self.case_insensitive {
+                folder.eq_ignore_ascii_case(name)
+            } else {
+                folder == name
+            }
+        })
+    }
+
+    fn same_name(&self, a: &str, b: &str) -> bool {
+        if self.case_insensitive {
+            a.to_lowercase() == b.to_lowercase()
+        } else {
+            a == b
+        }
+    }
+
+    // What follows the media stem in a sidecar stem, or None when the
+    // sidecar is named after something else. "movie.en" gives "en".
+    fn tags<'a>(&self, media_stem: &str, stem: &'a str) -> Option<&'a str> {
+        let prefix = stem.get(..media_stem.len())?;
+        if !self.same_name(prefix, media_stem) {
+            return None;
+        }
+
+        match &stem[media_stem.len()..] {
+            "" => Some(""),
+            rest => rest.strip_prefix('.'),
+        }
+    }
 }
 
 pub fn get_files(dir: &Path, exts: &[&str]) -> EngramResult<Vec<MediaFile>> {
+    get_files_with(dir, exts, &SidecarRules::default())
+}
+
+pub fn get_files_with(
+    dir: &Path,
+    exts: &[&str],
+    rules: &SidecarRules,
+) -> EngramResult<Vec<MediaFile>> {
     let mut media_paths = Vec::new();
-    let mut srt_paths = Vec::new();
+    let mut subtitle_paths = Vec::new();
 
     for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
         if entry.file_type().is_file() {
             let path = entry.path();
             match path.extension().and_then(|e| e.to_str()) {
-                Some(ext) if ext.eq_ignore_ascii_case("srt") => {
-                    srt_paths.push(path.to_path_buf());
+                Some(ext) if is_sidecar(path, ext) => {
+                    subtitle_paths.push(path.to_path_buf());
                 }
                 Some(ext) if exts.contains(&ext.to_lowercase().as_str()) => {
                     media_paths.push(path.to_path_buf());
@@ -30,33 +200,396 @@ pub fn get_files(dir: &Path, exts: &[&str]) -> EngramResult<Vec<MediaFile>> {
         }
     }
 
-    let media_files = media_paths
-        .into_iter()
-        .map(|media| {
-            let srt = media.with_extension("srt");
-            let subtitles = if srt_paths.contains(&srt) {
-                Some(srt)
-            } else {
-                None
-            };
-
-            MediaFile { media, subtitles }
-        })
-        .collect();
-
-    Ok(media_files)
+    Ok(pair_sidecars(media_paths, subtitle_paths, rules))
 }
 
 pub fn get_files_par(
     dirs: &[PathBuf],
     exts: &[&str],
+    rules: &SidecarRules,
 ) -> EngramResult<Vec<MediaFile>> {
     let files = dirs
         .par_iter()
-        .map(|dir| get_files(dir, exts))
+        .map(|dir| get_files_with(dir, exts, rules))
         .collect::<EngramResult<Vec<Vec<MediaFile>>>>()?
         .into_iter()
         .flatten()
         .collect();
     Ok(files)
 }
+
+// Finds the sidecars of a single file without walking its whole directory
+// tree, only the places the rules look at.
+pub fn media_file(
+    media: &Path,
+    exts: &[&str],
+    rules: &SidecarRules,
+) -> EngramResult<MediaFile> {
+    let dir = media.parent().unwrap_or(Path::new("."));
+    let stem = media.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+
+    let mut media_paths = Vec::new();
+    let mut subtitle_paths = Vec::new();
+    let mut folders = Vec::new();
+
+    for entry in std::fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            if rules.is_folder(&path) {
+                folders.push(path);
+            }
+            continue;
+        }
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if is_sidecar(&path, ext) => subtitle_paths.push(path),
+            Some(ext) if exts.contains(&ext.to_lowercase().as_str()) => {
+                media_paths.push(path)
+            }
+            _ => {}
+        }
+    }
+
+    for folder in folders {
+        for entry in std::fs::read_dir(&folder)?.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let name = path.file_name().and_then(|n| n.to_str());
+            let nested = path.is_dir()
+                && name.is_some_and(|name| rules.same_name(name, stem));
+
+            let files = if nested {
+                std::fs::read_dir(&path)?
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.path())
+                    .collect()
+            } else {
+                vec![path]
+            };
+
+            subtitle_paths.extend(files.into_iter().filter(|file| {
+                file.extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|ext| is_sidecar(file, ext))
+            }));
+        }
+    }
+
+    if !media_paths.iter().any(|path| path == media) {
+        media_paths.push(media.to_path_buf());
+    }
+
+    Ok(pair_sidecars(media_paths, subtitle_paths, rules)
+        .into_iter()
+        .find(|file| file.media == media)
+        .unwrap_or_else(|| MediaFile {
+            media: media.to_path_buf(),
+            subtitles: Vec::new(),
+        }))
+}
+
+// A .sub next to an .idx is a VobSub image track.
+fn is_sidecar(path: &Path, ext: &str) -> bool {
+    subtitles::is_subtitle_extension(ext)
+        && !(ext.eq_ignore_ascii_case("sub")
+            && path.with_extension("idx").exists())
+}
+
+fn pair_sidecars(
+    media_paths: Vec<PathBuf>,
+    subtitle_paths: Vec<PathBuf>,
+    rules: &SidecarRules,
+) -> Vec<MediaFile> {
+    let mut by_dir: HashMap<&Path, Vec<usize>> = HashMap::new();
+    for (i, media) in media_paths.iter().enumerate() {
+        if let Some(dir) = media.parent() {
+            by_dir.entry(dir).or_default().push(i);
+        }
+    }
+
+    let mut sidecars: Vec<Vec<Sidecar>> = vec![Vec::new(); media_paths.len()];
+
+    for path in subtitle_paths {
+        let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let stem = stem.to_string();
+        let parent = path.parent();
+        let grandparent = parent.and_then(Path::parent);
+
+        // The media in `dir` whose name the sidecar carries. The longest
+        // stem wins so movie.part2.en.srt goes to movie.part2.mkv.
+        let named = |dir: Option<&Path>| {
+            by_dir
+                .get(dir?)?
+                .iter()
+                .filter_map(|&i| {
+                    let media_stem = media_paths[i].file_stem()?.to_str()?;
+                    let tags = rules.tags(media_stem, &stem)?;
+                    Some((i, media_stem.len(), tags))
+                })
+                .max_by_key(|(_, len, _)| *len)
+                .map(|(i, _, tags)| (i, SubtitleTags::parse(tags)))
+        };
+        let alone = |dir: Option<&Path>| match by_dir.get(dir?)?.as_slice() {
+            [i] => Some(*i),
+            _ => None,
+        };
+
+        let in_folder = parent.is_some_and(|p| rules.is_folder(p));
+        let in_nested_folder = grandparent.is_some_and(|p| rules.is_folder(p));
+
+        let matched = if rules.same_directory
+            && let Some(found) = named(parent)
+        {
+            Some(found)
+        } else if in_folder && let Some(found) = named(grandparent) {
+            Some(found)
+        } else if in_folder && let Some(i) = alone(grandparent) {
+            Some((i, SubtitleTags::parse_title(&stem)))
+        } else if in_nested_folder {
+            // Subs/movie/2_English.srt, named after the folder instead.
+            let folder = parent
+                .and_then(|p| p.file_name())
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            grandparent
+                .and_then(Path::parent)
+                .and_then(|dir| by_dir.get(dir))
+                .and_then(|media| {
+                    media.iter().copied().find(|&i| {
+                        media_paths[i]
+                            .file_stem()
+                            .and_then(|s| s.to_str())
+                            .is_some_and(|s| rules.same_name(s, folder))
+                    })
+                })
+                .map(|i| (i, SubtitleTags::parse_title(&stem)))
+        } else {
+            None
+        };
+
+        if let Some((i, tags)) = matched {
+            sidecars[i].push(Sidecar::new(path, tags));
+        }
+    }
+
+    media_paths
+        .into_iter()
+        .zip(sidecars)
+        .map(|(media, mut subtitles)| {
+            subtitles.sort_by(|a, b| {
+                (a.forced, a.sdh, format_rank(&a.path))
+                    .cmp(&(b.forced, b.sdh, format_rank(&b.path)))
+                    .then_with(|| a.path.cmp(&b.path))
+            });
+            MediaFile { media, subtitles }
+        })
+        .collect()
+}
+
+// Position of the extension in the format registry, so SRT wins.
+fn format_rank(path: &Path) -> usize {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    subtitles::subtitle_extensions()
+        .position(|e| ext.eq_ignore_ascii_case(e))
+        .unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(language: Option<&str>, forced: bool, sdh: bool) -> SubtitleTags {
+        SubtitleTags {
+            language: language.map(String::from),
+            forced,
+            sdh,
+        }
+    }
+
+    #[test]
+    fn parses_sidecar_tags() {
+        let cases = [
+            ("en", tags(Some("en"), false, false)),
+            ("so", tags(Some("so"), false, false)),
+            ("pt-BR.sdh", tags(Some("pt"), false, true)),
+            ("eng.forced", tags(Some("en"), true, false)),
+            ("en.hi", tags(Some("en"), false, true)),
+            ("hi", tags(Some("hi"), false, false)),
+            ("English (SDH)", tags(Some("en"), false, true)),
+            ("", tags(None, false, false)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(SubtitleTags::parse(text), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn ignores_two_letter_words_in_titles() {
+        let cases = [
+            ("English (SDH)", tags(Some("en"), false, true)),
+            ("2_English", tags(Some("en"), false, false)),
+            ("Forced GER", tags(Some("de"), true, false)),
+            ("So it is", tags(None, false, false)),
+            ("movie.so", tags(None, false, false)),
+            ("As Is [CC]", tags(None, false, true)),
+            ("fr", tags(Some("fr"), false, false)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(SubtitleTags::parse_title(text), expected, "{text}");
+        }
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    fn paired(
+        media: &[&str],
+        subtitles: &[&str],
+        rules: &SidecarRules,
+    ) -> Vec<Vec<Sidecar>> {
+        pair_sidecars(paths(media), paths(subtitles), rules)
+            .into_iter()
+            .map(|file| file.subtitles)
+            .collect()
+    }
+
+    fn sidecar(
+        path: &str,
+        language: Option<&str>,
+        forced: bool,
+        sdh: bool,
+    ) -> Sidecar {
+        Sidecar::new(path.into(), tags(language, forced, sdh))
+    }
+
+    #[test]
+    fn pairs_sidecars_by_name() {
+        let media = ["/lib/movie.mkv", "/lib/movie.part2.mkv"];
+        let subtitles = [
+            "/lib/movie.eng.forced.srt",
+            "/lib/movie.en.sdh.srt",
+            "/lib/MOVIE.de.vtt",
+            "/lib/movie.srt",
+            "/lib/movie.en.srt",
+            "/lib/movie.part2.fr.srt",
+            "/lib/other.srt",
+            "/elsewhere/movie.srt",
+        ];
+
+        // Full subtitles before SDH before forced, SRT before other formats.
+        assert_eq!(
+            paired(&media, &subtitles, &SidecarRules::default()),
+            [
+                vec![
+                    sidecar("/lib/movie.en.srt", Some("en"), false, false),
+                    sidecar("/lib/movie.srt", None, false, false),
+                    sidecar("/lib/MOVIE.de.vtt", Some("de"), false, false),
+                    sidecar("/lib/movie.en.sdh.srt", Some("en"), false, true),
+                    sidecar(
+                        "/lib/movie.eng.forced.srt",
+                        Some("en"),
+                        true,
+                        false
+                    ),
+                ],
+                vec![sidecar(
+                    "/lib/movie.part2.fr.srt",
+                    Some("fr"),
+                    false,
+                    false
+                )],
+            ]
+        );
+
+        let rules = SidecarRules {
+            case_insensitive: false,
+            ..Default::default()
+        };
+        assert_eq!(paired(&media, &["/lib/MOVIE.de.vtt"], &rules), [[], []]);
+
+        let rules = SidecarRules {
+            same_directory: false,
+            ..Default::default()
+        };
+        assert_eq!(paired(&media, &subtitles, &rules), [[], []]);
+    }
+
+    #[test]
+    fn pairs_sidecars_in_subtitle_folders() {
+        let media = ["/lib/a/movie.mkv", "/lib/b/x.mkv", "/lib/b/y.mkv"];
+        let subtitles = [
+            "/lib/a/Subs/English.srt",
+            "/lib/a/subs/movie.fr.srt",
+            "/lib/a/Extras/German.srt",
+            "/lib/b/Subs/2_English.srt",
+            "/lib/b/Subtitles/y.es.srt",
+            "/lib/b/Subs/x/3_German (Forced).srt",
+        ];
+
+        assert_eq!(
+            paired(&media, &subtitles, &SidecarRules::default()),
+            [
+                // Alone in its folder, so unnamed files are its own.
+                vec![
+                    sidecar(
+                        "/lib/a/Subs/English.srt",
+                        Some("en"),
+                        false,
+                        false
+                    ),
+                    sidecar(
+                        "/lib/a/subs/movie.fr.srt",
+                        Some("fr"),
+                        false,
+                        false
+                    ),
+                ],
+                vec![sidecar(
+                    "/lib/b/Subs/x/3_German (Forced).srt",
+                    Some("de"),
+                    true,
+                    false
+                )],
+                vec![sidecar(
+                    "/lib/b/Subtitles/y.es.srt",
+                    Some("es"),
+                    false,
+                    false
+                )],
+            ]
+        );
+    }
+
+    #[test]
+    fn prefers_one_sidecar_per_language() {
+        let file = pair_sidecars(
+            paths(&["/lib/movie.mkv"]),
+            paths(&[
+                "/lib/movie.en.forced.srt",
+                "/lib/movie.en.sdh.srt",
+                "/lib/movie.en.ass",
+                "/lib/movie.fr.sdh.srt",
+            ]),
+            &SidecarRules::default(),
+        )
+        .remove(0);
+
+        let preferred: Vec<&Path> = file
+            .preferred_subtitles()
+            .into_iter()
+            .map(|sidecar| sidecar.path.as_path())
+            .collect();
+        assert_eq!(
+            preferred,
+            [
+                Path::new("/lib/movie.en.ass"),
+                Path::new("/lib/movie.fr.sdh.srt")
+            ]
+        );
+    }
+}
@@ -0,0 +1,957 @@
+use std::cell::OnceCell;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use crate::cancel::CancellationToken;
+use crate::db::{Database, IndexedFile};
+use crate::index::{self, MediaFile, SidecarRules};
+use crate::models::ModelManager;
+use crate::ocr::Ocr;
+use crate::search::SearchIndex;
+use crate::subtitles::{self, ParseWarning, Segment};
+use crate::sync::{self, SyncCorrection, SyncResult};
+use crate::transcribe::{TranscribeOptions, Transcriber, TranscriberModel};
+use crate::writeback::{self, SidecarOutput};
+use crate::{EngramResult, errors::EngramError, get_engram_dir, media};
+
+pub const DEFAULT_EXTENSIONS: &[&str] =
+    &["mp4", "mkv", "avi", "mov", "webm", "m4v", "wmv", "flv"];
+
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct IndexerConfig {
+    pub roots: Vec<PathBuf>,
+    pub extensions: Vec<String>,
+    pub model: TranscriberModel,
+    // Used for files under roots without options of their own, see
+    // `Indexer::set_root_transcribe_options`.
+    pub transcribe_options: TranscribeOptions,
+    // An imported model to use instead of `model`, see `ModelManager::import`.
+    pub custom_model: Option<String>,
+    pub batch_size: usize,
+    pub sidecar_rules: SidecarRules,
+    // Read image based subtitle tracks with Tesseract before falling back to
+    // Whisper.
+    pub ocr: bool,
+    // Also write Whisper transcriptions out as subtitle files when set.
+    pub write_sidecars: Option<SidecarOutput>,
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        Self {
+            roots: dirs::home_dir().into_iter().collect(),
+            extensions: DEFAULT_EXTENSIONS
+                .iter()
+                .map(|e| e.to_string())
+                .collect(),
+            model: TranscriberModel::Base,
+            transcribe_options: TranscribeOptions::default(),
+            custom_model: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            sidecar_rules: SidecarRules::default(),
+            ocr: true,
+            write_sidecars: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleSource {
+    Sidecar,
+    Embedded,
+    // Read from an image based track with OCR.
+    Recognized,
+    Transcribed,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IndexStats {
+    pub discovered: usize,
+    pub skipped: usize,
+    pub indexed: usize,
+    pub failed: Vec<(PathBuf, String)>,
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexStage {
+    ReadingSubtitles,
+    ExtractingSubtitles,
+    RecognizingText,
+    ExtractingAudio,
+    // Percentage of the Whisper model downloaded so far, only on first use.
+    DownloadingModel(i32),
+    // Percentage of the audio Whisper has processed so far.
+    Transcribing(i32),
+    Indexing,
+}
+
+#[derive(Debug, Clone)]
+pub enum IndexEvent {
+    Discovered {
+        total: usize,
+    },
+    FileStarted {
+        path: PathBuf,
+        position: usize,
+        total: usize,
+    },
+    Stage {
+        path: PathBuf,
+        stage: IndexStage,
+    },
+    FileSkipped {
+        path: PathBuf,
+    },
+    // Problems the sidecar parser recovered from.
+    SubtitleWarnings {
+        path: PathBuf,
+        warnings: Vec<ParseWarning>,
+    },
+    // A transcription was saved as a subtitle file.
+    SidecarWritten {
+        path: PathBuf,
+        subtitle: PathBuf,
+    },
+    FileFinished {
+        path: PathBuf,
+        source: SubtitleSource,
+        segments: usize,
+    },
+    FileFailed {
+        path: PathBuf,
+        error: String,
+    },
+    Finished(IndexStats),
+}
+
+pub type EventHandler = Arc<dyn Fn(IndexEvent) + Send + Sync>;
+
+pub struct Indexer {
+    db: Database,
+    index: SearchIndex,
+    config: IndexerConfig,
+    transcriber: Option<Transcriber>,
+    // Looked up once, None when Tesseract is not installed.
+    ocr: OnceCell<Option<Ocr>>,
+    on_event: Option<EventHandler>,
+    cancel: CancellationToken,
+}
+
+impl Indexer {
+    pub fn new(
+        db: Database,
+        index: SearchIndex,
+        config: IndexerConfig,
+    ) -> Self {
+        Self {
+            db,
+            index,
+            config,
+            transcriber: None,
+            ocr: OnceCell::new(),
+            on_event: None,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    pub fn on_event<F>(&mut self, handler: F)
+    where
+        F: Fn(IndexEvent) + Send + Sync + 'static,
+    {
+        self.on_event = Some(Arc::new(handler));
+    }
+
+    pub fn set_cancel_token(&mut self, cancel: CancellationToken) {
+        self.cancel = cancel;
+    }
+
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    pub fn open(config: IndexerConfig) -> EngramResult<Self> {
+        let dir = get_engram_dir()?;
+        let db = Database::open(&dir.join("engram.db"))?;
+        let index = SearchIndex::open_or_create(&dir.join("index"))?;
+
+        let mut indexer = Self::new(db, index, config);
+        if indexer.index.num_docs() == 0 {
+            indexer.rebuild_index()?;
+        }
+
+        Ok(indexer)
+    }
+
+    // The library's search index for searching while another indexer may be
+    // running. Unlike `open` it never writes to the index.
+    pub fn open_search_index() -> EngramResult<SearchIndex> {
+        SearchIndex::open_read_only(&get_engram_dir()?.join("index"))
+    }
+
+    // Fills an empty search index from the segments stored in the database,
+    // without reading any media again.
+    pub fn rebuild_index(&mut self) -> EngramResult<usize> {
+        let mut rebuilt = 0;
+        for path in self.db.all_paths()? {
+            if let Some(segments) = self.db.load_segments(&path)? {
+                self.index.update_segments(Path::new(&path), &segments)?;
+                rebuilt += 1;
+            }
+        }
+
+        if rebuilt > 0 {
+            self.index.commit()?;
+        }
+
+        Ok(rebuilt)
+    }
+
+    pub fn db(&self) -> &Database {
+        &self.db
+    }
+
+    pub fn search_index(&self) -> &SearchIndex {
+        &self.index
+    }
+
+    pub fn config(&self) -> &IndexerConfig {
+        &self.config
+    }
+
+    pub fn run(&mut self) -> EngramResult<IndexStats> {
+        let exts: Vec<&str> =
+            self.config.extensions.iter().map(String::as_str).collect();
+        let files = index::get_files_par(
+            &self.config.roots,
+            &exts,
+            &self.config.sidecar_rules,
+        )?;
+
+        let total = files.len();
+        let mut stats = IndexStats {
+            discovered: total,
+            ..Default::default()
+        };
+        self.emit(IndexEvent::Discovered { total });
+
+        let batch_size = self.config.batch_size.max(1);
+        let mut pending = 0usize;
+
+        for (position, file) in files.iter().enumerate() {
+            if self.cancel.is_cancelled() {
+                stats.cancelled = true;
+                break;
+            }
+
+            let path = file.media.clone();
+            self.emit(IndexEvent::FileStarted {
+                path: path.clone(),
+                position,
+                total,
+            });
+
+            match self.index_file(file) {
+                Ok(Some((source, segments))) => {
+                    stats.indexed += 1;
+                    pending += 1;
+                    self.emit(IndexEvent::FileFinished {
+                        path,
+                        source,
+                        segments,
+                    });
+                }
+                Ok(None) => {
+                    stats.skipped += 1;
+                    self.emit(IndexEvent::FileSkipped { path });
+                }
+                // Nothing was written for the interrupted file, so everything
+                // stored so far is still consistent.
+                Err(EngramError::Cancelled) => {
+                    stats.cancelled = true;
+                    break;
+                }
+                Err(e) => {
+                    let error = e.to_string();
+                    stats.failed.push((path.clone(), error.clone()));
+                    self.emit(IndexEvent::FileFailed { path, error });
+                }
+            }
+
+            if pending >= batch_size {
+                self.index.commit()?;
+                pending = 0;
+            }
+        }
+
+        if pending > 0 {
+            self.index.commit()?;
+        }
+
+        self.emit(IndexEvent::Finished(stats.clone()));
+
+        Ok(stats)
+    }
+
+    // Returns None if the file was already indexed and has not changed,
+    // otherwise where its segments came from and how many there were.
+    pub fn index_file(
+        &mut self,
+        file: &MediaFile,
+    ) -> EngramResult<Option<(SubtitleSource, usize)>> {
+        let path = file.media.to_string_lossy().to_string();
+        let (modified_at, file_size) = file_stamp(&file.media)?;
+
+        if self.db.is_upto_date(&path, modified_at, file_size)?
+            && self.index.has_media_file(&file.media)?
+        {
+            return Ok(None);
+        }
+
+        self.store_media(file, modified_at, file_size).map(Some)
+    }
+
+    // Reads the segments of `file` and only then replaces what was stored
+    // for it, so a failure leaves the previous ones in place.
+    fn store_media(
+        &mut self,
+        file: &MediaFile,
+        modified_at: i64,
+        file_size: i64,
+    ) -> EngramResult<(SubtitleSource, usize)> {
+        let path = file.media.to_string_lossy().to_string();
+        let options = self.transcribe_options(&file.media)?;
+        let (segments, source) = self.load_segments(file, &options)?;
+        self.cancel.check()?;
+
+        // Written before anything is stored, so a failure is retried on the
+        // next run.
+        if source == SubtitleSource::Transcribed
+            && let Some(output) = &self.config.write_sidecars
+            && let Some(subtitle) =
+                self.write_sidecar(output, &file.media, &segments)?
+        {
+            self.emit(IndexEvent::SidecarWritten {
+                path: file.media.clone(),
+                subtitle,
+            });
+        }
+        self.emit_stage(&file.media, IndexStage::Indexing);
+
+        let (transcription_model, transcription_options) = match source {
+            SubtitleSource::Transcribed => {
+                (Some(self.model_name().to_string()), Some(options))
+            }
+            _ => (None, None),
+        };
+
+        self.db.store_file(
+            &IndexedFile {
+                path,
+                modified_at,
+                file_size,
+                has_subtitles: source != SubtitleSource::Transcribed,
+                transcription_model,
+                transcription_options,
+            },
+            &segments,
+        )?;
+        self.index.update_segments(&file.media, &segments)?;
+
+        Ok((source, segments.len()))
+    }
+
+    pub fn commit(&mut self) -> EngramResult<()> {
+        self.index.commit()
+    }
+
+    // Drops files that no longer exist from both the database and the index.
+    pub fn prune_missing(&mut self) -> EngramResult<Vec<String>> {
+        let removed = self.db.prune_missing()?;
+
+        for path in &removed {
+            self.index.remove_media_file(Path::new(path))?;
+        }
+
+        if !removed.is_empty() {
+            self.index.commit()?;
+        }
+
+        Ok(removed)
+    }
+
+    // Decodes `subtitle` with `encoding` from now on, or detects it again when
+    // None. Media already indexed from it is indexed again right away.
+    pub fn set_encoding_override(
+        &mut self,
+        subtitle: &Path,
+        encoding: Option<&str>,
+    ) -> EngramResult<usize> {
+        if let Some(label) = encoding {
+            subtitles::encoding_for_label(label)?;
+        }
+        self.db
+            .set_encoding_override(&subtitle.to_string_lossy(), encoding)?;
+
+        let media = self.media_with_sidecar(subtitle)?;
+        self.reindex(&media)
+    }
+
+    // Lines `subtitle` up with the speech in the media it belongs to and
+    // keeps the correction for later runs. Returns it along with the number
+    // of files indexed again.
+    pub fn sync_subtitle(
+        &mut self,
+        subtitle: &Path,
+    ) -> EngramResult<(SyncResult, usize)> {
+        let media = self.media_with_sidecar(subtitle)?;
+        let file = media.first().ok_or_else(|| {
+            EngramError::MediaError(format!(
+                "No indexed media uses {}",
+                subtitle.display()
+            ))
+        })?;
+
+        let segments = self.parse_sidecar(subtitle)?;
+        let audio =
+            media::extract_audio_cancellable(&file.media, &self.cancel)?;
+        let result = sync::estimate(&segments, &audio);
+
+        self.db.set_sync_correction(
+            &subtitle.to_string_lossy(),
+            Some(&result.correction),
+        )?;
+        let reindexed = self.reindex(&media)?;
+
+        Ok((result, reindexed))
+    }
+
+    // Sets the correction by hand, None goes back to the original timing.
+    pub fn set_sync_correction(
+        &mut self,
+        subtitle: &Path,
+        correction: Option<SyncCorrection>,
+    ) -> EngramResult<usize> {
+        self.db.set_sync_correction(
+            &subtitle.to_string_lossy(),
+            correction.as_ref(),
+        )?;
+
+        let media = self.media_with_sidecar(subtitle)?;
+        self.reindex(&media)
+    }
+
+    // The options of the innermost library root holding `media` that has
+    // its own, the configured ones otherwise.
+    pub fn transcribe_options(
+        &self,
+        media: &Path,
+    ) -> EngramResult<TranscribeOptions> {
+        Ok(self
+            .db
+            .root_transcribe_options()?
+            .into_iter()
+            .filter(|(root, _)| media.starts_with(root))
+            .max_by_key(|(root, _)| Path::new(root).components().count())
+            .map(|(_, options)| options)
+            .unwrap_or_else(|| self.config.transcribe_options.clone()))
+    }
+
+    // Only files transcribed after the change use the new options.
+    pub fn set_root_transcribe_options(
+        &self,
+        root: &Path,
+        options: Option<&TranscribeOptions>,
+    ) -> EngramResult<()> {
+        self.db
+            .set_root_transcribe_options(&root.to_string_lossy(), options)
+    }
+
+    // Returns where the transcription went, or None when a subtitle file of
+    // the user's is already in the way. Falls back to a mirror under the
+    // engram directory when the media's directory is read-only.
+    fn write_sidecar(
+        &self,
+        output: &SidecarOutput,
+        media: &Path,
+        segments: &[Segment],
+    ) -> EngramResult<Option<PathBuf>> {
+        let target = output.path_for(media, self.model_name());
+        if target.exists()
+            && !self.db.is_generated_sidecar(&target.to_string_lossy())?
+        {
+            return Ok(None);
+        }
+
+        let contents = output.format.generate(segments);
+        let written = writeback::write_sidecar(&target, &contents)?;
+
+        self.db.add_generated_sidecar(
+            &written.to_string_lossy(),
+            &media.to_string_lossy(),
+        )?;
+
+        Ok(Some(written))
+    }
+
+    // Indexed media that `subtitle` is a sidecar of.
+    fn media_with_sidecar(
+        &self,
+        subtitle: &Path,
+    ) -> EngramResult<Vec<MediaFile>> {
+        // Sidecars sit next to their media or up to two folders below it.
+        let dirs: Vec<&Path> = subtitle.ancestors().skip(1).take(3).collect();
+        let exts: Vec<&str> =
+            self.config.extensions.iter().map(String::as_str).collect();
+
+        let mut media = Vec::new();
+        for path in self.db.all_paths()?.into_iter().map(PathBuf::from) {
+            if !path.parent().is_some_and(|dir| dirs.contains(&dir)) {
+                continue;
+            }
+
+            let file =
+                index::media_file(&path, &exts, &self.config.sidecar_rules)?;
+            if file.subtitles.iter().any(|s| s.path == subtitle) {
+                media.push(file);
+            }
+        }
+
+        Ok(media)
+    }
+
+    fn reindex(&mut self, media: &[MediaFile]) -> EngramResult<usize> {
+        for file in media {
+            let (modified_at, file_size) = file_stamp(&file.media)?;
+            self.store_media(file, modified_at, file_size)?;
+        }
+
+        if !media.is_empty() {
+            self.index.commit()?;
+        }
+
+        Ok(media.len())
+    }
+
+    // With the stored sync correction applied.
+    fn read_sidecar(&self, path: &Path) -> EngramResult<Vec<Segment>> {
+        let mut segments = self.parse_sidecar(path)?;
+        if let Some(correction) =
+            self.db.sync_correction(&path.to_string_lossy())?
+        {
+            correction.apply_all(&mut segments);
+        }
+
+        Ok(segments)
+    }
+
+    fn parse_sidecar(&self, path: &Path) -> EngramResult<Vec<Segment>> {
+        let encoding = self
+            .db
+            .encoding_override(&path.to_string_lossy())?
+            .map(|label| subtitles::encoding_for_label(&label))
+            .transpose()?;
+
+        let (segments, warnings) =
+            subtitles::parse_subtitle_file(path, encoding)?;
+        if !warnings.is_empty() {
+            self.emit(IndexEvent::SubtitleWarnings {
+                path: path.to_path_buf(),
+                warnings,
+            });
+        }
+
+        Ok(segments)
+    }
+
+    fn load_segments(
+        &mut self,
+        file: &MediaFile,
+        options: &TranscribeOptions,
+    ) -> EngramResult<(Vec<Segment>, SubtitleSource)> {
+        let sidecars = file.preferred_subtitles();
+        if !sidecars.is_empty() {
+            self.emit_stage(&file.media, IndexStage::ReadingSubtitles);
+        }
+
+        // A broken sidecar only fails the file when no other one worked.
+        let mut segments = Vec::new();
+        let mut error = None;
+        for sidecar in sidecars {
+            match self.read_sidecar(&sidecar.path) {
+                Ok(parsed) => {
+                    segments.extend(parsed.into_iter().map(|seg| Segment {
+                        language: sidecar.language.clone(),
+                        ..seg
+                    }))
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+
+        if !segments.is_empty() {
+            segments.sort_by_key(|seg| seg.start);
+            return Ok((segments, SubtitleSource::Sidecar));
+        }
+        if let Some(e) = error {
+            return Err(e);
+        }
+
+        // A missing subtitle stream is not an error, we fall back to Whisper.
+        self.emit_stage(&file.media, IndexStage::ExtractingSubtitles);
+        if let Ok(tracks) = media::extract_subtitles(&file.media) {
+            let mut segments: Vec<Segment> = media::preferred_tracks(&tracks)
+                .into_iter()
+                .flat_map(|track| track.segments.iter().cloned())
+                .collect();
+            if !segments.is_empty() {
+                segments.sort_by_key(|seg| seg.start);
+                return Ok((segments, SubtitleSource::Embedded));
+            }
+        }
+
+        if self.config.ocr {
+            match self.recognize_subtitles(&file.media) {
+                Ok(segments) if !segments.is_empty() => {
+                    return Ok((segments, SubtitleSource::Recognized));
+                }
+                Err(EngramError::Cancelled) => {
+                    return Err(EngramError::Cancelled);
+                }
+                _ => {}
+            }
+        }
+
+        self.emit_stage(&file.media, IndexStage::ExtractingAudio);
+        let audio =
+            media::extract_audio_cancellable(&file.media, &self.cancel)?;
+
+        // Loads the model before reporting progress on the transcription,
+        // downloading it when this is the first file that needs it.
+        self.transcriber(&file.media)?;
+        self.emit_stage(&file.media, IndexStage::Transcribing(0));
+        let on_event = self.on_event.clone();
+        let path = file.media.clone();
+        let cancel = self.cancel.clone();
+        let segments = self.transcriber(&file.media)?.transcribe_cancellable(
+            &audio,
+            options,
+            &cancel,
+            move |percent| {
+                if let Some(handler) = &on_event {
+                    handler(IndexEvent::Stage {
+                        path: path.clone(),
+                        stage: IndexStage::Transcribing(percent),
+                    });
+                }
+            },
+        )?;
+
+        Ok((segments.into_vec(), SubtitleSource::Transcribed))
+    }
+
+    // Segments from the image based tracks, one per language. Empty when
+    // Tesseract is not installed or there are no such tracks.
+    fn recognize_subtitles(&self, media: &Path) -> EngramResult<Vec<Segment>> {
+        let Some(ocr) = self.ocr.get_or_init(Ocr::detect) else {
+            return Ok(Vec::new());
+        };
+
+        let tracks = media::bitmap_subtitle_tracks(media)?;
+        if tracks.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.emit_stage(media, IndexStage::RecognizingText);
+        let mut segments = Vec::new();
+        for track in media::preferred_tracks(&tracks) {
+            let mut job = ocr.job(track.language.as_deref())?;
+            media::extract_subtitle_images(
+                media,
+                track.index,
+                &self.cancel,
+                |start, end, image| job.add(start, end, &image),
+            )?;
+            segments.extend(job.recognize(&self.cancel)?);
+        }
+        segments.sort_by_key(|seg| seg.start);
+
+        Ok(segments)
+    }
+
+    // The model is only loaded (and downloaded) once a file needs it.
+    fn transcriber(&mut self, media: &Path) -> EngramResult<&Transcriber> {
+        if self.transcriber.is_none() {
+            let mut models = ModelManager::open()?;
+            let path = match &self.config.custom_model {
+                Some(name) => models.custom_model(name)?,
+                None => {
+                    let mut last = -1;
+                    models.install(self.config.model, |done, total| {
+                        let percent = total
+                            .filter(|&total| total > 0)
+                            .map(|total| (done * 100 / total) as i32)
+                            .unwrap_or(0);
+                        if percent != last {
+                            last = percent;
+                            self.emit_stage(
+                                media,
+                                IndexStage::DownloadingModel(percent),
+                            );
+                        }
+                    })?
+                }
+            };
+            self.transcriber = Some(Transcriber::from_file(&path)?);
+        }
+
+        Ok(self.transcriber.as_ref().unwrap())
+    }
+
+    // What transcriptions are tagged with.
+    fn model_name(&self) -> &str {
+        self.config
+            .custom_model
+            .as_deref()
+            .unwrap_or(self.config.model.name())
+    }
+
+    fn emit(&self, event: IndexEvent) {
+        if let Some(handler) = &self.on_event {
+            handler(event);
+        }
+    }
+
+    fn emit_stage(&self, path: &Path, stage: IndexStage) {
+        self.emit(IndexEvent::Stage {
+            path: path.to_path_buf(),
+            stage,
+        });
+    }
+}
+
+fn file_stamp(path: &Path) -> EngramResult<(i64, i64)> {
+    let metadata = std::fs::metadata(path)?;
+    let modified_at = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    Ok((modified_at, metadata.len() as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::Mutex;
+
+    struct Library {
+        dir: PathBuf,
+        indexer: Indexer,
+        events: Arc<Mutex<Vec<IndexEvent>>>,
+    }
+
+    impl Library {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("engram-indexer-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("media")).unwrap();
+
+            let db = Database::open(&dir.join("engram.db")).unwrap();
+            let index =
+                SearchIndex::open_or_create(&dir.join("index")).unwrap();
+            let config = IndexerConfig {
+                roots: vec![dir.join("media")],
+                ..Default::default()
+            };
+
+            let mut indexer = Indexer::new(db, index, config);
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let sink = events.clone();
+            indexer.on_event(move |event| sink.lock().unwrap().push(event));
+
+            Self {
+                dir,
+                indexer,
+                events,
+            }
+        }
+
+        fn write(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.dir.join("media").join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        }
+
+        fn stages(&self) -> Vec<IndexStage> {
+            let mut events = self.events.lock().unwrap();
+            events
+                .drain(..)
+                .filter_map(|event| match event {
+                    IndexEvent::Stage { stage, .. } => Some(stage),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        fn stored(&self, media: &Path) -> Vec<String> {
+            self.indexer
+                .db()
+                .load_segments(&media.to_string_lossy())
+                .unwrap()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|seg| seg.text)
+                .collect()
+        }
+    }
+
+    impl Drop for Library {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    const SRT: &str = "1\n00:00:01,000 --> 00:00:02,000\nHello\n";
+
+    #[test]
+    fn prefers_sidecars_and_skips_unchanged_files() {
+        let mut library = Library::new("sidecar");
+        let media = library.write("movie.mkv", "not really a video");
+        library.write("movie.en.srt", SRT);
+
+        let stats = library.indexer.run().unwrap();
+        assert_eq!((stats.discovered, stats.indexed), (1, 1));
+        // The media itself is never opened when a sidecar works.
+        assert_eq!(
+            library.stages(),
+            [IndexStage::ReadingSubtitles, IndexStage::Indexing]
+        );
+        assert_eq!(library.stored(&media), ["Hello"]);
+        assert!(
+            library
+                .indexer
+                .search_index()
+                .has_media_file(&media)
+                .unwrap()
+        );
+
+        let stats = library.indexer.run().unwrap();
+        assert_eq!((stats.indexed, stats.skipped), (0, 1));
+        assert!(library.stages().is_empty());
+    }
+
+    #[test]
+    fn falls_back_from_embedded_tracks_to_audio() {
+        let mut library = Library::new("fallback");
+        let media = library.write("movie.mkv", "not really a video");
+
+        let stats = library.indexer.run().unwrap();
+        assert_eq!(stats.failed.len(), 1);
+        // Embedded tracks come first, and neither they nor the image based
+        // ones exist here, so the audio is next in line for Whisper.
+        assert_eq!(
+            library.stages(),
+            [IndexStage::ExtractingSubtitles, IndexStage::ExtractingAudio]
+        );
+        assert!(library.stored(&media).is_empty());
+        assert!(
+            library
+                .indexer
+                .db()
+                .get_file(&media.to_string_lossy())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn keeps_segments_when_reindexing_fails() {
+        let mut library = Library::new("reindex");
+        let media = library.write("movie.mkv", "not really a video");
+        let subtitle = library.write("movie.srt", SRT);
+        library.indexer.run().unwrap();
+
+        // Only an empty sidecar left, so indexing again fails on the audio.
+        fs::write(&subtitle, "").unwrap();
+        assert!(
+            library
+                .indexer
+                .set_sync_correction(&subtitle, None)
+                .is_err()
+        );
+        assert_eq!(library.stored(&media), ["Hello"]);
+        assert!(
+            library
+                .indexer
+                .search_index()
+                .has_media_file(&media)
+                .unwrap()
+        );
+
+        fs::write(&subtitle, SRT.replace("Hello", "Again")).unwrap();
+        assert_eq!(
+            library
+                .indexer
+                .set_sync_correction(&subtitle, None)
+                .unwrap(),
+            1
+        );
+        assert_eq!(library.stored(&media), ["Again"]);
+    }
+
+    #[test]
+    fn searches_while_the_indexer_holds_the_writer() {
+        let mut library = Library::new("read-only");
+        let media = library.write("movie.mkv", "not really a video");
+        let index_path = library.dir.join("index");
+        let missing = library.dir.join("missing");
+        assert!(SearchIndex::open_read_only(&missing).is_err());
+        assert!(!missing.exists());
+
+        library.write("movie.srt", SRT);
+        library.indexer.run().unwrap();
+
+        // The indexer still holds the writer lock here.
+        let mut index = SearchIndex::open_read_only(&index_path).unwrap();
+        let results = index.search("hello", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].file, media);
+        assert!(index.remove_media_file(&media).is_err());
+    }
+
+    #[test]
+    fn never_overwrites_subtitles_it_did_not_write() {
+        let library = Library::new("writeback");
+        let media = library.write("movie.mkv", "not really a video");
+        let output = SidecarOutput::default();
+        let segments = [Segment {
+            start: 1000,
+            end: 2000,
+            text: "Hello".into(),
+            ..Default::default()
+        }];
+
+        let target = library.write("movie.engram.srt", "mine");
+        let written = library.indexer.write_sidecar(&output, &media, &segments);
+        assert_eq!(written.unwrap(), None);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "mine");
+
+        // Its own sidecars are rewritten with the new transcription.
+        fs::remove_file(&target).unwrap();
+        for _ in 0..2 {
+            let written =
+                library.indexer.write_sidecar(&output, &media, &segments);
+            assert_eq!(written.unwrap().as_deref(), Some(target.as_path()));
+        }
+        assert_eq!(fs::read_to_string(&target).unwrap(), SRT);
+    }
+}
@@ -1,10 +1,17 @@
+pub mod cancel;
 pub mod db;
 pub mod errors;
+pub mod export;
 pub mod index;
+pub mod indexer;
 pub mod media;
+pub mod models;
+pub mod ocr;
 pub mod search;
 pub mod subtitles;
+pub mod sync;
 pub mod transcribe;
+pub mod writeback;
 
 pub type EngramResult<T> = Result<T, errors::EngramError>;
 
@@ -1,8 +1,10 @@
+use std::collections::HashSet;
 use std::path::Path;
 
 use ffmpeg_next::media::Type::{Audio, Subtitle, Video};
 
-use crate::{EngramResult, errors::EngramError, subtitles};
+use crate::cancel::CancellationToken;
+use crate::{EngramResult, errors::EngramError, index, ocr, subtitles};
 
 pub struct RawFrame {
     pub data: Vec<u8>,
@@ -11,6 +13,13 @@ pub struct RawFrame {
 }
 
 pub fn extract_audio(path: &Path) -> EngramResult<Box<[f32]>> {
+    extract_audio_cancellable(path, &CancellationToken::new())
+}
+
+pub fn extract_audio_cancellable(
+    path: &Path,
+    cancel: &CancellationToken,
+) -> EngramResult<Box<[f32]>> {
     ffmpeg_next::init()?;
 
     let mut ictx = ffmpeg_next::format::input(path)?;
@@ -40,6 +49,8 @@ pub fn extract_audio(path: &Path) -> EngramResult<Box<[f32]>> {
     let mut samples: Vec<f32> = Vec::new();
 
     for (stream, packet) in ictx.packets() {
+        cancel.check()?;
+
         if stream.index() != stream_idx {
             continue;
         }
@@ -89,7 +100,10 @@ pub fn generate_thumbnail(
 
         let mut frame = ffmpeg_next::frame::Video::empty();
         if decoder.receive_frame(&mut frame).is_ok() {
-            return decode_frame_to_raw(&frame, shrink_factor);
+            return decode_frame_to_raw(
+                &frame,
+                FrameSize::Shrink(shrink_factor),
+            );
         }
     }
 
@@ -103,12 +117,55 @@ pub fn generate_thumbnail_preview(
     start: u64,
     end: u64,
     shrink_factor: u32,
+) -> EngramResult<Box<[RawFrame]>> {
+    sample_frames(
+        file,
+        (start * 1000) as i64,
+        (end * 1000) as i64,
+        12,
+        FrameSize::Shrink(shrink_factor),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSize {
+    // Divides both dimensions by the factor.
+    Shrink(u32),
+    // Scales to the given width, keeping the aspect ratio.
+    Width(u32),
+}
+
+impl FrameSize {
+    fn apply(&self, width: u32, height: u32) -> (u32, u32) {
+        match *self {
+            FrameSize::Shrink(factor) => {
+                let factor = factor.max(1);
+                ((width / factor).max(1), (height / factor).max(1))
+            }
+            FrameSize::Width(target) => {
+                let scaled = (height as u64 * target as u64
+                    / width.max(1) as u64) as u32;
+                // Most encoders want even dimensions.
+                (target.max(2) & !1, scaled.max(2) & !1)
+            }
+        }
+    }
+}
+
+// Decodes the frames between `start` and `end` (in milliseconds) at a fixed
+// rate into RGBA images.
+pub fn sample_frames(
+    file: &Path,
+    start: i64,
+    end: i64,
+    fps: u32,
+    size: FrameSize,
 ) -> EngramResult<Box<[RawFrame]>> {
     ffmpeg_next::init()?;
 
     let mut ictx = ffmpeg_next::format::input(file)?;
 
-    let position = (start * 1_000_000) as i64;
+    let position = start * 1000;
     ictx.seek(position, ..position)?;
 
     let stream = ictx.streams().best(Video).ok_or_else(|| {
@@ -124,9 +181,10 @@ pub fn generate_thumbnail_preview(
 
     let mut decoder = ctx.decoder().video()?;
 
-    let delay = 1.0 / 12.0; // FPS
+    let delay = 1.0 / fps.max(1) as f64;
+    let end = end as f64 / 1000.0;
 
-    let mut nts = start as f64; // Next time stamp
+    let mut nts = start as f64 / 1000.0; // Next time stamp
     let mut frames: Vec<RawFrame> = Vec::new();
 
     'outer: for (stream, packet) in ictx.packets() {
@@ -142,12 +200,12 @@ pub fn generate_thumbnail_preview(
                 .map(|t| t as f64 * f64::from(time_base)) // ticks to seconds
                 .unwrap_or(nts);
 
-            if cts >= end as f64 {
+            if cts >= end {
                 break 'outer;
             }
 
             if cts >= nts {
-                frames.push(decode_frame_to_raw(&frame, shrink_factor)?);
+                frames.push(decode_frame_to_raw(&frame, size)?);
                 nts += delay;
             }
         }
@@ -156,88 +214,346 @@ pub fn generate_thumbnail_preview(
     Ok(frames.into())
 }
 
-pub fn extract_subtitles(
-    path: &Path,
-) -> EngramResult<Box<[subtitles::Segment]>> {
+#[derive(Debug, Clone)]
+pub struct SubtitleTrack {
+    // Stream index in the container.
+    pub index: usize,
+    // ISO 639-1 code, from the stream tags or failing that its title.
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub forced: bool,
+    pub sdh: bool,
+    pub segments: Vec<subtitles::Segment>,
+}
+
+// One track per language, the full non-SDH one when there is a choice.
+pub fn preferred_tracks(tracks: &[SubtitleTrack]) -> Vec<&SubtitleTrack> {
+    let mut sorted: Vec<&SubtitleTrack> = tracks.iter().collect();
+    sorted.sort_by_key(|track| (track.forced, track.sdh, track.index));
+
+    let mut languages = HashSet::new();
+    sorted
+        .into_iter()
+        .filter(|track| languages.insert(track.language.as_deref()))
+        .collect()
+}
+
+// Decodes every text subtitle stream in a single pass. Image based tracks
+// such as PGS and VobSub are left to `extract_subtitle_images`.
+pub fn extract_subtitles(path: &Path) -> EngramResult<Vec<SubtitleTrack>> {
     ffmpeg_next::init()?;
     let mut ictx = ffmpeg_next::format::input(&path)?;
-    let mut entries = Vec::new();
 
-    let stream =
-        ictx.streams()
-            .best(Subtitle)
-            .ok_or(EngramError::MediaError(format!(
-                "No subtitle stream found for {}",
-                path.display()
-            )))?;
+    let mut tracks = Vec::new();
+    let mut decoders = Vec::new();
 
-    let stream_index = stream.index();
-    let time_base = stream.time_base();
+    for stream in ictx.streams() {
+        let parameters = stream.parameters();
+        if parameters.medium() != Subtitle || is_bitmap(parameters.id()) {
+            continue;
+        }
+
+        let Ok(decoder) =
+            ffmpeg_next::codec::context::Context::from_parameters(parameters)
+                .and_then(|ctx| ctx.decoder().subtitle())
+        else {
+            continue;
+        };
+
+        tracks.push(subtitle_track(&stream));
+        decoders.push((decoder, stream.time_base()));
+    }
+
+    if tracks.is_empty() {
+        return Err(EngramError::MediaError(format!(
+            "No text subtitle stream found for {}",
+            path.display()
+        )));
+    }
+
+    let ass_options = subtitles::AssOptions::default();
+
+    for (stream, packet) in ictx.packets() {
+        let Some(i) = tracks.iter().position(|t| t.index == stream.index())
+        else {
+            continue;
+        };
+        let (decoder, time_base) = &mut decoders[i];
+        let track = &mut tracks[i];
+
+        let mut subtitle = ffmpeg_next::Subtitle::new();
+        let _ = decoder.decode(&packet, &mut subtitle);
+        let (start, end) = packet_span(&packet, *time_base);
+
+        for rect in subtitle.rects() {
+            use ffmpeg_next::subtitle::Rect;
+
+            let segment = match rect {
+                Rect::Text(t) => subtitles::Segment::from_markup(
+                    start,
+                    end,
+                    t.get().trim().to_owned(),
+                ),
+                Rect::Ass(a) => {
+                    // Older FFmpeg versions still emit full Dialogue lines.
+                    let raw = a.get();
+                    let (event, format) = match raw.strip_prefix("Dialogue:") {
+                        Some(event) => (event, subtitles::ASS_DEFAULT_FORMAT),
+                        None => (raw, subtitles::ASS_EMBEDDED_FORMAT),
+                    };
+                    // A malformed event only costs that line, not the
+                    // whole track.
+                    match subtitles::parse_ass_event(
+                        event,
+                        format,
+                        &ass_options,
+                    ) {
+                        // The packet carries the timing, not the event.
+                        Ok(Some(segment)) => subtitles::Segment {
+                            start,
+                            end,
+                            ..segment
+                        },
+                        Ok(None) | Err(_) => continue,
+                    }
+                }
+                _ => continue,
+            };
+
+            if !segment.text.is_empty() {
+                track.segments.push(subtitles::Segment {
+                    language: track.language.clone(),
+                    ..segment
+                });
+            }
+        }
+    }
 
+    tracks.retain(|track| !track.segments.is_empty());
+
+    Ok(tracks)
+}
+
+// The image based subtitle tracks, without their segments.
+pub fn bitmap_subtitle_tracks(path: &Path) -> EngramResult<Vec<SubtitleTrack>> {
+    ffmpeg_next::init()?;
+    let ictx = ffmpeg_next::format::input(&path)?;
+
+    Ok(ictx
+        .streams()
+        .filter(|stream| {
+            let parameters = stream.parameters();
+            parameters.medium() == Subtitle && is_bitmap(parameters.id())
+        })
+        .map(|stream| subtitle_track(&stream))
+        .collect())
+}
+
+// How long an image stays up when nothing clears it.
+const BITMAP_DURATION: i64 = 5000;
+
+// Decodes the images of an image based subtitle track and hands each one to
+// `on_image` with its timing in milliseconds.
+pub fn extract_subtitle_images<F>(
+    path: &Path,
+    stream_index: usize,
+    cancel: &CancellationToken,
+    mut on_image: F,
+) -> EngramResult<()>
+where
+    F: FnMut(i64, i64, ocr::SubtitleImage) -> EngramResult<()>,
+{
+    use ffmpeg_next::subtitle::Rect;
+
+    ffmpeg_next::init()?;
+    let mut ictx = ffmpeg_next::format::input(&path)?;
+
+    let stream = ictx.stream(stream_index).ok_or_else(|| {
+        EngramError::MediaError(format!("No stream {stream_index}"))
+    })?;
+    let time_base = stream.time_base();
     let ctx = ffmpeg_next::codec::context::Context::from_parameters(
         stream.parameters(),
     )?;
-
     let mut decoder = ctx.decoder().subtitle()?;
 
+    // PGS shows an image until the next display set replaces or clears it,
+    // so its end is only known once that arrives.
+    let mut shown: Option<(i64, ocr::SubtitleImage)> = None;
+
     for (stream, packet) in ictx.packets() {
         if stream.index() != stream_index {
             continue;
         }
+        cancel.check()?;
 
         let mut subtitle = ffmpeg_next::Subtitle::new();
-        let _ = decoder.decode(&packet, &mut subtitle);
+        if !decoder.decode(&packet, &mut subtitle).unwrap_or(false) {
+            continue;
+        }
 
-        let tb_num = time_base.numerator() as i64;
-        let tb_den = time_base.denominator() as i64;
+        let (pts, _) = packet_span(&packet, time_base);
+        let start = pts + subtitle.start() as i64;
 
-        let start = packet
-            .pts()
-            .unwrap_or(0)
+        if let Some((shown_at, image)) = shown.take() {
+            on_image(shown_at, start, image)?;
+        }
+
+        let bitmaps: Vec<_> = subtitle
+            .rects()
+            .filter_map(|rect| match rect {
+                Rect::Bitmap(bitmap) => Some(bitmap),
+                _ => None,
+            })
+            .collect();
+        let Some(image) = render_bitmaps(&bitmaps) else {
+            continue;
+        };
+
+        match subtitle.end() {
+            end if end > subtitle.start() && end != u32::MAX => {
+                on_image(start, pts + end as i64, image)?
+            }
+            _ => shown = Some((start, image)),
+        }
+    }
+
+    if let Some((shown_at, image)) = shown {
+        on_image(shown_at, shown_at + BITMAP_DURATION, image)?;
+    }
+
+    Ok(())
+}
+
+fn is_bitmap(id: ffmpeg_next::codec::Id) -> bool {
+    use ffmpeg_next::codec::Id;
+
+    matches!(
+        id,
+        Id::HDMV_PGS_SUBTITLE | Id::DVD_SUBTITLE | Id::DVB_SUBTITLE | Id::XSUB
+    )
+}
+
+// Language and flags from the stream tags, falling back to its title.
+fn subtitle_track(
+    stream: &ffmpeg_next::format::stream::Stream,
+) -> SubtitleTrack {
+    use ffmpeg_next::format::stream::Disposition;
+
+    let metadata = stream.metadata();
+    let title = metadata.get("title").map(String::from);
+    let mut tags =
+        index::SubtitleTags::parse_title(title.as_deref().unwrap_or_default());
+    if let Some(language) = metadata
+        .get("language")
+        .and_then(subtitles::normalize_language)
+    {
+        tags.language = Some(language.to_string());
+    }
+
+    let disposition = stream.disposition();
+    SubtitleTrack {
+        index: stream.index(),
+        language: tags.language,
+        title,
+        forced: tags.forced || disposition.contains(Disposition::FORCED),
+        sdh: tags.sdh || disposition.contains(Disposition::HEARING_IMPAIRED),
+        segments: Vec::new(),
+    }
+}
+
+// Start and end of a packet in milliseconds.
+fn packet_span(
+    packet: &ffmpeg_next::Packet,
+    time_base: ffmpeg_next::Rational,
+) -> (i64, i64) {
+    let tb_num = time_base.numerator() as i64;
+    let tb_den = time_base.denominator() as i64;
+
+    let start = packet
+        .pts()
+        .unwrap_or(0)
+        .saturating_mul(tb_num)
+        .saturating_mul(1000)
+        / tb_den;
+
+    let end = start
+        + packet
+            .duration()
             .saturating_mul(tb_num)
             .saturating_mul(1000)
             / tb_den;
 
-        let end = start
-            + packet
-                .duration()
-                .saturating_mul(tb_num)
-                .saturating_mul(1000)
-                / tb_den;
-
-        for rect in subtitle.rects() {
-            use ffmpeg_next::subtitle::Rect;
-
-            let text = match rect {
-                Rect::Text(t) => t.get().to_owned(),
-                Rect::Ass(a) => {
-                    let raw = a.get().to_owned();
-                    match raw.splitn(10, ',').nth(9) {
-                        Some(t) => t.trim().to_string(),
-                        None => continue,
-                    }
-                }
-                _ => continue,
-            };
+    (start, end)
+}
 
-            if !text.is_empty() {
-                entries.push(subtitles::Segment { start, end, text });
+// Draws the palette images of one display set onto a single grayscale
+// canvas, dark text on white, the way Tesseract reads best. Brightness and
+// opacity together decide how dark a pixel gets, so a white fill with a black
+// outline comes out as black text.
+fn render_bitmaps(
+    bitmaps: &[ffmpeg_next::subtitle::Bitmap],
+) -> Option<ocr::SubtitleImage> {
+    let rects: Vec<_> = bitmaps
+        .iter()
+        .map(|bitmap| unsafe { &*bitmap.as_ptr() })
+        .filter(|rect| rect.w > 0 && rect.h > 0 && !rect.data[0].is_null())
+        .collect();
+
+    let left = rects.iter().map(|r| r.x).min()?;
+    let top = rects.iter().map(|r| r.y).min()?;
+    let right = rects.iter().map(|r| r.x + r.w).max()?;
+    let bottom = rects.iter().map(|r| r.y + r.h).max()?;
+
+    let mut image =
+        ocr::SubtitleImage::blank((right - left) as u32, (bottom - top) as u32);
+
+    for rect in rects {
+        let (w, h) = (rect.w as usize, rect.h as usize);
+        let colors = rect.nb_colors.max(0) as usize;
+        let stride = rect.linesize[0] as usize;
+        let (indices, palette) = unsafe {
+            (
+                std::slice::from_raw_parts(rect.data[0], stride * h),
+                std::slice::from_raw_parts(
+                    rect.data[1] as *const u32,
+                    if rect.data[1].is_null() { 0 } else { colors },
+                ),
+            )
+        };
+
+        for y in 0..h {
+            for x in 0..w {
+                let Some(&argb) = palette.get(indices[y * stride + x] as usize)
+                else {
+                    continue;
+                };
+                let alpha = argb >> 24;
+                let (r, g, b) =
+                    ((argb >> 16) & 255, (argb >> 8) & 255, argb & 255);
+                let luma = (r * 299 + g * 587 + b * 114) / 1000;
+                let ink = (alpha * luma / 255) as u8;
+
+                image.darken(
+                    (rect.x - left) as u32 + x as u32,
+                    (rect.y - top) as u32 + y as u32,
+                    255 - ink,
+                );
             }
         }
     }
 
-    Ok(entries.into())
+    Some(image)
 }
 
 fn decode_frame_to_raw(
     frame: &ffmpeg_next::frame::Video,
-    shrink_factor: u32,
+    size: FrameSize,
 ) -> EngramResult<RawFrame> {
     use ffmpeg_next::format::Pixel::RGBA;
     use ffmpeg_next::software::scaling::{self, flag::Flags};
 
-    let width = (frame.width() / shrink_factor).max(1);
-    let height = (frame.height() / shrink_factor).max(1);
+    let (width, height) = size.apply(frame.width(), frame.height());
 
     let mut scaler = scaling::Context::get(
         frame.format(),
@@ -252,8 +568,19 @@ fn decode_frame_to_raw(
     let mut out = ffmpeg_next::frame::Video::empty();
     scaler.run(frame, &mut out)?;
 
+    // Rows can be padded, so copy them one at a time.
+    let row = width as usize * 4;
+    let stride = out.stride(0);
+    let data = out
+        .data(0)
+        .chunks(stride)
+        .take(height as usize)
+        .flat_map(|line| &line[..row])
+        .copied()
+        .collect();
+
     Ok(RawFrame {
-        data: out.data(0).to_vec(),
+        data,
         width,
         height,
     })
@@ -0,0 +1,564 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::errors::EngramError;
+use crate::transcribe::TranscriberModel;
+use crate::{EngramResult, get_engram_dir};
+
+const REPO: &str = "https://huggingface.co/ggerganov/whisper.cpp";
+
+// Name, size and SHA-256 of the upstream model files, copied from their Git
+// LFS pointers. Pinned files are only ever checked against this table, so a
+// tampered listing or mirror cannot vouch for a different file.
+const CHECKSUMS: &[(&str, u64, &str)] = &[];
+
+// Lists every file in the repository along with the SHA-256 and size Git LFS
+// keeps for it. Only used for files missing from `CHECKSUMS`.
+const TREE_API: &str =
+    "https://huggingface.co/api/models/ggerganov/whisper.cpp/tree/main";
+
+// Every GGML model starts with this magic number, stored little-endian.
+const GGML_MAGIC: &[u8] = b"lmgg";
+
+const CUSTOM_DIR: &str = "custom";
+
+pub const MODELS: &[TranscriberModel] = &[
+    TranscriberModel::Tiny,
+    TranscriberModel::Base,
+    TranscriberModel::Small,
+    TranscriberModel::Medium,
+    TranscriberModel::Large,
+];
+
+// The file we use for each model in the upstream repository.
+pub fn model_file(model: TranscriberModel) -> &'static str {
+    match model {
+        TranscriberModel::Tiny => "ggml-tiny-q5_1.bin",
+        TranscriberModel::Base => "ggml-base-q5_1.bin",
+        TranscriberModel::Small => "ggml-small-q5_1.bin",
+        TranscriberModel::Medium => "ggml-medium-q5_0.bin",
+        TranscriberModel::Large => "ggml-large-v3-q5_0.bin",
+    }
+}
+
+// Rough download size in bytes, shown until the real one has been fetched.
+fn approximate_size(model: TranscriberModel) -> u64 {
+    match model {
+        TranscriberModel::Tiny => 32_000_000,
+        TranscriberModel::Base => 60_000_000,
+        TranscriberModel::Small => 190_000_000,
+        TranscriberModel::Medium => 539_000_000,
+        TranscriberModel::Large => 1_080_000_000,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ModelEntry {
+    // "base" for the built in models, the chosen name for imported ones.
+    pub name: String,
+    pub path: PathBuf,
+    // Expected size when not installed yet, approximate when the checksum
+    // table has not been fetched.
+    pub size: u64,
+    pub installed: bool,
+    // The file matched its checksum the last time it was checked.
+    pub verified: bool,
+    pub custom: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileInfo {
+    sha256: String,
+    size: u64,
+}
+
+// Downloads, checks and imports Whisper models, kept in a `models` directory
+// next to the database. What is known about the files lives in
+// `manifest.json` so checks still work offline.
+pub struct ModelManager {
+    dir: PathBuf,
+    // Checksums of the upstream files, by file name.
+    expected: HashMap<String, FileInfo>,
+    // Files that passed a check, by path relative to `dir`, along with their
+    // modification time so they are only hashed again once they change.
+    verified: HashMap<String, (FileInfo, u64)>,
+}
+
+impl ModelManager {
+    pub fn open() -> EngramResult<Self> {
+        let dir = get_engram_dir()?.join("models");
+        fs::create_dir_all(dir.join(CUSTOM_DIR))?;
+
+        let mut manager = Self {
+            dir,
+            expected: HashMap::new(),
+            verified: HashMap::new(),
+        };
+        manager.load_manifest()?;
+        manager.migrate_legacy_files()?;
+
+        Ok(manager)
+    }
+
+    pub fn list(&self) -> EngramResult<Vec<ModelEntry>> {
+        let mut entries = Vec::new();
+
+        for &model in MODELS {
+            let file = model_file(model);
+            let path = self.dir.join(file);
+            let size = match fs::metadata(&path) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => self
+                    .expected(file)
+                    .map(|info| info.size)
+                    .unwrap_or_else(|| approximate_size(model)),
+            };
+
+            entries.push(ModelEntry {
+                name: model.name().to_string(),
+                installed: path.exists(),
+                verified: self.is_verified(file, &path),
+                path,
+                size,
+                custom: false,
+            });
+        }
+
+        let mut custom: Vec<PathBuf> = fs::read_dir(self.dir.join(CUSTOM_DIR))?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| path.extension().is_some_and(|e| e == "bin"))
+            .collect();
+        custom.sort();
+
+        for path in custom {
+            let name = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            entries.push(ModelEntry {
+                verified: self.is_verified(&custom_key(&name), &path),
+                size: fs::metadata(&path)?.len(),
+                name,
+                path,
+                installed: true,
+                custom: true,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    // Fetches checksums for the files `CHECKSUMS` does not pin from Hugging
+    // Face.
+    pub fn refresh_checksums(&mut self) -> EngramResult<()> {
+        let body = ureq::get(TREE_API).call()?.body_mut().read_to_string()?;
+        let tree: Value = serde_json::from_str(&body).map_err(|e| {
+            EngramError::ModelError(format!("Invalid model listing: {e}"))
+        })?;
+
+        for entry in tree.as_array().into_iter().flatten() {
+            let path = entry["path"].as_str();
+            let sha256 = entry["lfs"]["oid"].as_str();
+            let size = entry["lfs"]["size"].as_u64();
+            if let (Some(path), Some(sha256), Some(size)) = (path, sha256, size)
+                && pinned_checksum(path).is_none()
+            {
+                self.expected.insert(
+                    path.to_string(),
+                    FileInfo {
+                        sha256: sha256.to_string(),
+                        size,
+                    },
+                );
+            }
+        }
+
+        self.save_manifest()
+    }
+
+    // The path of a checked copy of `model`, downloading it first when it is
+    // missing. `on_progress` gets the bytes downloaded so far and the total
+    // when the server reports it.
+    pub fn install<F>(
+        &mut self,
+        model: TranscriberModel,
+        mut on_progress: F,
+    ) -> EngramResult<PathBuf>
+    where
+        F: FnMut(u64, Option<u64>),
+    {
+        let file = model_file(model);
+        let path = self.dir.join(file);
+
+        if self.expected(file).is_none()
+            && let Err(e) = self.refresh_checksums()
+        {
+            // Offline. A copy we already have is used as long as it looks
+            // like a model, and gets checked once the table can be fetched.
+            if path.exists() {
+                check_magic(&path)?;
+                return Ok(path);
+            }
+            return Err(e);
+        }
+
+        if !path.exists() {
+            download(file, &path, &mut on_progress)?;
+        }
+
+        if let Err(e) = self.verify(file, &path) {
+            // A bad download is not worth resuming.
+            fs::remove_file(&path)?;
+            return Err(e);
+        }
+
+        Ok(path)
+    }
+
+    // The path of an imported model, checked against the hash it had when
+    // it was imported.
+    pub fn custom_model(&mut self, name: &str) -> EngramResult<PathBuf> {
+        let path = self.dir.join(CUSTOM_DIR).join(format!("{name}.bin"));
+        if !path.exists() {
+            return Err(EngramError::ModelError(format!(
+                "No imported model named {name}"
+            )));
+        }
+
+        self.verify(&custom_key(name), &path)?;
+
+        Ok(path)
+    }
+
+    // Copies a GGML file from disk, for machines that cannot download. An
+    // official model is recognized by its checksum and installed in its
+    // place, anything else is kept as a custom model under `name`, or the
+    // file name when there is none.
+    pub fn import(
+        &mut self,
+        source: &Path,
+        name: Option<&str>,
+    ) -> EngramResult<ModelEntry> {
+        check_magic(source)?;
+        let info = hash_file(source)?;
+
+        let official = MODELS.iter().copied().find(|&model| {
+            self.expected(model_file(model)) == Some(info.clone())
+        });
+
+        let (key, name, custom) = match official {
+            Some(model) => (
+                model_file(model).to_string(),
+                model.name().to_string(),
+                false,
+            ),
+            None => {
+                let name = name
+                    .map(String::from)
+                    .or_else(|| {
+                        source
+                            .file_stem()
+                            .map(|s| s.to_string_lossy().to_string())
+                    })
+                    .unwrap_or_else(|| "custom".into());
+                if name.is_empty()
+                    || name.contains(['/', '\\'])
+                    || name.parse::<TranscriberModel>().is_ok()
+                {
+                    return Err(EngramError::ModelError(format!(
+                        "Invalid model name: {name}"
+                    )));
+                }
+                (custom_key(&name), name, true)
+            }
+        };
+
+        let path = self.dir.join(&key);
+        let tmp = path.with_extension("tmp");
+        fs::copy(source, &tmp)?;
+        fs::rename(&tmp, &path)?;
+
+        if custom {
+            self.expected.insert(key.clone(), info.clone());
+        }
+        self.verified
+            .insert(key, (info.clone(), modified_time(&path)?));
+        self.save_manifest()?;
+
+        Ok(ModelEntry {
+            name,
+            path,
+            size: info.size,
+            installed: true,
+            verified: true,
+            custom,
+        })
+    }
+
+    // What `key` should hash to, pinned checksums first.
+    fn expected(&self, key: &str) -> Option<FileInfo> {
+        pinned_checksum(key).or_else(|| self.expected.get(key).cloned())
+    }
+
+    fn is_verified(&self, key: &str, path: &Path) -> bool {
+        let Some((info, modified)) = self.verified.get(key) else {
+            return false;
+        };
+
+        fs::metadata(path).is_ok_and(|m| m.len() == info.size)
+            && modified_time(path).is_ok_and(|m| m == *modified)
+            && self.expected(key).is_none_or(|expected| expected == *info)
+    }
+
+    // Hashes the file unless it passed already and has not changed since.
+    fn verify(&mut self, key: &str, path: &Path) -> EngramResult<()> {
+        if self.is_verified(key, path) {
+            return Ok(());
+        }
+
+        let Some(expected) = self.expected(key) else {
+            return Err(EngramError::ModelError(format!(
+                "No checksum known for {key}, connect once or import it"
+            )));
+        };
+
+        let size = fs::metadata(path)?.len();
+        if size != expected.size {
+            return Err(EngramError::ModelError(format!(
+                "{} is {size} bytes instead of {}, it is incomplete or the \
+                 wrong model",
+                path.display(),
+                expected.size
+            )));
+        }
+
+        let info = hash_file(path)?;
+        if info != expected {
+            return Err(EngramError::ModelError(format!(
+                "Checksum mismatch for {}",
+                path.display()
+            )));
+        }
+
+        self.verified
+            .insert(key.to_string(), (info, modified_time(path)?));
+        self.save_manifest()
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.dir.join("manifest.json")
+    }
+
+    fn load_manifest(&mut self) -> EngramResult<()> {
+        let Ok(contents) = fs::read_to_string(self.manifest_path()) else {
+            return Ok(());
+        };
+        // A damaged manifest only means everything gets checked again.
+        let Ok(manifest) = serde_json::from_str::<Value>(&contents) else {
+            return Ok(());
+        };
+
+        let files = |value: &Value| {
+            value
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter_map(|(key, entry)| {
+                    let info = FileInfo {
+                        sha256: entry["sha256"].as_str()?.to_string(),
+                        size: entry["size"].as_u64()?,
+                    };
+                    Some((key.clone(), info, entry["modified"].as_u64()))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for (key, info, _) in files(&manifest["expected"]) {
+            self.expected.insert(key, info);
+        }
+        for (key, info, modified) in files(&manifest["verified"]) {
+            self.verified.insert(key, (info, modified.unwrap_or(0)));
+        }
+
+        Ok(())
+    }
+
+    fn save_manifest(&self) -> EngramResult<()> {
+        let expected: serde_json::Map<String, Value> = self
+            .expected
+            .iter()
+            .map(|(key, info)| {
+                (
+                    key.clone(),
+                    json!({ "sha256": info.sha256, "size": info.size }),
+                )
+            })
+            .collect();
+        let verified: serde_json::Map<String, Value> = self
+            .verified
+            .iter()
+            .map(|(key, (info, modified))| {
+                (
+                    key.clone(),
+                    json!({
+                        "sha256": info.sha256,
+                        "size": info.size,
+                        "modified": modified,
+                    }),
+                )
+            })
+            .collect();
+
+        let manifest = json!({ "expected": expected, "verified": verified });
+        let tmp = self.manifest_path().with_extension("tmp");
+        fs::write(&tmp, manifest.to_string())?;
+        fs::rename(&tmp, self.manifest_path())?;
+
+        Ok(())
+    }
+
+    // Models used to be stored as `{model as u8}.bin` in the engram
+    // directory. They are moved over and checked like any other download.
+    fn migrate_legacy_files(&self) -> EngramResult<()> {
+        let root = get_engram_dir()?;
+
+        for &model in MODELS {
+            let legacy = root.join(format!("{}.bin", model as u8));
+            let path = self.dir.join(model_file(model));
+            if legacy.exists() && !path.exists() {
+                fs::rename(&legacy, &path)?;
+            }
+            let _ = fs::remove_file(legacy.with_extension("tmp"));
+        }
+
+        Ok(())
+    }
+}
+
+// Downloads into `<path>.tmp` and moves it in place once complete. A partial
+// `.tmp` left by an earlier attempt is resumed with a range request.
+fn download(
+    file: &str,
+    path: &Path,
+    on_progress: &mut dyn FnMut(u64, Option<u64>),
+) -> EngramResult<()> {
+    let url = format!("{REPO}/resolve/main/{file}?download=true");
+    let tmp = path.with_extension("tmp");
+    let mut offset = fs::metadata(&tmp).map(|m| m.len()).unwrap_or(0);
+
+    let mut request = ureq::get(&url);
+    if offset > 0 {
+        request = request.header("Range", format!("bytes={offset}-"));
+    }
+
+    let response = match request.call() {
+        Ok(response) => response,
+        // Nothing left to fetch, the partial file is complete.
+        Err(ureq::Error::StatusCode(416)) if offset > 0 => {
+            fs::rename(&tmp, path)?;
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    // A server that ignores the range sends the whole file again.
+    if response.status().as_u16() != 206 {
+        offset = 0;
+    }
+    let total = response
+        .headers()
+        .get("content-length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(|length| length + offset);
+
+    let mut out = if offset > 0 {
+        fs::OpenOptions::new().append(true).open(&tmp)?
+    } else {
+        fs::File::create(&tmp)?
+    };
+
+    let mut reader = response.into_body().into_reader();
+    let mut buffer = vec![0; 1 << 16];
+    let mut done = offset;
+    on_progress(done, total);
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        out.write_all(&buffer[..read])?;
+        done += read as u64;
+        on_progress(done, total);
+    }
+
+    out.sync_all()?;
+    drop(out);
+    fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+fn pinned_checksum(file: &str) -> Option<FileInfo> {
+    CHECKSUMS.iter().find(|(name, _, _)| *name == file).map(
+        |&(_, size, sha256)| FileInfo {
+            sha256: sha256.to_string(),
+            size,
+        },
+    )
+}
+
+fn custom_key(name: &str) -> String {
+    format!("{CUSTOM_DIR}/{name}.bin")
+}
+
+fn check_magic(path: &Path) -> EngramResult<()> {
+    let mut magic = [0; 4];
+    fs::File::open(path)?.read_exact(&mut magic)?;
+
+    if magic != GGML_MAGIC {
+        return Err(EngramError::ModelError(format!(
+            "{} is not a GGML model",
+            path.display()
+        )));
+    }
+
+    Ok(())
+}
+
+fn hash_file(path: &Path) -> EngramResult<FileInfo> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 20];
+    let mut size = 0;
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+
+    Ok(FileInfo {
+        sha256: format!("{:x}", hasher.finalize()),
+        size,
+    })
+}
+
+fn modified_time(path: &Path) -> EngramResult<u64> {
+    Ok(fs::metadata(path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0))
+}
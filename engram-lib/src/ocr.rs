@@ -0,0 +1,251 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::cancel::CancellationToken;
+use crate::errors::EngramError;
+use crate::{EngramResult, subtitles};
+
+// Images per Tesseract run, it pays a startup cost for every run.
+const BATCH_SIZE: usize = 100;
+
+// White space around the text, Tesseract struggles with glyphs that touch
+// the edge of the image.
+const MARGIN: u32 = 10;
+
+// 8-bit grayscale image.
+#[derive(Debug, Clone)]
+pub struct SubtitleImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl SubtitleImage {
+    pub fn blank(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![255; width as usize * height as usize],
+        }
+    }
+
+    // Keeps the darker of the current and the new value.
+    pub fn darken(&mut self, x: u32, y: u32, value: u8) {
+        if x < self.width && y < self.height {
+            let pixel = &mut self.pixels[(y * self.width + x) as usize];
+            *pixel = (*pixel).min(value);
+        }
+    }
+
+    // Binary PGM with a white margin.
+    fn to_pgm(&self) -> Vec<u8> {
+        let width = self.width + 2 * MARGIN;
+        let height = self.height + 2 * MARGIN;
+
+        let mut pgm = format!("P5\n{width} {height}\n255\n").into_bytes();
+        let blank = vec![255; width as usize];
+        for _ in 0..MARGIN {
+            pgm.extend(&blank);
+        }
+        for row in self.pixels.chunks(self.width.max(1) as usize) {
+            pgm.extend(&blank[..MARGIN as usize]);
+            pgm.extend(row);
+            pgm.extend(&blank[..MARGIN as usize]);
+        }
+        for _ in 0..MARGIN {
+            pgm.extend(&blank);
+        }
+
+        pgm
+    }
+}
+
+// Text recognition with the Tesseract command line tool, when it is
+// installed.
+#[derive(Debug, Clone)]
+pub struct Ocr {
+    // Trained languages Tesseract has, such as "eng" or "chi_sim".
+    languages: Vec<String>,
+}
+
+impl Ocr {
+    pub fn detect() -> Option<Self> {
+        let output = Command::new("tesseract")
+            .arg("--list-langs")
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+
+        // The first line names the tessdata directory.
+        let languages = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .skip(1)
+            .map(str::trim)
+            .filter(|lang| !lang.is_empty() && *lang != "osd")
+            .map(String::from)
+            .collect();
+
+        Some(Self { languages })
+    }
+
+    // The installed model for an ISO 639-1 code, English when there is none.
+    fn model(&self, language: Option<&str>) -> Option<&str> {
+        let codes: &[&str] = match language {
+            Some("zh") => &["chi_sim", "chi_tra"],
+            Some(code) => subtitles::language_codes(code),
+            None => &[],
+        };
+
+        codes
+            .iter()
+            .chain(&["eng"])
+            .find_map(|code| self.languages.iter().find(|l| l == code))
+            .map(String::as_str)
+    }
+
+    pub fn job(&self, language: Option<&str>) -> EngramResult<OcrJob> {
+        static JOBS: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "engram-ocr-{}-{}",
+            std::process::id(),
+            JOBS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+
+        Ok(OcrJob {
+            dir,
+            model: self.model(language).map(String::from),
+            language: language.map(String::from),
+            images: Vec::new(),
+        })
+    }
+}
+
+// The images of one track. They are written to a scratch directory as they
+// come in rather than kept in memory, a film has over a thousand of them.
+pub struct OcrJob {
+    dir: PathBuf,
+    model: Option<String>,
+    language: Option<String>,
+    images: Vec<(i64, i64, PathBuf)>,
+}
+
+impl OcrJob {
+    pub fn add(
+        &mut self,
+        start: i64,
+        end: i64,
+        image: &SubtitleImage,
+    ) -> EngramResult<()> {
+        let path = self.dir.join(format!("{:05}.pgm", self.images.len()));
+        fs::write(&path, image.to_pgm())?;
+        self.images.push((start, end, path));
+
+        Ok(())
+    }
+
+    pub fn recognize(
+        &self,
+        cancel: &CancellationToken,
+    ) -> EngramResult<Vec<subtitles::Segment>> {
+        let mut segments = Vec::new();
+
+        for (n, batch) in self.images.chunks(BATCH_SIZE).enumerate() {
+            cancel.check()?;
+
+            let texts = self.run(n, batch)?;
+            for ((start, end, _), text) in batch.iter().zip(texts) {
+                let text = subtitles::normalize_text(&fix_misreads(&text));
+                if text.is_empty() {
+                    continue;
+                }
+
+                segments.push(subtitles::Segment {
+                    start: *start,
+                    end: *end,
+                    text,
+                    language: self.language.clone(),
+                    ..Default::default()
+                });
+            }
+        }
+
+        Ok(segments)
+    }
+
+    // Tesseract reads a list of images as the pages of one document and
+    // separates the text of each page with a form feed.
+    fn run(
+        &self,
+        n: usize,
+        batch: &[(i64, i64, PathBuf)],
+    ) -> EngramResult<Vec<String>> {
+        let list = self.dir.join(format!("batch-{n}.txt"));
+        let paths = batch
+            .iter()
+            .map(|(_, _, path)| path.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(&list, paths)?;
+
+        let mut command = Command::new("tesseract");
+        // A single block of text, which is what a subtitle is.
+        command.arg(&list).arg("stdout").args(["--psm", "6"]);
+        if let Some(model) = &self.model {
+            command.args(["-l", model]);
+        }
+
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(EngramError::OcrError(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+
+        let texts: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .split('\u{c}')
+            .take(batch.len())
+            .map(String::from)
+            .collect();
+        if texts.len() < batch.len() {
+            return Err(EngramError::OcrError(format!(
+                "Expected text for {} images, got {}",
+                batch.len(),
+                texts.len()
+            )));
+        }
+
+        Ok(texts)
+    }
+}
+
+impl Drop for OcrJob {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Subtitle fonts make Tesseract read a capital I as a pipe.
+fn fix_misreads(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            line.split(' ')
+                .map(|word| match word {
+                    "|" => "I",
+                    "|'m" => "I'm",
+                    "|'ll" => "I'll",
+                    "|'ve" => "I've",
+                    "|'d" => "I'd",
+                    word => word,
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -1,25 +1,40 @@
 use std::fs;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use tantivy::{
-    Index, IndexReader, IndexWriter, ReloadPolicy, collector::TopDocs, doc,
-    query::QueryParser, schema::*,
+    Index, IndexReader, IndexWriter, ReloadPolicy,
+    collector::TopDocs,
+    doc,
+    query::{BooleanQuery, Occur, Query, QueryParser, TermQuery},
+    schema::*,
 };
 
 use crate::index::MediaFile;
-use crate::subtitles;
+use crate::subtitles::{
+    self, ParseWarning,
+    words::{words_from_json, words_to_json},
+};
 use crate::{EngramResult, errors::EngramError};
 
 pub const DEFAULT_WRITER_BYTES: usize = 50_000_000;
 
+// Cues further apart than this are not searched as one phrase.
+const MAX_WINDOW_GAP_MS: i64 = 2000;
+
 pub struct SearchIndex {
     index: Index,
-    writer: IndexWriter,
+    // None when opened read-only.
+    writer: Option<IndexWriter>,
     reader: IndexReader,
     file_field: Field,
     text_field: Field,
+    raw_field: Field,
+    language_field: Field,
     start_field: Field,
     end_field: Field,
     segment_id_field: Field,
+    span_field: Field,
+    words_field: Field,
 }
 
 #[derive(Debug, Clone)]
@@ -27,57 +42,84 @@ pub struct SearchResult {
     pub file: PathBuf,
     pub segment: subtitles::Segment,
     pub score: f32,
+    // Start and end of the words that matched, when the segment has word
+    // timings.
+    pub matched: Option<(i64, i64)>,
 }
 
-impl SearchIndex {
-    pub fn create(path: &Path) -> EngramResult<Self> {
-        fs::create_dir_all(path)?;
+impl SearchResult {
+    // What to cut: the matched words when we know when they are said, the
+    // whole segment otherwise.
+    pub fn span(&self) -> (i64, i64) {
+        self.matched
+            .unwrap_or((self.segment.start, self.segment.end))
+    }
 
-        let mut schema_builder = Schema::builder();
+    // The segment cut down to `span`.
+    pub fn matched_segment(&self) -> subtitles::Segment {
+        let (start, end) = self.span();
+        self.segment.narrow(start, end)
+    }
+}
 
-        let path_field = schema_builder.add_text_field("file", STRING | STORED);
-        let text_field = schema_builder.add_text_field("text", TEXT | STORED);
-        let start_field =
-            schema_builder.add_i64_field("start", INDEXED | STORED);
-        let end_field = schema_builder.add_i64_field("end", INDEXED | STORED);
-        let segment_id_field =
-            schema_builder.add_u64_field("id", INDEXED | STORED);
+fn build_schema() -> Schema {
+    let mut schema_builder = Schema::builder();
 
-        let schema = schema_builder.build();
+    schema_builder.add_text_field("file", STRING | STORED);
+    schema_builder.add_text_field("text", TEXT | STORED);
+    schema_builder.add_text_field("raw", STORED);
+    schema_builder.add_text_field("language", STRING | STORED);
+    schema_builder.add_i64_field("start", INDEXED | STORED);
+    schema_builder.add_i64_field("end", INDEXED | STORED);
+    schema_builder.add_u64_field("id", INDEXED | STORED);
+    schema_builder.add_u64_field("span", STORED);
+    schema_builder.add_text_field("words", STORED);
 
-        let index = Index::create_in_dir(path, schema.clone())?;
+    schema_builder.build()
+}
 
-        let writer = index.writer(DEFAULT_WRITER_BYTES)?;
+impl SearchIndex {
+    pub fn create(path: &Path) -> EngramResult<Self> {
+        fs::create_dir_all(path)?;
 
-        let reader = index
-            .reader_builder()
-            .reload_policy(ReloadPolicy::OnCommitWithDelay)
-            .try_into()?;
+        let index = Index::create_in_dir(path, build_schema())?;
 
-        Ok(Self {
-            index,
-            writer,
-            reader,
-            file_field: path_field,
-            text_field,
-            start_field,
-            end_field,
-            segment_id_field,
-        })
+        Self::from_index(index, true)
     }
 
     pub fn open(index_path: &Path) -> EngramResult<Self> {
-        let index = Index::open_in_dir(index_path)?;
+        Self::from_index(Index::open_in_dir(index_path)?, true)
+    }
+
+    // For searching while an indexer is writing. Takes no writer lock and
+    // picks up what the indexer commits.
+    pub fn open_read_only(index_path: &Path) -> EngramResult<Self> {
+        if !index_path.join("meta.json").exists() {
+            return Err(EngramError::SearchError(
+                "Nothing has been indexed yet".into(),
+            ));
+        }
+
+        Self::from_index(Index::open_in_dir(index_path)?, false)
+    }
 
+    fn from_index(index: Index, writable: bool) -> EngramResult<Self> {
         let schema = index.schema();
 
         let path_field = schema.get_field("file")?;
         let text_field = schema.get_field("text")?;
+        let raw_field = schema.get_field("raw")?;
+        let language_field = schema.get_field("language")?;
         let start_field = schema.get_field("start")?;
         let end_field = schema.get_field("end")?;
         let segment_id_field = schema.get_field("id")?;
+        let span_field = schema.get_field("span")?;
+        let words_field = schema.get_field("words")?;
 
-        let writer = index.writer(DEFAULT_WRITER_BYTES)?;
+        let writer = match writable {
+            true => Some(index.writer(DEFAULT_WRITER_BYTES)?),
+            false => None,
+        };
 
         let reader = index
             .reader_builder()
@@ -90,76 +132,225 @@ impl SearchIndex {
             reader,
             file_field: path_field,
             text_field,
+            raw_field,
+            language_field,
             start_field,
             end_field,
             segment_id_field,
+            span_field,
+            words_field,
         })
     }
 
-    pub fn add_media_file(&mut self, file: &MediaFile) -> EngramResult<()> {
+    // An index written with an older schema is thrown away. The segments are
+    // all in the database, see `Indexer::rebuild_index`.
+    pub fn open_or_create(path: &Path) -> EngramResult<Self> {
+        if !path.join("meta.json").exists() {
+            return Self::create(path);
+        }
+
+        let index = Index::open_in_dir(path)?;
+        let schema = index.schema();
+        let current = build_schema()
+            .fields()
+            .all(|(_, entry)| schema.get_field(entry.name()).is_ok());
+        if current {
+            return Self::from_index(index, true);
+        }
+
+        drop(index);
+        fs::remove_dir_all(path)?;
+        Self::create(path)
+    }
+
+    pub fn num_docs(&self) -> u64 {
+        self.reader.searcher().num_docs()
+    }
+
+    // Returns the sidecars that needed repairs while parsing, with what was
+    // repaired.
+    pub fn add_media_file(
+        &mut self,
+        file: &MediaFile,
+    ) -> EngramResult<Vec<(PathBuf, Vec<ParseWarning>)>> {
         if self.has_media_file(&file.media)? {
-            return Ok(());
+            return Ok(Vec::new());
         }
 
         self.insert_media_file(file)
     }
 
-    fn insert_media_file(&mut self, file: &MediaFile) -> EngramResult<()> {
-        let subtitle_path = file.subtitles.as_ref().ok_or_else(|| {
-            EngramError::SearchError(
+    fn insert_media_file(
+        &mut self,
+        file: &MediaFile,
+    ) -> EngramResult<Vec<(PathBuf, Vec<ParseWarning>)>> {
+        let sidecars = file.preferred_subtitles();
+        if sidecars.is_empty() {
+            return Err(EngramError::SearchError(
                 "MediaFile has no subtitles associated.".into(),
-            )
-        })?;
+            ));
+        }
 
-        let segments = subtitles::parse_srt_file(subtitle_path)?;
+        let mut segments = Vec::new();
+        let mut warnings = Vec::new();
+        for sidecar in sidecars {
+            let (parsed, repaired) =
+                subtitles::parse_subtitle_file(&sidecar.path, None)?;
+            if !repaired.is_empty() {
+                warnings.push((sidecar.path.clone(), repaired));
+            }
+            segments.extend(parsed.into_iter().map(|seg| subtitles::Segment {
+                language: sidecar.language.clone(),
+                ..seg
+            }));
+        }
 
         if segments.is_empty() {
             return Err(EngramError::SubtitleParseError(format!(
-                "No segments parsed from {:?}",
-                subtitle_path
+                "No segments parsed for {:?}",
+                file.media
             )));
         }
+        segments.sort_by_key(|seg| seg.start);
 
-        let media_path = file.media.to_string_lossy().to_string();
+        self.insert_segments(&file.media, &segments)?;
 
-        for (idx, segment) in segments.iter().enumerate() {
-            let doc = doc!(
-                self.file_field => media_path.clone(),
-                self.text_field => segment.text.clone(),
-                self.start_field => segment.start,
-                self.end_field => segment.end,
-                self.segment_id_field => idx as u64,
-            );
+        Ok(warnings)
+    }
+
+    fn insert_segments(
+        &mut self,
+        media: &Path,
+        segments: &[subtitles::Segment],
+    ) -> EngramResult<()> {
+        let media_path = media.to_string_lossy().to_string();
+
+        // Only one track is kept per language, so this splits the cues back
+        // into their tracks.
+        let mut tracks: Vec<Vec<&subtitles::Segment>> = Vec::new();
+        for segment in segments {
+            match tracks
+                .iter_mut()
+                .find(|track| track[0].language == segment.language)
+            {
+                Some(track) => track.push(segment),
+                None => tracks.push(vec![segment]),
+            }
+        }
 
-            self.writer.add_document(doc)?;
+        // Cues are numbered track after track, so both cues of a pair have
+        // consecutive ids.
+        let mut first_idx = 0;
+        for track in &tracks {
+            for (idx, segment) in track.iter().enumerate() {
+                self.add_segment(&media_path, first_idx + idx, 1, segment)?;
+            }
+
+            // Pairs of cues as well, so a phrase split across two of them
+            // still matches. Only cues that follow each other closely are
+            // paired.
+            for (idx, pair) in track.windows(2).enumerate() {
+                let [first, second] = pair else { continue };
+                if second.start - first.end > MAX_WINDOW_GAP_MS {
+                    continue;
+                }
+
+                let raw =
+                    (first.raw.is_some() || second.raw.is_some()).then(|| {
+                        format!("{}\n{}", first.raw_text(), second.raw_text())
+                    });
+                let window = subtitles::Segment {
+                    start: first.start,
+                    end: second.end.max(first.end),
+                    text: format!("{}\n{}", first.text, second.text),
+                    raw,
+                    language: first.language.clone(),
+                    words: [first.words.as_slice(), &second.words].concat(),
+                    ..Default::default()
+                };
+                self.add_segment(&media_path, first_idx + idx, 2, &window)?;
+            }
+
+            first_idx += track.len();
         }
 
         Ok(())
     }
 
-    pub fn remove_media_file(&mut self, path: &Path) {
-        let media_path_str = path.to_string_lossy().to_string();
+    // `idx` is the position of the first cue the document covers and `span`
+    // how many it covers.
+    fn add_segment(
+        &mut self,
+        media_path: &str,
+        idx: usize,
+        span: u64,
+        segment: &subtitles::Segment,
+    ) -> EngramResult<()> {
+        let mut doc = doc!(
+            self.file_field => media_path,
+            self.text_field => segment.text.clone(),
+            self.start_field => segment.start,
+            self.end_field => segment.end,
+            self.segment_id_field => idx as u64,
+            self.span_field => span,
+        );
+        if let Some(raw) = &segment.raw {
+            doc.add_text(self.raw_field, raw);
+        }
+        if let Some(language) = &segment.language {
+            doc.add_text(self.language_field, language);
+        }
+        if !segment.words.is_empty() {
+            doc.add_text(
+                self.words_field,
+                words_to_json(&segment.words).to_string(),
+            );
+        }
 
-        self.writer.delete_term(tantivy::Term::from_field_text(
-            self.file_field,
-            &media_path_str,
-        ));
+        self.writer()?.add_document(doc)?;
+
+        Ok(())
     }
 
-    pub fn update_media_file(&mut self, file: &MediaFile) -> EngramResult<()> {
-        self.remove_media_file(&file.media);
-        self.insert_media_file(file)?;
+    pub fn remove_media_file(&mut self, path: &Path) -> EngramResult<()> {
+        let media_path_str = path.to_string_lossy().to_string();
+        let term =
+            tantivy::Term::from_field_text(self.file_field, &media_path_str);
+        self.writer()?.delete_term(term);
 
         Ok(())
     }
 
+    pub fn update_media_file(
+        &mut self,
+        file: &MediaFile,
+    ) -> EngramResult<Vec<(PathBuf, Vec<ParseWarning>)>> {
+        self.remove_media_file(&file.media)?;
+        self.insert_media_file(file)
+    }
+
+    pub fn update_segments(
+        &mut self,
+        media: &Path,
+        segments: &[subtitles::Segment],
+    ) -> EngramResult<()> {
+        self.remove_media_file(media)?;
+        self.insert_segments(media, segments)
+    }
+
     pub fn commit(&mut self) -> EngramResult<()> {
-        self.writer.commit()?;
+        self.writer()?.commit()?;
         self.reader.reload()?;
 
         Ok(())
     }
 
+    fn writer(&mut self) -> EngramResult<&mut IndexWriter> {
+        self.writer.as_mut().ok_or_else(|| {
+            EngramError::SearchError("Search index is open read-only".into())
+        })
+    }
+
     pub fn has_media_file(&self, path: &Path) -> EngramResult<bool> {
         let searcher = self.reader.searcher();
         let media_path = path.to_string_lossy().to_string();
@@ -179,8 +370,20 @@ impl SearchIndex {
         &self,
         query: &str,
         limit: usize,
+    ) -> EngramResult<Vec<SearchResult>> {
+        self.search_language(query, limit, None)
+    }
+
+    // Only matches segments from tracks in `language`, given as a code or an
+    // English name. Untagged tracks never match.
+    pub fn search_language(
+        &self,
+        query: &str,
+        limit: usize,
+        language: Option<&str>,
     ) -> EngramResult<Vec<SearchResult>> {
         let searcher = self.reader.searcher();
+        let text = query;
 
         let query_parser =
             QueryParser::for_index(&self.index, vec![self.text_field]);
@@ -199,63 +402,206 @@ impl SearchIndex {
                 EngramError::SearchError(format!("Failed to parse query: {e}"))
             })?;
 
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(limit))?;
+        let query: Box<dyn Query> = match language {
+            Some(language) => {
+                let code = subtitles::normalize_language(language)
+                    .map(String::from)
+                    .unwrap_or_else(|| language.trim().to_lowercase());
+                let term = TermQuery::new(
+                    Term::from_field_text(self.language_field, &code),
+                    IndexRecordOption::Basic,
+                );
+                Box::new(BooleanQuery::new(vec![
+                    (Occur::Must, query),
+                    (Occur::Must, Box::new(term)),
+                ]))
+            }
+            None => query,
+        };
 
-        let mut results = Vec::new();
+        // A match inside one cue also matches the pairs around it, so pages
+        // are fetched until `limit` hits are left once those are dropped.
+        let page = limit.max(1) * 3;
+        let mut hits = Vec::new();
+        let mut offset = 0;
+
+        loop {
+            let top_docs = searcher.search(
+                &query,
+                &TopDocs::with_limit(page).and_offset(offset),
+            )?;
+            let exhausted = top_docs.len() < page;
+
+            for (score, doc_address) in top_docs {
+                let hit = self.read_hit(&searcher, score, doc_address, text)?;
+                hits.push(hit);
+            }
+
+            let results = dedup_hits(&hits, limit);
+            if exhausted || results.len() >= limit {
+                return Ok(results);
+            }
+            offset += page;
+        }
+    }
 
-        for (score, doc_address) in top_docs {
-            let retrieved_doc =
-                searcher.doc::<tantivy::TantivyDocument>(doc_address)?;
+    fn read_hit(
+        &self,
+        searcher: &tantivy::Searcher,
+        score: f32,
+        doc_address: tantivy::DocAddress,
+        text: &str,
+    ) -> EngramResult<(Range<u64>, SearchResult)> {
+        let retrieved_doc =
+            searcher.doc::<tantivy::TantivyDocument>(doc_address)?;
+
+        let file = retrieved_doc
+            .get_first(self.file_field)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| {
+                EngramError::SearchError("Missing file field in result".into())
+            })?;
 
-            let file = retrieved_doc
-                .get_first(self.file_field)
-                .and_then(|v| v.as_str())
-                .ok_or_else(|| {
-                    EngramError::SearchError(
-                        "Missing file field in result".into(),
-                    )
-                })?;
-
-            let text = retrieved_doc
-                .get_first(self.text_field)
+        let segment_text = retrieved_doc
+            .get_first(self.text_field)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| {
+                EngramError::SearchError("Missing text field in result".into())
+            })?;
+
+        let start = retrieved_doc
+            .get_first(self.start_field)
+            .and_then(|v| v.as_i64())
+            .ok_or_else(|| {
+                EngramError::SearchError("Missing start field in result".into())
+            })?;
+
+        let end = retrieved_doc
+            .get_first(self.end_field)
+            .and_then(|v| v.as_i64())
+            .ok_or_else(|| {
+                EngramError::SearchError("Missing end field in result".into())
+            })?;
+
+        let optional = |field| {
+            retrieved_doc
+                .get_first(field)
                 .and_then(|v| v.as_str())
-                .ok_or_else(|| {
-                    EngramError::SearchError(
-                        "Missing text field in result".into(),
-                    )
-                })?;
-
-            let start = retrieved_doc
-                .get_first(self.start_field)
-                .and_then(|v| v.as_i64())
-                .ok_or_else(|| {
-                    EngramError::SearchError(
-                        "Missing start field in result".into(),
-                    )
-                })?;
-
-            let end = retrieved_doc
-                .get_first(self.end_field)
-                .and_then(|v| v.as_i64())
-                .ok_or_else(|| {
-                    EngramError::SearchError(
-                        "Missing end field in result".into(),
-                    )
-                })?;
-
-            let segment = subtitles::Segment {
-                start,
-                end,
-                text: text.into(),
-            };
-
-            results.push(SearchResult {
+                .map(String::from)
+        };
+
+        let words = optional(self.words_field)
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .map(|value| words_from_json(&value))
+            .unwrap_or_default();
+        let matched = subtitles::match_span(&words, text);
+
+        let segment = subtitles::Segment {
+            start,
+            end,
+            text: segment_text.into(),
+            raw: optional(self.raw_field),
+            language: optional(self.language_field),
+            words,
+            ..Default::default()
+        };
+
+        let id = retrieved_doc
+            .get_first(self.segment_id_field)
+            .and_then(|v| v.as_u64())
+            .unwrap_or_default();
+        let span = retrieved_doc
+            .get_first(self.span_field)
+            .and_then(|v| v.as_u64())
+            .unwrap_or(1);
+
+        Ok((
+            id..id + span,
+            SearchResult {
                 file: PathBuf::from(file),
                 segment,
                 score,
-            });
+                matched,
+            },
+        ))
+    }
+}
+
+// Keeps one hit per match. Where hits in a file overlap the one covering the
+// fewest cues wins, so a pair only shows up when neither of its cues matched
+// on its own.
+fn dedup_hits(
+    hits: &[(Range<u64>, SearchResult)],
+    limit: usize,
+) -> Vec<SearchResult> {
+    let mut hits: Vec<&(Range<u64>, SearchResult)> = hits.iter().collect();
+    hits.sort_by(|(a, x), (b, y)| {
+        (a.end - a.start)
+            .cmp(&(b.end - b.start))
+            .then(y.score.total_cmp(&x.score))
+    });
+
+    let mut kept: Vec<&(Range<u64>, SearchResult)> = Vec::new();
+    for hit in hits {
+        let (cues, result) = hit;
+        let overlaps = kept.iter().any(|(other, kept)| {
+            kept.file == result.file
+                && cues.start < other.end
+                && other.start < cues.end
+        });
+        if !overlaps {
+            kept.push(hit);
+        }
+    }
+
+    let mut results: Vec<SearchResult> =
+        kept.into_iter().map(|(_, hit)| hit.clone()).collect();
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+    results.truncate(limit);
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cue(start: i64, text: &str) -> subtitles::Segment {
+        subtitles::Segment {
+            start,
+            end: start + 1000,
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn returns_distinct_cues_up_to_the_limit() {
+        let dir = std::env::temp_dir()
+            .join(format!("engram-search-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut index = SearchIndex::create(&dir).unwrap();
+
+        // Every cue matches, so each one comes with two pairs around it.
+        let segments: Vec<subtitles::Segment> = (0..40)
+            .map(|i| cue(i * 1500, &"hello hello ".repeat(1 + i as usize % 3)))
+            .collect();
+        index
+            .update_segments(Path::new("/media/movie.mkv"), &segments)
+            .unwrap();
+        index.commit().unwrap();
+
+        for limit in [1, 5, 20, 40, 60] {
+            let results = index.search("hello", limit).unwrap();
+            assert_eq!(results.len(), limit.min(40), "{limit}");
+            // No cue shows up twice.
+            for (i, a) in results.iter().enumerate() {
+                for b in &results[i + 1..] {
+                    let (a, b) = (&a.segment, &b.segment);
+                    assert!(a.end <= b.start || b.end <= a.start, "{limit}");
+                }
+            }
         }
 
-        Ok(results)
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
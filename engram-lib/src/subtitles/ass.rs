@@ -0,0 +1,436 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use super::{
+    ParseWarning, SNIFF_LINES, Segment, SubtitleFormat, read_subtitle_file,
+};
+use crate::{EngramResult, errors::EngramError};
+
+// Field order of an ASS event when the file has no Format line.
+pub const ASS_DEFAULT_FORMAT: &[&str] = &[
+    "layer", "start", "end", "style", "name", "marginl", "marginr", "marginv",
+    "effect", "text",
+];
+
+// Field order of the events FFmpeg decodes from embedded ASS tracks.
+pub const ASS_EMBEDDED_FORMAT: &[&str] = &[
+    "readorder",
+    "layer",
+    "style",
+    "name",
+    "marginl",
+    "marginr",
+    "marginv",
+    "effect",
+    "text",
+];
+
+pub struct Ass;
+
+impl SubtitleFormat for Ass {
+    fn name(&self) -> &'static str {
+        "Advanced SubStation Alpha"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["ass", "ssa"]
+    }
+
+    fn detect(&self, content: &str) -> bool {
+        content.lines().take(SNIFF_LINES).any(|line| {
+            let line = line.trim_start_matches('\u{feff}').trim();
+            line.eq_ignore_ascii_case("[script info]")
+                || line.eq_ignore_ascii_case("[events]")
+        })
+    }
+
+    fn parse(&self, content: &str) -> EngramResult<Vec<Segment>> {
+        parse_ass(content, &AssOptions::default())
+    }
+
+    fn parse_with_warnings(
+        &self,
+        content: &str,
+    ) -> EngramResult<(Vec<Segment>, Vec<ParseWarning>)> {
+        parse_ass_lenient(content, &AssOptions::default())
+    }
+
+    fn serialize(&self, segments: &[Segment]) -> String {
+        generate_ass(segments)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AssOptions {
+    // Style names to drop, compared case-insensitively.
+    pub exclude_styles: Vec<String>,
+    // Typesetting for on-screen text such as signs and titles.
+    pub skip_signs: bool,
+    // Song lyrics, which are usually timed per syllable.
+    pub skip_karaoke: bool,
+}
+
+impl Default for AssOptions {
+    fn default() -> Self {
+        Self {
+            exclude_styles: Vec::new(),
+            skip_signs: true,
+            skip_karaoke: true,
+        }
+    }
+}
+
+impl AssOptions {
+    fn excludes(&self, style: &str, raw_text: &str) -> bool {
+        let lower = style.to_lowercase();
+
+        if self
+            .exclude_styles
+            .iter()
+            .any(|s| s.eq_ignore_ascii_case(style))
+        {
+            return true;
+        }
+
+        if self.skip_signs && lower.contains("sign") {
+            return true;
+        }
+
+        self.skip_karaoke
+            && (lower.contains("kara")
+                || lower.contains("song")
+                || has_override(raw_text, &["\\k", "\\K"]))
+    }
+}
+
+pub fn parse_ass_file(
+    path: &Path,
+    options: &AssOptions,
+) -> EngramResult<Vec<Segment>> {
+    parse_ass(&read_subtitle_file(path, None)?, options)
+}
+
+pub fn parse_ass(
+    content: &str,
+    options: &AssOptions,
+) -> EngramResult<Vec<Segment>> {
+    parse_ass_lenient(content, options).map(|(segments, _)| segments)
+}
+
+// Events that cannot be parsed are skipped with a warning. Only a file
+// without an [Events] section is an error.
+pub fn parse_ass_lenient(
+    content: &str,
+    options: &AssOptions,
+) -> EngramResult<(Vec<Segment>, Vec<ParseWarning>)> {
+    let mut in_events = false;
+    let mut has_events = false;
+    let mut format: Vec<String> =
+        ASS_DEFAULT_FORMAT.iter().map(|f| f.to_string()).collect();
+    let mut segments = Vec::new();
+    let mut warnings = Vec::new();
+
+    let lines = content.trim_start_matches('\u{feff}').lines();
+    for (number, line) in lines.enumerate() {
+        let line = line.trim();
+
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+            has_events |= in_events;
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+
+        if let Some(fields) = line.strip_prefix("Format:") {
+            format =
+                fields.split(',').map(|f| f.trim().to_lowercase()).collect();
+        } else if let Some(event) = line.strip_prefix("Dialogue:") {
+            let format: Vec<&str> = format.iter().map(String::as_str).collect();
+            match parse_ass_event(event, &format, options) {
+                Ok(Some(segment)) => segments.push(segment),
+                Ok(None) => {}
+                Err(e) => warnings.push(ParseWarning {
+                    line: number + 1,
+                    message: format!("Skipped event: {e}"),
+                }),
+            }
+        }
+    }
+
+    if !has_events {
+        return Err(EngramError::SubtitleParseError(
+            "Missing [Events] section".into(),
+        ));
+    }
+
+    // Events are not required to be in order.
+    segments.sort_by_key(|seg| seg.start);
+
+    Ok((segments, warnings))
+}
+
+// Parses the fields of a single event. Returns None for events that are
+// filtered out or have no text left once the override tags are removed.
+pub fn parse_ass_event(
+    event: &str,
+    format: &[&str],
+    options: &AssOptions,
+) -> EngramResult<Option<Segment>> {
+    // Text is always last and is the only field that may contain commas.
+    let fields: Vec<&str> = event.splitn(format.len(), ',').collect();
+    let field = |name: &str| {
+        format
+            .iter()
+            .position(|f| *f == name)
+            .and_then(|i| fields.get(i))
+            .map(|value| value.trim())
+    };
+
+    let raw_text = field("text").unwrap_or_default();
+    let style = field("style").unwrap_or_default();
+    if options.excludes(style, raw_text) {
+        return Ok(None);
+    }
+
+    // Vector drawings are shapes, not dialogue.
+    if has_override(raw_text, &["\\p1", "\\p2", "\\p3", "\\p4"]) {
+        return Ok(None);
+    }
+
+    let text = clean_ass_text(raw_text);
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    let start = field("start").map(parse_ass_timestamp).transpose()?;
+    let end = field("end").map(parse_ass_timestamp).transpose()?;
+    let non_empty =
+        |value: Option<&str>| value.filter(|v| !v.is_empty()).map(String::from);
+    let raw = (text != raw_text).then(|| raw_text.to_string());
+
+    Ok(Some(Segment {
+        start: start.unwrap_or(0),
+        end: end.unwrap_or(0),
+        text,
+        style: non_empty(Some(style)),
+        actor: non_empty(field("name")),
+        raw,
+        ..Default::default()
+    }))
+}
+
+// "H:MM:SS.cc", with centiseconds.
+pub fn parse_ass_timestamp(ts: &str) -> EngramResult<i64> {
+    let invalid =
+        || EngramError::SubtitleParseError(format!("Invalid timestamp: {ts}"));
+
+    let (clock, fraction) = ts.trim().split_once('.').ok_or_else(invalid)?;
+    let parts: Vec<i64> = clock
+        .split(':')
+        .map(|part| part.parse().map_err(|_| invalid()))
+        .collect::<EngramResult<_>>()?;
+    let [hours, minutes, seconds] = parts[..] else {
+        return Err(invalid());
+    };
+
+    // Pad or cut to milliseconds, "5" is 500ms and "05" is 50ms.
+    let digits: String =
+        fraction.chars().chain("000".chars()).take(3).collect();
+    let milliseconds: i64 = digits.parse().map_err(|_| invalid())?;
+
+    Ok(hours * 3_600_000 + minutes * 60_000 + seconds * 1000 + milliseconds)
+}
+
+// Removes {...} override blocks and turns the \N, \n and \h escapes into
+// plain text.
+pub fn clean_ass_text(raw: &str) -> String {
+    let mut text = String::with_capacity(raw.len());
+    let mut depth = 0;
+
+    for c in raw.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            c if depth == 0 => text.push(c),
+            _ => {}
+        }
+    }
+
+    text.replace("\\N", "\n")
+        .replace("\\n", "\n")
+        .replace("\\h", "\u{a0}")
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Whether any override block in `raw` contains one of `tags`.
+fn has_override(raw: &str, tags: &[&str]) -> bool {
+    raw.split('{')
+        .skip(1)
+        .filter_map(|block| block.split_once('}').map(|(tags, _)| tags))
+        .any(|block| tags.iter().any(|tag| block.contains(tag)))
+}
+
+const ASS_STYLE_FORMAT: &str = "Format: Name, Fontname, Fontsize, \
+    PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, \
+    Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, \
+    Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding";
+
+// White text with a black outline, bottom center.
+const ASS_STYLE_VALUES: &str = "Arial,20,&H00FFFFFF,&H000000FF,&H00000000,\
+    &H00000000,0,0,0,0,100,100,0,0,1,2,0,2,10,10,10,1";
+
+// Writes a v4+ script. Every style name in use gets the default look, as
+// only the name survives parsing.
+pub fn generate_ass(segments: &[Segment]) -> String {
+    let styles: BTreeSet<&str> = std::iter::once("Default")
+        .chain(segments.iter().filter_map(|seg| seg.style.as_deref()))
+        .collect();
+
+    let styles = styles
+        .iter()
+        .map(|name| format!("Style: {name},{ASS_STYLE_VALUES}\n"))
+        .collect::<String>();
+
+    let events = segments
+        .iter()
+        .map(|seg| {
+            format!(
+                "Dialogue: 0,{},{},{},{},0,0,0,,{}\n",
+                format_ass_timestamp(seg.start),
+                format_ass_timestamp(seg.end),
+                seg.style.as_deref().unwrap_or("Default"),
+                seg.actor.as_deref().unwrap_or_default(),
+                seg.raw_text().replace('\n', "\\N"),
+            )
+        })
+        .collect::<String>();
+
+    format!(
+        "[Script Info]\nScriptType: v4.00+\n\n[V4+ Styles]\n\
+         {ASS_STYLE_FORMAT}\n{styles}\n[Events]\n\
+         Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, \
+         Effect, Text\n{events}"
+    )
+}
+
+pub fn format_ass_timestamp(ms: i64) -> String {
+    let hours = ms / 3_600_000;
+    let minutes = (ms % 3_600_000) / 60_000;
+    let seconds = (ms % 60_000) / 1000;
+    let centiseconds = (ms % 1000) / 10;
+    format!("{hours}:{minutes:02}:{seconds:02}.{centiseconds:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subtitles::cue_times;
+
+    const ASS_HEADER: &str = "[Script Info]\nScriptType: v4.00+\n\n[Events]\n\
+        Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, \
+        Effect, Text\n";
+
+    fn parse_default_ass(content: &str) -> EngramResult<Vec<Segment>> {
+        parse_ass(content, &AssOptions::default())
+    }
+
+    #[test]
+    fn parses_ass_timestamps() {
+        assert_eq!(parse_ass_timestamp("0:00:01.50").unwrap(), 1500);
+        assert_eq!(parse_ass_timestamp("1:02:03.04").unwrap(), 3_723_040);
+        assert_eq!(parse_ass_timestamp(" 0:00:01.5 ").unwrap(), 1500);
+    }
+
+    #[test]
+    fn rejects_malformed_ass_timestamps() {
+        for ts in ["0:00:01", "00:01.50", "0:00:xx.50", "0:00:01.ab", ""] {
+            assert!(parse_ass_timestamp(ts).is_err(), "{ts}");
+        }
+    }
+
+    #[test]
+    fn rejects_ass_without_events() {
+        let content = "[Script Info]\nTitle: Nothing\n";
+        assert!(parse_default_ass(content).is_err());
+    }
+
+    #[test]
+    fn parses_ass_without_blank_lines() {
+        let content = "[Script Info]\nScriptType: v4.00+\n[Events]\n\
+            Format: Layer, Start, End, Style, Name, MarginL, MarginR, \
+            MarginV, Effect, Text\n\
+            Dialogue: 0,0:00:03.00,0:00:04.00,Default,,0,0,0,,Second\n\
+            Dialogue: 0,0:00:01.00,0:00:02.00,Default,Bob,0,0,0,,First, \
+            with a comma\n";
+        let segments = parse_default_ass(content).unwrap();
+
+        assert_eq!(
+            cue_times(&segments),
+            [(1000, 2000, "First, with a comma"), (3000, 4000, "Second")]
+        );
+        assert_eq!(segments[0].actor.as_deref(), Some("Bob"));
+        assert_eq!(segments[0].style.as_deref(), Some("Default"));
+    }
+
+    #[test]
+    fn strips_ass_bom() {
+        let content = format!(
+            "\u{feff}{ASS_HEADER}\
+             Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,Hello\n"
+        );
+        assert_eq!(
+            cue_times(&parse_default_ass(&content).unwrap()),
+            [(1000, 2000, "Hello")]
+        );
+    }
+
+    #[test]
+    fn filters_ass_signs_karaoke_and_drawings() {
+        let content = format!(
+            "{ASS_HEADER}\
+             Dialogue: 0,0:00:01.00,0:00:02.00,Signs,,0,0,0,,EXIT\n\
+             Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{{\\k20}}La\n\
+             Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{{\\p1}}m 0 0\n\
+             Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{{\\i1}}Kept\n"
+        );
+        let segments = parse_default_ass(&content).unwrap();
+
+        assert_eq!(cue_times(&segments), [(1000, 2000, "Kept")]);
+    }
+
+    #[test]
+    fn skips_malformed_ass_events() {
+        let content = format!(
+            "{ASS_HEADER}\
+             Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,First\n\
+             Dialogue: 0,0:00:xx.00,0:00:03.00,Default,,0,0,0,,Broken\n\
+             Dialogue: 0,0:00:04.00,0:00:05.00,Default,,0,0,0,,Last\n"
+        );
+        let (segments, warnings) = Ass.parse_with_warnings(&content).unwrap();
+
+        assert_eq!(
+            cue_times(&segments),
+            [(1000, 2000, "First"), (4000, 5000, "Last")]
+        );
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 7);
+        assert_eq!(
+            cue_times(&parse_default_ass(&content).unwrap()),
+            cue_times(&segments)
+        );
+    }
+
+    #[test]
+    fn cleans_ass_text() {
+        assert_eq!(
+            clean_ass_text("{\\an8}Top\\Nline {\\b1}bold{\\b0}"),
+            "Top\nline bold"
+        );
+    }
+}
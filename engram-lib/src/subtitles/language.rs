@@ -0,0 +1,145 @@
+// ISO 639-1 code, ISO 639-2 codes (bibliographic and terminologic) and the
+// English name of the languages we recognize in file names and stream tags.
+// Covers every language Whisper knows. Hawaiian and Cantonese have no ISO
+// 639-1 code and go by the ones Whisper uses.
+const LANGUAGES: &[(&str, &[&str], &str)] = &[
+    ("af", &["afr"], "afrikaans"),
+    ("am", &["amh"], "amharic"),
+    ("ar", &["ara"], "arabic"),
+    ("as", &["asm"], "assamese"),
+    ("az", &["aze"], "azerbaijani"),
+    ("ba", &["bak"], "bashkir"),
+    ("be", &["bel"], "belarusian"),
+    ("bg", &["bul"], "bulgarian"),
+    ("bn", &["ben"], "bengali"),
+    ("bo", &["tib", "bod"], "tibetan"),
+    ("br", &["bre"], "breton"),
+    ("bs", &["bos"], "bosnian"),
+    ("ca", &["cat"], "catalan"),
+    ("cs", &["cze", "ces"], "czech"),
+    ("cy", &["wel", "cym"], "welsh"),
+    ("da", &["dan"], "danish"),
+    ("de", &["ger", "deu"], "german"),
+    ("el", &["gre", "ell"], "greek"),
+    ("en", &["eng"], "english"),
+    ("es", &["spa"], "spanish"),
+    ("et", &["est"], "estonian"),
+    ("eu", &["baq", "eus"], "basque"),
+    ("fa", &["per", "fas"], "persian"),
+    ("fi", &["fin"], "finnish"),
+    ("fo", &["fao"], "faroese"),
+    ("fr", &["fre", "fra"], "french"),
+    ("gl", &["glg"], "galician"),
+    ("gu", &["guj"], "gujarati"),
+    ("ha", &["hau"], "hausa"),
+    ("haw", &[], "hawaiian"),
+    ("he", &["heb"], "hebrew"),
+    ("hi", &["hin"], "hindi"),
+    ("hr", &["hrv"], "croatian"),
+    ("ht", &["hat"], "haitian"),
+    ("hu", &["hun"], "hungarian"),
+    ("hy", &["arm", "hye"], "armenian"),
+    ("id", &["ind"], "indonesian"),
+    ("is", &["ice", "isl"], "icelandic"),
+    ("it", &["ita"], "italian"),
+    ("ja", &["jpn"], "japanese"),
+    ("jv", &["jav", "jw"], "javanese"),
+    ("ka", &["geo", "kat"], "georgian"),
+    ("kk", &["kaz"], "kazakh"),
+    ("km", &["khm"], "khmer"),
+    ("kn", &["kan"], "kannada"),
+    ("ko", &["kor"], "korean"),
+    ("la", &["lat"], "latin"),
+    ("lb", &["ltz"], "luxembourgish"),
+    ("ln", &["lin"], "lingala"),
+    ("lo", &["lao"], "lao"),
+    ("lt", &["lit"], "lithuanian"),
+    ("lv", &["lav"], "latvian"),
+    ("mg", &["mlg"], "malagasy"),
+    ("mi", &["mao", "mri"], "maori"),
+    ("mk", &["mac", "mkd"], "macedonian"),
+    ("ml", &["mal"], "malayalam"),
+    ("mn", &["mon"], "mongolian"),
+    ("mr", &["mar"], "marathi"),
+    ("ms", &["may", "msa"], "malay"),
+    ("mt", &["mlt"], "maltese"),
+    ("my", &["bur", "mya"], "burmese"),
+    ("ne", &["nep"], "nepali"),
+    ("nl", &["dut", "nld"], "dutch"),
+    ("nn", &["nno"], "nynorsk"),
+    ("no", &["nor", "nob"], "norwegian"),
+    ("oc", &["oci"], "occitan"),
+    ("pa", &["pan"], "punjabi"),
+    ("pl", &["pol"], "polish"),
+    ("ps", &["pus"], "pashto"),
+    ("pt", &["por"], "portuguese"),
+    ("ro", &["rum", "ron"], "romanian"),
+    ("ru", &["rus"], "russian"),
+    ("sa", &["san"], "sanskrit"),
+    ("sd", &["snd"], "sindhi"),
+    ("si", &["sin"], "sinhala"),
+    ("sk", &["slo", "slk"], "slovak"),
+    ("sl", &["slv"], "slovenian"),
+    ("sn", &["sna"], "shona"),
+    ("so", &["som"], "somali"),
+    ("sq", &["alb", "sqi"], "albanian"),
+    ("sr", &["srp"], "serbian"),
+    ("su", &["sun"], "sundanese"),
+    ("sv", &["swe"], "swedish"),
+    ("sw", &["swa"], "swahili"),
+    ("ta", &["tam"], "tamil"),
+    ("te", &["tel"], "telugu"),
+    ("tg", &["tgk"], "tajik"),
+    ("th", &["tha"], "thai"),
+    ("tk", &["tuk"], "turkmen"),
+    ("tl", &["tgl"], "tagalog"),
+    ("tr", &["tur"], "turkish"),
+    ("tt", &["tat"], "tatar"),
+    ("uk", &["ukr"], "ukrainian"),
+    ("ur", &["urd"], "urdu"),
+    ("uz", &["uzb"], "uzbek"),
+    ("vi", &["vie"], "vietnamese"),
+    ("yi", &["yid"], "yiddish"),
+    ("yo", &["yor"], "yoruba"),
+    ("yue", &[], "cantonese"),
+    ("zh", &["chi", "zho"], "chinese"),
+];
+
+// Maps "en", "eng", "English" or "en-US" to "en".
+pub fn normalize_language(tag: &str) -> Option<&'static str> {
+    let tag = tag.trim().to_lowercase();
+    let primary = tag.split(['-', '_']).next().unwrap_or_default();
+
+    LANGUAGES
+        .iter()
+        .find(|(code, codes, name)| {
+            *code == primary || codes.contains(&primary) || *name == primary
+        })
+        .map(|(code, _, _)| *code)
+}
+
+// normalize_language for words in free text, where two-letter codes are
+// more likely English words such as "as", "is" or "so".
+pub fn language_in_text(word: &str) -> Option<&'static str> {
+    let word = word.trim();
+    if word.len() <= 2 {
+        return None;
+    }
+    normalize_language(word)
+}
+
+pub fn language_name(code: &str) -> Option<&'static str> {
+    LANGUAGES
+        .iter()
+        .find(|(c, _, _)| *c == code)
+        .map(|(_, _, name)| *name)
+}
+
+// ISO 639-2 codes for an ISO 639-1 code, bibliographic first.
+pub fn language_codes(code: &str) -> &'static [&'static str] {
+    LANGUAGES
+        .iter()
+        .find(|(c, _, _)| *c == code)
+        .map(|(_, codes, _)| *codes)
+        .unwrap_or_default()
+}
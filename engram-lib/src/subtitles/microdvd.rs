@@ -0,0 +1,202 @@
+use super::{Segment, SubtitleFormat};
+use crate::EngramResult;
+
+// Used when the file does not declare its frame rate.
+pub const DEFAULT_FPS: f64 = 23.976;
+
+// How long a line without an end frame stays up, in milliseconds.
+const OPEN_END_DURATION: i64 = 3000;
+
+// Timed in frames rather than milliseconds, hence the frame rate.
+pub struct MicroDvd {
+    pub fps: f64,
+}
+
+impl SubtitleFormat for MicroDvd {
+    fn name(&self) -> &'static str {
+        "MicroDVD"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["sub"]
+    }
+
+    fn detect(&self, content: &str) -> bool {
+        content
+            .trim_start_matches('\u{feff}')
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .and_then(|line| split_frames(line.trim()))
+            .is_some()
+    }
+
+    fn parse(&self, content: &str) -> EngramResult<Vec<Segment>> {
+        let mut fps = self.fps;
+        let mut segments = Vec::new();
+
+        for line in content.trim_start_matches('\u{feff}').lines() {
+            let Some((start, end, text)) = split_frames(line.trim()) else {
+                continue;
+            };
+
+            // A leading "{1}{1}25" declares the frame rate.
+            if segments.is_empty()
+                && start == 1
+                && end == Some(1)
+                && let Ok(rate) = text.trim().parse::<f64>()
+                && rate > 0.0
+            {
+                fps = rate;
+                continue;
+            }
+
+            let text = clean_microdvd_text(text);
+            if text.is_empty() {
+                continue;
+            }
+
+            let start = frame_to_ms(start, fps);
+            let end = end
+                .map(|end| frame_to_ms(end, fps))
+                .unwrap_or(start + OPEN_END_DURATION);
+
+            segments.push(Segment {
+                start,
+                end,
+                text,
+                ..Default::default()
+            });
+        }
+
+        Ok(segments)
+    }
+
+    fn serialize(&self, segments: &[Segment]) -> String {
+        let lines = segments
+            .iter()
+            .map(|seg| {
+                format!(
+                    "{{{}}}{{{}}}{}\n",
+                    ms_to_frame(seg.start, self.fps),
+                    ms_to_frame(seg.end, self.fps),
+                    seg.text.replace('\n', "|")
+                )
+            })
+            .collect::<String>();
+
+        format!("{{1}}{{1}}{}\n{lines}", self.fps)
+    }
+}
+
+// "{start}{end}text", the end frame may be left empty.
+fn split_frames(line: &str) -> Option<(u64, Option<u64>, &str)> {
+    let (start, rest) = line.strip_prefix('{')?.split_once('}')?;
+    let (end, text) = rest.strip_prefix('{')?.split_once('}')?;
+
+    let start = start.trim().parse().ok()?;
+    let end = match end.trim() {
+        "" => None,
+        end => Some(end.parse().ok()?),
+    };
+
+    Some((start, end, text))
+}
+
+// Lines are separated by "|". Formatting codes such as {y:i} or
+// {c:$0000ff} are dropped, as is the "/" that marks an italic line.
+fn clean_microdvd_text(raw: &str) -> String {
+    raw.split('|')
+        .map(|line| {
+            let mut line = line.trim();
+            while let Some(rest) = line.strip_prefix('{')
+                && let Some((code, rest)) = rest.split_once('}')
+                && code.contains(':')
+            {
+                line = rest.trim_start();
+            }
+            line.trim_start_matches('/').trim()
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn frame_to_ms(frame: u64, fps: f64) -> i64 {
+    (frame as f64 * 1000.0 / fps).round() as i64
+}
+
+fn ms_to_frame(ms: i64, fps: f64) -> i64 {
+    (ms as f64 * fps / 1000.0).round() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subtitles::cue_times;
+
+    fn parse(fps: f64, content: &str) -> Vec<Segment> {
+        MicroDvd { fps }.parse(content).unwrap()
+    }
+
+    #[test]
+    fn uses_default_frame_rate() {
+        let segments = parse(DEFAULT_FPS, "{24}{48}Hello\n");
+        assert_eq!(cue_times(&segments), [(1001, 2002, "Hello")]);
+    }
+
+    #[test]
+    fn uses_configured_frame_rate() {
+        let segments = parse(25.0, "{25}{50}Hello\n");
+        assert_eq!(cue_times(&segments), [(1000, 2000, "Hello")]);
+    }
+
+    #[test]
+    fn reads_declared_frame_rate() {
+        let segments = parse(DEFAULT_FPS, "{1}{1}25\n{25}{50}Hello\n");
+        assert_eq!(cue_times(&segments), [(1000, 2000, "Hello")]);
+
+        let segments = parse(DEFAULT_FPS, "{1}{1}29.97\n{2997}{5994}Hello\n");
+        assert_eq!(cue_times(&segments), [(100_000, 200_000, "Hello")]);
+    }
+
+    #[test]
+    fn skips_malformed_frames() {
+        let content = "{x}{50}Bad start\n{25}{y}Bad end\n{25}Missing end\n\
+                       no frames\n{75}{100}Good\n";
+        let segments = parse(25.0, content);
+        assert_eq!(cue_times(&segments), [(3000, 4000, "Good")]);
+    }
+
+    #[test]
+    fn keeps_open_ended_lines_up() {
+        let segments = parse(25.0, "{25}{}Open\n");
+        assert_eq!(
+            cue_times(&segments),
+            [(1000, 1000 + OPEN_END_DURATION, "Open")]
+        );
+    }
+
+    #[test]
+    fn handles_blank_lines_bom_and_formatting() {
+        let content =
+            "\u{feff}{25}{50}{y:i}/Two|{c:$0000ff}lines\r\n\r\n\n{75}{100}Next";
+        assert!(MicroDvd { fps: 25.0 }.detect(content));
+
+        let segments = parse(25.0, content);
+        assert_eq!(
+            cue_times(&segments),
+            [(1000, 2000, "Two\nlines"), (3000, 4000, "Next")]
+        );
+    }
+
+    #[test]
+    fn round_trips_frame_rate() {
+        let segments = parse(25.0, "{25}{50}Two|lines\n");
+        let written = MicroDvd { fps: 25.0 }.serialize(&segments);
+        assert_eq!(written, "{1}{1}25\n{25}{50}Two|lines\n");
+
+        // The declared rate wins over the one the reader assumes.
+        let parsed = parse(DEFAULT_FPS, &written);
+        assert_eq!(cue_times(&parsed), [(1000, 2000, "Two\nlines")]);
+    }
+}
@@ -0,0 +1,634 @@
+pub mod ass;
+pub mod language;
+pub mod microdvd;
+pub mod srt;
+pub mod subviewer;
+pub mod vtt;
+pub mod words;
+
+use crate::{EngramResult, errors::EngramError};
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+use std::fs;
+use std::path::Path;
+
+pub use ass::{
+    ASS_DEFAULT_FORMAT, ASS_EMBEDDED_FORMAT, Ass, AssOptions, clean_ass_text,
+    parse_ass, parse_ass_event, parse_ass_file, parse_ass_lenient,
+    parse_ass_timestamp,
+};
+pub use language::{
+    language_codes, language_in_text, language_name, normalize_language,
+};
+pub use microdvd::MicroDvd;
+pub use srt::{
+    Srt, generate_srt, parse_srt, parse_srt_file, parse_srt_lenient,
+};
+pub use subviewer::SubViewer;
+pub use vtt::{
+    WebVtt, format_vtt_timestamp, generate_vtt, parse_vtt, parse_vtt_lenient,
+    parse_vtt_timestamp,
+};
+pub use words::{Word, join_tokens, match_span};
+
+#[derive(Debug, Clone, Default)]
+pub struct Segment {
+    pub start: i64,
+    pub end: i64,
+    pub text: String,
+    // Only ASS/SSA carries these.
+    pub style: Option<String>,
+    pub actor: Option<String>,
+    // The text with its original markup, when it had any.
+    pub raw: Option<String>,
+    // ISO 639-1 code of the track the segment came from, when known.
+    pub language: Option<String>,
+    // When each word is spoken, for transcribed segments.
+    pub words: Vec<Word>,
+}
+
+impl Segment {
+    pub fn from_markup(start: i64, end: i64, raw: String) -> Self {
+        let text = normalize_text(&raw);
+        let raw = (text != raw).then_some(raw);
+
+        Self {
+            start,
+            end,
+            text,
+            raw,
+            ..Default::default()
+        }
+    }
+
+    // What gets written back out when re-exporting.
+    pub fn raw_text(&self) -> &str {
+        self.raw.as_deref().unwrap_or(&self.text)
+    }
+
+    // The part of the segment between `start` and `end`. Without word
+    // timings only the times change.
+    pub fn narrow(&self, start: i64, end: i64) -> Segment {
+        let words: Vec<Word> = self
+            .words
+            .iter()
+            .filter(|word| word.end > start && word.start < end)
+            .cloned()
+            .collect();
+        if words.is_empty() {
+            return Segment {
+                start,
+                end,
+                ..self.clone()
+            };
+        }
+
+        Segment {
+            start,
+            end,
+            text: words
+                .iter()
+                .map(|word| word.text.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+            raw: None,
+            words,
+            ..self.clone()
+        }
+    }
+}
+
+// Plain text for indexing and display. Drops HTML-style tags such as <i>,
+// <font color=...> and WebVTT voice or timestamp tags, ASS override blocks
+// such as {\an8} and decodes the usual escapes. Hard spaces stay
+// non-breaking.
+pub fn normalize_text(raw: &str) -> String {
+    let mut text = String::with_capacity(raw.len());
+    let mut rest = raw;
+
+    while let Some(c) = rest.chars().next() {
+        let tail = &rest[c.len_utf8()..];
+        let skip = match c {
+            '<' => tag_len(tail),
+            '{' => tail.find('}'),
+            _ => None,
+        };
+
+        match skip {
+            Some(len) => rest = &tail[len + 1..],
+            None => {
+                text.push(c);
+                rest = tail;
+            }
+        }
+    }
+
+    text.replace("\\N", "\n")
+        .replace("\\n", "\n")
+        .replace("\\h", "\u{a0}")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&nbsp;", "\u{a0}")
+        .replace(['\u{200e}', '\u{200f}'], "")
+        .replace("&lrm;", "")
+        .replace("&rlm;", "")
+        .replace("&amp;", "&")
+        .lines()
+        .map(|line| {
+            line.split(|c: char| c.is_whitespace() && c != '\u{a0}')
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Length of the tag `tail` starts with, up to the closing '>'. A lone '<' in
+// dialogue is not a tag.
+fn tag_len(tail: &str) -> Option<usize> {
+    let end = tail.find('>')?;
+    let inner = &tail[..end];
+    let first = inner.chars().next()?;
+
+    let is_tag = first.is_ascii_alphabetic()
+        || first == '/'
+        || (first.is_ascii_digit() && inner.contains(':'));
+    (is_tag && !inner.contains('<')).then_some(end)
+}
+
+// Something a parser had to repair or skip. `line` is 1-based.
+#[derive(Debug, Clone)]
+pub struct ParseWarning {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// Lines a format looks at when sniffing content.
+const SNIFF_LINES: usize = 32;
+
+pub trait SubtitleFormat: Sync {
+    fn name(&self) -> &'static str;
+    // Lowercase, without the dot.
+    fn extensions(&self) -> &'static [&'static str];
+    // Whether `content` looks like this format.
+    fn detect(&self, content: &str) -> bool;
+    fn parse(&self, content: &str) -> EngramResult<Vec<Segment>>;
+    // Formats that recover from malformed cues report what they fixed.
+    fn parse_with_warnings(
+        &self,
+        content: &str,
+    ) -> EngramResult<(Vec<Segment>, Vec<ParseWarning>)> {
+        Ok((self.parse(content)?, Vec::new()))
+    }
+    fn serialize(&self, segments: &[Segment]) -> String;
+}
+
+// In order of preference when a media file has several sidecars.
+static FORMATS: &[&dyn SubtitleFormat] = &[
+    &Srt,
+    &WebVtt,
+    &Ass,
+    &MicroDvd {
+        fps: microdvd::DEFAULT_FPS,
+    },
+    &SubViewer,
+];
+
+pub fn formats() -> &'static [&'static dyn SubtitleFormat] {
+    FORMATS
+}
+
+pub fn subtitle_extensions() -> impl Iterator<Item = &'static str> {
+    FORMATS
+        .iter()
+        .flat_map(|format| format.extensions().iter().copied())
+}
+
+pub fn is_subtitle_extension(ext: &str) -> bool {
+    subtitle_extensions().any(|e| ext.eq_ignore_ascii_case(e))
+}
+
+pub fn detect_format(content: &str) -> Option<&'static dyn SubtitleFormat> {
+    FORMATS
+        .iter()
+        .copied()
+        .find(|format| format.detect(content))
+}
+
+// Formats registered for `extension` come first, several share `.sub`. When
+// none of them recognizes the content every other format gets a go.
+pub fn find_format(
+    content: &str,
+    extension: Option<&str>,
+) -> Option<&'static dyn SubtitleFormat> {
+    let candidates: Vec<&'static dyn SubtitleFormat> = match extension {
+        Some(ext) => FORMATS
+            .iter()
+            .copied()
+            .filter(|format| {
+                format
+                    .extensions()
+                    .iter()
+                    .any(|e| ext.eq_ignore_ascii_case(e))
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    candidates
+        .iter()
+        .copied()
+        .find(|format| format.detect(content))
+        .or_else(|| detect_format(content))
+        .or_else(|| candidates.first().copied())
+}
+
+// Also returns what the parser had to repair or skip. `encoding` overrides
+// detection.
+pub fn parse_subtitle_file(
+    path: &Path,
+    encoding: Option<&'static Encoding>,
+) -> EngramResult<(Vec<Segment>, Vec<ParseWarning>)> {
+    let content = read_subtitle_file(path, encoding)?;
+    let extension = path.extension().and_then(|e| e.to_str());
+
+    let format = find_format(&content, extension).ok_or_else(|| {
+        EngramError::SubtitleParseError(format!(
+            "Unrecognized subtitle format: {}",
+            path.display()
+        ))
+    })?;
+
+    format.parse_with_warnings(&content)
+}
+
+pub(crate) fn read_subtitle_file(
+    path: &Path,
+    encoding: Option<&'static Encoding>,
+) -> EngramResult<String> {
+    Ok(decode_subtitle_bytes(&fs::read(path)?, encoding))
+}
+
+pub fn decode_subtitle_bytes(
+    bytes: &[u8],
+    encoding: Option<&'static Encoding>,
+) -> String {
+    let encoding = encoding.unwrap_or_else(|| detect_encoding(bytes));
+    let (text, _) = encoding.decode_with_bom_removal(bytes);
+    text.into_owned()
+}
+
+// A BOM settles it. Otherwise UTF-16 is recognized by its zero bytes, valid
+// UTF-8 is taken as is and the rest goes to a statistical detector.
+pub fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+
+    // Before UTF-8, as zero bytes are valid UTF-8.
+    if let Some(encoding) = detect_utf16(bytes) {
+        return encoding;
+    }
+
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector.guess(None, true)
+}
+
+// Subtitles are full of ASCII digits and punctuation, which in UTF-16 have a
+// zero in every other byte.
+fn detect_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(4096)];
+    let pairs = sample.len() / 2;
+    if pairs == 0 {
+        return None;
+    }
+
+    let (even, odd) = sample.chunks_exact(2).fold((0, 0), |(even, odd), p| {
+        (even + (p[0] == 0) as usize, odd + (p[1] == 0) as usize)
+    });
+
+    if odd * 3 > pairs && even * 10 < pairs {
+        Some(UTF_16LE)
+    } else if even * 3 > pairs && odd * 10 < pairs {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+// Takes WHATWG labels such as "windows-1251", "shift_jis" or "gbk".
+pub fn encoding_for_label(label: &str) -> EngramResult<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| {
+        EngramError::SubtitleParseError(format!("Unknown encoding: {label}"))
+    })
+}
+
+pub fn parse_timestamp(ts: &str) -> EngramResult<i64> {
+    let parts: Vec<&str> = ts.split(':').collect();
+    if parts.len() != 3 {
+        return Err(EngramError::SubtitleParseError(format!(
+            "Invalid timestamp: {ts}"
+        )));
+    }
+
+    let hours: i64 = parts[0].trim().parse().map_err(|_| {
+        EngramError::SubtitleParseError(format!("Invalid hours: {}", parts[0]))
+    })?;
+
+    let minutes: i64 = parts[1].trim().parse().map_err(|_| {
+        EngramError::SubtitleParseError(format!(
+            "Invalid minutes: {}",
+            parts[1]
+        ))
+    })?;
+
+    let sec_parts: Vec<&str> = parts[2].split(',').collect();
+    if sec_parts.len() != 2 {
+        return Err(EngramError::SubtitleParseError(format!(
+            "Invalid seconds: {}",
+            parts[2]
+        )));
+    }
+
+    let seconds: i64 = sec_parts[0].trim().parse().map_err(|_| {
+        EngramError::SubtitleParseError(format!(
+            "Invalid seconds: {}",
+            sec_parts[0]
+        ))
+    })?;
+
+    let milliseconds: i64 = sec_parts[1].trim().parse().map_err(|_| {
+        EngramError::SubtitleParseError(format!(
+            "Invalid milliseconds: {}",
+            sec_parts[1]
+        ))
+    })?;
+
+    Ok(hours * 3_600_000 + minutes * 60_000 + seconds * 1000 + milliseconds)
+}
+
+pub fn format_timestamp(ms: i64) -> String {
+    let hours = ms / 3_600_000;
+    let minutes = (ms % 3_600_000) / 60_000;
+    let seconds = (ms % 60_000) / 1000;
+    let milliseconds = ms % 1000;
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        hours, minutes, seconds, milliseconds
+    )
+}
+
+// Keeps the segments overlapping `start..end` and shifts them so they are
+// relative to `start`, clamped to the span. Segments with word timings only
+// keep the words inside it.
+pub fn retime(segments: &[Segment], start: i64, end: i64) -> Vec<Segment> {
+    segments
+        .iter()
+        .filter(|seg| seg.end > start && seg.start < end)
+        .map(|seg| {
+            let mut seg = seg.narrow(seg.start.max(start), seg.end.min(end));
+            seg.start -= start;
+            seg.end -= start;
+            for word in &mut seg.words {
+                word.start = word.start.max(start) - start;
+                word.end = word.end.min(end) - start;
+            }
+            seg
+        })
+        .collect()
+}
+
+// Start, end and text of each segment, which is what most tests compare.
+#[cfg(test)]
+pub(crate) fn cue_times(segments: &[Segment]) -> Vec<(i64, i64, &str)> {
+    segments
+        .iter()
+        .map(|seg| (seg.start, seg.end, seg.text.as_str()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(start: i64, end: i64, text: &str) -> Word {
+        Word {
+            start,
+            end,
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn retimes_segments_to_a_span() {
+        let segments = [
+            Segment {
+                start: 0,
+                end: 900,
+                text: "Before".into(),
+                ..Default::default()
+            },
+            Segment {
+                start: 500,
+                end: 1500,
+                text: "Across the start".into(),
+                ..Default::default()
+            },
+            Segment {
+                start: 2000,
+                end: 4000,
+                text: "one two three".into(),
+                words: vec![
+                    word(2000, 2500, "one"),
+                    word(2600, 3200, "two"),
+                    word(3300, 4000, "three"),
+                ],
+                ..Default::default()
+            },
+            Segment {
+                start: 3000,
+                end: 3500,
+                text: "After".into(),
+                ..Default::default()
+            },
+        ];
+
+        let retimed = retime(&segments, 1000, 3000);
+        assert_eq!(
+            cue_times(&retimed),
+            [(0, 500, "Across the start"), (1000, 2000, "one two")]
+        );
+        // Words are clamped to the span as well.
+        assert_eq!(
+            retimed[1].words,
+            [word(1000, 1500, "one"), word(1600, 2000, "two")]
+        );
+        assert!(retime(&segments, 5000, 6000).is_empty());
+    }
+
+    #[test]
+    fn strips_markup() {
+        let segment = Segment::from_markup(
+            1000,
+            2000,
+            "{\\an8}<i>Top</i>\\Nline <font color=\"#fff\">two</font>".into(),
+        );
+
+        assert_eq!(segment.text, "Top\nline two");
+        assert_eq!(
+            segment.raw.as_deref(),
+            Some("{\\an8}<i>Top</i>\\Nline <font color=\"#fff\">two</font>")
+        );
+        assert_eq!(segment.raw_text(), segment.raw.as_deref().unwrap());
+    }
+
+    #[test]
+    fn keeps_hard_spaces() {
+        assert_eq!(normalize_text("10\\hkm"), "10\u{a0}km");
+        assert_eq!(normalize_text("a&nbsp;b  c"), "a\u{a0}b c");
+    }
+
+    #[test]
+    fn keeps_plain_text_without_raw() {
+        let segment = Segment::from_markup(0, 1, "a < b, 1 <2".into());
+
+        assert_eq!(segment.text, "a < b, 1 <2");
+        assert!(segment.raw.is_none());
+        assert_eq!(segment.raw_text(), "a < b, 1 <2");
+    }
+
+    #[test]
+    fn decodes_escapes_and_drops_timestamp_tags() {
+        assert_eq!(
+            normalize_text("<v Bob>Tom &amp; <00:01.500>Jerry &lt;3</v>"),
+            "Tom & Jerry <3"
+        );
+        assert_eq!(normalize_text("  <b></b>\n\n  "), "");
+    }
+
+    const SRT: &str = "1\r\n00:00:01,000 --> 00:00:02,000\r\nCafé\r\n";
+
+    fn utf16(text: &str, big_endian: bool, bom: bool) -> Vec<u8> {
+        let units =
+            bom.then_some(0xfeff).into_iter().chain(text.encode_utf16());
+        units
+            .flat_map(|unit| match big_endian {
+                true => unit.to_be_bytes(),
+                false => unit.to_le_bytes(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_utf8_with_and_without_bom() {
+        let with_bom = [b"\xef\xbb\xbf".as_slice(), SRT.as_bytes()].concat();
+
+        assert_eq!(detect_encoding(&with_bom), UTF_8);
+        assert_eq!(decode_subtitle_bytes(&with_bom, None), SRT);
+        assert_eq!(decode_subtitle_bytes(SRT.as_bytes(), None), SRT);
+    }
+
+    #[test]
+    fn decodes_utf16() {
+        for big_endian in [false, true] {
+            for bom in [false, true] {
+                let bytes = utf16(SRT, big_endian, bom);
+                let expected = if big_endian { UTF_16BE } else { UTF_16LE };
+
+                assert_eq!(detect_encoding(&bytes), expected);
+                assert_eq!(decode_subtitle_bytes(&bytes, None), SRT);
+            }
+        }
+    }
+
+    #[test]
+    fn honors_encoding_override() {
+        let bytes = b"1\n00:00:01,000 --> 00:00:02,000\nCaf\xe9\n";
+        let encoding = encoding_for_label("windows-1252").unwrap();
+
+        let content = decode_subtitle_bytes(bytes, Some(encoding));
+        assert_eq!(
+            cue_times(&parse_srt(&content).unwrap()),
+            [(1000, 2000, "Café")]
+        );
+        assert!(encoding_for_label("not-an-encoding").is_err());
+    }
+
+    #[test]
+    fn parses_utf16_files() {
+        let dir = std::env::temp_dir()
+            .join(format!("engram-subtitles-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let vtt = "WEBVTT\n\n00:01.000 --> 00:02.000\nCafé\n";
+        let cases = [
+            ("le.srt", utf16(SRT, false, true)),
+            ("be.srt", utf16(SRT, true, false)),
+            ("le.vtt", utf16(vtt, false, false)),
+        ];
+        for (name, bytes) in cases {
+            let path = dir.join(name);
+            fs::write(&path, bytes).unwrap();
+
+            let (segments, _) = parse_subtitle_file(&path, None).unwrap();
+            assert_eq!(cue_times(&segments), [(1000, 2000, "Café")], "{name}");
+            if name.ends_with(".srt") {
+                let segments = parse_srt_file(&path).unwrap();
+                assert_eq!(cue_times(&segments), [(1000, 2000, "Café")]);
+            }
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detects_formats_behind_a_bom() {
+        let cases = [
+            ("\u{feff}1\n00:00:01,000 --> 00:00:02,000\nHi\n", "SubRip"),
+            ("\u{feff}WEBVTT\n\n00:01.000 --> 00:02.000\nHi\n", "WebVTT"),
+            ("\u{feff}[Script Info]\n", "Advanced SubStation Alpha"),
+            ("\u{feff}{1}{25}Hi\n", "MicroDVD"),
+            ("\u{feff}00:00:01.00,00:00:02.00\nHi\n", "SubViewer"),
+        ];
+        for (content, name) in cases {
+            assert_eq!(detect_format(content).map(|f| f.name()), Some(name));
+        }
+    }
+
+    #[test]
+    fn picks_between_sub_formats() {
+        let microdvd = "{1}{25}Hi\n";
+        let subviewer = "[INFORMATION]\n[END INFORMATION]\n\
+                         00:00:01.00,00:00:02.00\nHi\n";
+
+        let name =
+            |content| find_format(content, Some("SUB")).map(|f| f.name());
+        assert_eq!(name(microdvd), Some("MicroDVD"));
+        assert_eq!(name(subviewer), Some("SubViewer"));
+        assert_eq!(name("garbage"), Some("MicroDVD"));
+        assert!(find_format("garbage", None).is_none());
+    }
+
+    #[test]
+    fn parses_legacy_timestamps() {
+        assert_eq!(parse_timestamp("01:02:03,004").unwrap(), 3_723_004);
+        for ts in ["01:02:03.004", "02:03,004", "aa:02:03,004", ""] {
+            assert!(parse_timestamp(ts).is_err(), "{ts}");
+        }
+        assert_eq!(format_timestamp(3_723_004), "01:02:03,004");
+    }
+}
@@ -0,0 +1,288 @@
+use std::path::Path;
+
+use super::{
+    ParseWarning, SNIFF_LINES, Segment, SubtitleFormat, format_timestamp,
+    read_subtitle_file,
+};
+use crate::EngramResult;
+
+pub struct Srt;
+
+impl SubtitleFormat for Srt {
+    fn name(&self) -> &'static str {
+        "SubRip"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["srt"]
+    }
+
+    // SubRip has no header, so anything with a timing line that is not
+    // WebVTT will do.
+    fn detect(&self, content: &str) -> bool {
+        !content.trim_start_matches('\u{feff}').starts_with("WEBVTT")
+            && content
+                .lines()
+                .take(SNIFF_LINES)
+                .any(|line| parse_srt_timing(line.trim()).is_some())
+    }
+
+    fn parse(&self, content: &str) -> EngramResult<Vec<Segment>> {
+        parse_srt(content)
+    }
+
+    fn parse_with_warnings(
+        &self,
+        content: &str,
+    ) -> EngramResult<(Vec<Segment>, Vec<ParseWarning>)> {
+        Ok(parse_srt_lenient(content))
+    }
+
+    fn serialize(&self, segments: &[Segment]) -> String {
+        generate_srt(segments)
+    }
+}
+
+// Reads the file as SubRip whatever its extension. `parse_subtitle_file`
+// picks the format by itself.
+pub fn parse_srt_file(path: &Path) -> EngramResult<Vec<Segment>> {
+    parse_srt(&read_subtitle_file(path, None)?)
+}
+
+pub fn parse_srt(content: &str) -> EngramResult<Vec<Segment>> {
+    Ok(parse_srt_lenient(content).0)
+}
+
+// Cues are found by their timing line rather than by blank lines, so missing
+// cue numbers and blank lines inside the text do not throw the parser off.
+// Cues that cannot be repaired are skipped with a warning.
+pub fn parse_srt_lenient(content: &str) -> (Vec<Segment>, Vec<ParseWarning>) {
+    let content = content
+        .replace('\u{feff}', "")
+        .replace("\r\n", "\n")
+        .replace('\r', "\n");
+    let lines: Vec<&str> = content.lines().map(str::trim).collect();
+
+    let timings: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.contains("-->"))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut segments = Vec::new();
+    let mut warnings = Vec::new();
+    let mut warn = |line: usize, message: String| {
+        warnings.push(ParseWarning {
+            line: line + 1,
+            message,
+        })
+    };
+
+    let is_number = |i: usize| lines[i].parse::<u64>().is_ok();
+
+    let first = timings.first().copied().unwrap_or(lines.len());
+    let cue_number = first > 0 && is_number(first - 1);
+    let preamble = if cue_number { first - 1 } else { first };
+    if let Some(i) = (0..preamble).find(|&i| !lines[i].is_empty()) {
+        warn(i, "Text outside of a cue".into());
+    }
+
+    for (k, &timing) in timings.iter().enumerate() {
+        if timing == 0 || !is_number(timing - 1) {
+            warn(timing, "Missing cue number".into());
+        }
+
+        // The text runs up to the next cue, minus that cue's number.
+        let next = timings.get(k + 1).copied().unwrap_or(lines.len());
+        let end =
+            if next < lines.len() && next - 1 > timing && is_number(next - 1) {
+                next - 1
+            } else {
+                next
+            };
+
+        let (mut start, mut stop) = match parse_srt_timing(lines[timing]) {
+            Some(times) => times,
+            None => {
+                warn(timing, format!("Invalid timing: {}", lines[timing]));
+                continue;
+            }
+        };
+
+        if start < 0 || stop < 0 {
+            warn(timing, "Negative timestamp clamped to zero".into());
+            start = start.max(0);
+            stop = stop.max(0);
+        }
+        if stop < start {
+            warn(timing, "Cue ends before it starts".into());
+            std::mem::swap(&mut start, &mut stop);
+        }
+
+        let raw = lines[timing + 1..end]
+            .iter()
+            .filter(|line| !line.is_empty())
+            .copied()
+            .collect::<Vec<_>>()
+            .join("\n");
+        let segment = Segment::from_markup(start, stop, raw);
+        if segment.text.is_empty() {
+            warn(timing, "Cue has no text".into());
+            continue;
+        }
+
+        segments.push(segment);
+    }
+
+    (segments, warnings)
+}
+
+// "start --> end", optionally followed by position coordinates.
+fn parse_srt_timing(line: &str) -> Option<(i64, i64)> {
+    let (start, rest) = line.split_once("-->")?;
+    let end = rest.split_whitespace().next()?;
+    Some((parse_srt_timestamp(start)?, parse_srt_timestamp(end)?))
+}
+
+// Unlike `parse_timestamp` this takes a dot before the milliseconds, a
+// missing hour or fraction and a leading minus sign.
+fn parse_srt_timestamp(ts: &str) -> Option<i64> {
+    let ts = ts.trim();
+    let (negative, ts) = match ts.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, ts),
+    };
+
+    let (clock, fraction) = ts.split_once([',', '.']).unwrap_or((ts, "0"));
+    let parts: Vec<i64> = clock
+        .split(':')
+        .map(|part| part.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        [minutes, seconds] => (0, minutes, seconds),
+        _ => return None,
+    };
+
+    if fraction.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    // "5" is 500ms, digits past the milliseconds are dropped.
+    let digits: String =
+        fraction.chars().chain("000".chars()).take(3).collect();
+    let milliseconds: i64 = digits.parse().ok()?;
+
+    let ms =
+        hours * 3_600_000 + minutes * 60_000 + seconds * 1000 + milliseconds;
+    Some(if negative { -ms } else { ms })
+}
+
+pub fn generate_srt(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .enumerate()
+        .map(|(i, seg)| {
+            format!(
+                "{}\n{} --> {}\n{}\n",
+                i + 1,
+                format_timestamp(seg.start),
+                format_timestamp(seg.end),
+                seg.raw_text()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subtitles::cue_times;
+
+    #[test]
+    fn skips_malformed_timings() {
+        let content = "1\n00:00:01,000 --> 00:00:02,000\nHello\n\n\
+                       2\n00:00:0x,000 --> 00:00:04,000\nBroken\n\n\
+                       3\n00:00:05,000 --> 00:00:06,000\nWorld\n";
+        let (segments, warnings) = parse_srt_lenient(content);
+
+        assert_eq!(
+            cue_times(&segments),
+            [(1000, 2000, "Hello"), (5000, 6000, "World")]
+        );
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 6);
+        assert!(warnings[0].message.starts_with("Invalid timing"));
+    }
+
+    #[test]
+    fn repairs_timings() {
+        let content = "1\n00:00:03,000 --> 00:00:01,000\nBackwards\n\n\
+                       2\n-00:00:01,000 --> 00:00:04,5\nNegative\n\n\
+                       3\n01:02.250 --> 01:03\nShort\n";
+        let (segments, warnings) = parse_srt_lenient(content);
+
+        assert_eq!(
+            cue_times(&segments),
+            [
+                (1000, 3000, "Backwards"),
+                (0, 4500, "Negative"),
+                (62_250, 63_000, "Short")
+            ]
+        );
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn handles_missing_blank_lines() {
+        let content = "1\n00:00:01,000 --> 00:00:02,000\nHello\n\
+                       2\n00:00:03,000 --> 00:00:04,000\nWorld\n\
+                       00:00:05,000 --> 00:00:06,000\nAgain";
+        let (segments, warnings) = parse_srt_lenient(content);
+
+        assert_eq!(
+            cue_times(&segments),
+            [
+                (1000, 2000, "Hello"),
+                (3000, 4000, "World"),
+                (5000, 6000, "Again")
+            ]
+        );
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message, "Missing cue number");
+    }
+
+    #[test]
+    fn keeps_blank_lines_inside_text() {
+        let content = "1\n00:00:01,000 --> 00:00:02,000\nFirst\n\nSecond\n\n\
+                       2\n00:00:03,000 --> 00:00:04,000\nThird\n";
+        let segments = parse_srt(content).unwrap();
+
+        assert_eq!(
+            cue_times(&segments),
+            [(1000, 2000, "First\nSecond"), (3000, 4000, "Third")]
+        );
+    }
+
+    #[test]
+    fn strips_bom_and_carriage_returns() {
+        let content = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,000\r\n\
+                       <i>Hello</i>\r\n\r\n";
+        assert!(Srt.detect(content));
+
+        let (segments, warnings) = parse_srt_lenient(content);
+        assert_eq!(cue_times(&segments), [(1000, 2000, "Hello")]);
+        assert_eq!(segments[0].raw.as_deref(), Some("<i>Hello</i>"));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn round_trips() {
+        let content = "1\n00:00:01,000 --> 00:00:02,000\nHello\n\n\
+                       2\n01:00:03,040 --> 01:00:04,500\nTwo\nlines\n";
+        let segments = parse_srt(content).unwrap();
+
+        assert_eq!(generate_srt(&segments), content);
+    }
+}
@@ -0,0 +1,158 @@
+use super::{SNIFF_LINES, Segment, SubtitleFormat, parse_ass_timestamp};
+use crate::EngramResult;
+
+// SubViewer 2.0, where every cue is a "start,end" line followed by text
+// with [br] line breaks.
+pub struct SubViewer;
+
+impl SubtitleFormat for SubViewer {
+    fn name(&self) -> &'static str {
+        "SubViewer"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["sub"]
+    }
+
+    fn detect(&self, content: &str) -> bool {
+        content
+            .trim_start_matches('\u{feff}')
+            .lines()
+            .take(SNIFF_LINES)
+            .any(|line| parse_timing(line).is_some())
+    }
+
+    fn parse(&self, content: &str) -> EngramResult<Vec<Segment>> {
+        let mut segments = Vec::new();
+        let mut lines =
+            content.trim_start_matches('\u{feff}').lines().peekable();
+
+        while let Some(line) = lines.next() {
+            let Some((start, end)) = parse_timing(line) else {
+                continue;
+            };
+
+            // A blank line ends the cue, and so does the next timing line
+            // when the blank line was left out.
+            let mut text = Vec::new();
+            while let Some(line) = lines.next_if(|line| {
+                !line.trim().is_empty() && parse_timing(line).is_none()
+            }) {
+                text.extend(line.split("[br]").map(str::trim));
+            }
+            text.retain(|line| !line.is_empty());
+            let raw = text.join("\n");
+
+            let segment = Segment::from_markup(start, end, raw);
+            if !segment.text.is_empty() {
+                segments.push(segment);
+            }
+        }
+
+        Ok(segments)
+    }
+
+    fn serialize(&self, segments: &[Segment]) -> String {
+        let cues = segments
+            .iter()
+            .map(|seg| {
+                format!(
+                    "{},{}\n{}\n",
+                    format_subviewer_timestamp(seg.start),
+                    format_subviewer_timestamp(seg.end),
+                    seg.raw_text().replace('\n', "[br]")
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        format!(
+            "[INFORMATION]\n[TITLE]\n[AUTHOR]\n[SOURCE]\n[PRG]\n[FILEPATH]\n\
+             [DELAY]0\n[CD TRACK]0\n[COMMENT]\n[END INFORMATION]\n\
+             [SUBTITLE]\n[COLF]&HFFFFFF,[STYLE]no,[SIZE]18,[FONT]Arial\n\
+             {cues}"
+        )
+    }
+}
+
+// "00:00:01.50,00:00:03.00", the same clock as ASS.
+fn parse_timing(line: &str) -> Option<(i64, i64)> {
+    let (start, end) = line.trim().split_once(',')?;
+    let start = parse_ass_timestamp(start).ok()?;
+    let end = parse_ass_timestamp(end).ok()?;
+    Some((start, end))
+}
+
+fn format_subviewer_timestamp(ms: i64) -> String {
+    let hours = ms / 3_600_000;
+    let minutes = (ms % 3_600_000) / 60_000;
+    let seconds = (ms % 60_000) / 1000;
+    let centiseconds = (ms % 1000) / 10;
+    format!("{hours:02}:{minutes:02}:{seconds:02}.{centiseconds:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subtitles::cue_times;
+
+    const HEADER: &str = "[INFORMATION]\n[TITLE]\n[END INFORMATION]\n\
+                          [SUBTITLE]\n[COLF]&HFFFFFF,[STYLE]no,[SIZE]18\n";
+
+    #[test]
+    fn parses_cues() {
+        let content = format!(
+            "{HEADER}00:00:01.50,00:00:03.00\nTwo[br]lines\n\n\
+             01:00:00.00,01:00:01.00\nNext\n"
+        );
+        let segments = SubViewer.parse(&content).unwrap();
+
+        assert_eq!(
+            cue_times(&segments),
+            [(1500, 3000, "Two\nlines"), (3_600_000, 3_601_000, "Next")]
+        );
+    }
+
+    #[test]
+    fn skips_malformed_timings() {
+        let content = format!(
+            "{HEADER}00:00:0x.00,00:00:02.00\nBroken\n\n\
+             00:00:03.00\nNo end\n\n\
+             00:00:05.00,00:00:06.00\nGood\n"
+        );
+        let segments = SubViewer.parse(&content).unwrap();
+
+        assert_eq!(cue_times(&segments), [(5000, 6000, "Good")]);
+    }
+
+    #[test]
+    fn handles_missing_blank_lines() {
+        let content = "00:00:01.00,00:00:02.00\nHello\n\
+                       00:00:03.00,00:00:04.00\nWorld\n";
+        let segments = SubViewer.parse(content).unwrap();
+
+        assert_eq!(
+            cue_times(&segments),
+            [(1000, 2000, "Hello"), (3000, 4000, "World")]
+        );
+    }
+
+    #[test]
+    fn strips_bom_and_carriage_returns() {
+        let content = "\u{feff}00:00:01.00,00:00:02.00\r\nHello\r\n\r\n";
+        assert!(SubViewer.detect(content));
+        assert_eq!(
+            cue_times(&SubViewer.parse(content).unwrap()),
+            [(1000, 2000, "Hello")]
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        let content = "00:00:01.50,00:00:03.00\nTwo[br]lines\n";
+        let segments = SubViewer.parse(content).unwrap();
+        let parsed = SubViewer.parse(&SubViewer.serialize(&segments)).unwrap();
+
+        assert_eq!(cue_times(&parsed), [(1500, 3000, "Two\nlines")]);
+    }
+}
@@ -0,0 +1,253 @@
+use super::{ParseWarning, Segment, SubtitleFormat, format_timestamp};
+use crate::{EngramResult, errors::EngramError};
+
+pub struct WebVtt;
+
+impl SubtitleFormat for WebVtt {
+    fn name(&self) -> &'static str {
+        "WebVTT"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["vtt"]
+    }
+
+    fn detect(&self, content: &str) -> bool {
+        content.trim_start_matches('\u{feff}').starts_with("WEBVTT")
+    }
+
+    fn parse(&self, content: &str) -> EngramResult<Vec<Segment>> {
+        parse_vtt(content)
+    }
+
+    fn parse_with_warnings(
+        &self,
+        content: &str,
+    ) -> EngramResult<(Vec<Segment>, Vec<ParseWarning>)> {
+        parse_vtt_lenient(content)
+    }
+
+    fn serialize(&self, segments: &[Segment]) -> String {
+        generate_vtt(segments)
+    }
+}
+
+pub fn parse_vtt(content: &str) -> EngramResult<Vec<Segment>> {
+    Ok(parse_vtt_lenient(content)?.0)
+}
+
+// Only a missing header fails the file, cues with a timestamp we cannot read
+// are skipped with a warning.
+pub fn parse_vtt_lenient(
+    content: &str,
+) -> EngramResult<(Vec<Segment>, Vec<ParseWarning>)> {
+    let content = content
+        .trim_start_matches('\u{feff}')
+        .replace("\r\n", "\n")
+        .replace('\r', "\n");
+
+    let mut blocks = content.split("\n\n");
+
+    // The header is "WEBVTT", optionally followed by a title and metadata.
+    let header = blocks.next().unwrap_or_default();
+    if !header.starts_with("WEBVTT") {
+        return Err(EngramError::SubtitleParseError(
+            "Missing WEBVTT header".into(),
+        ));
+    }
+
+    let mut segments = Vec::new();
+    let mut warnings = Vec::new();
+    // 0-based line the current block starts on.
+    let mut line = header.matches('\n').count() + 2;
+
+    for block in blocks {
+        let block_line =
+            line + (block.len() - block.trim_start_matches('\n').len());
+        line += block.matches('\n').count() + 2;
+
+        let block = block.trim_matches('\n');
+        if block.is_empty()
+            || block.starts_with("NOTE")
+            || block.starts_with("STYLE")
+            || block.starts_with("REGION")
+        {
+            continue;
+        }
+
+        // The cue identifier is optional. Cue text cannot hold "-->", so a
+        // timing line further down starts a cue whose blank line was left
+        // out.
+        let lines: Vec<&str> = block.lines().collect();
+        let timings: Vec<usize> = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| line.contains("-->"))
+            .map(|(i, _)| i)
+            .collect();
+        if timings.first().is_none_or(|&first| first > 1) {
+            continue;
+        }
+
+        for (k, &i) in timings.iter().enumerate() {
+            let timing = lines[i];
+            // The text runs up to the next cue, minus its identifier when it
+            // is a number.
+            let next = timings.get(k + 1).copied().unwrap_or(lines.len());
+            let text_end = if next < lines.len()
+                && next - 1 > i
+                && lines[next - 1].trim().parse::<u64>().is_ok()
+            {
+                next - 1
+            } else {
+                next
+            };
+
+            let Some((start, rest)) = timing.split_once("-->") else {
+                continue;
+            };
+            // Cue settings (position, align, ...) follow the end timestamp.
+            let end = rest.split_whitespace().next().unwrap_or_default();
+
+            let (start, end) =
+                match (parse_vtt_timestamp(start), parse_vtt_timestamp(end)) {
+                    (Ok(start), Ok(end)) => (start, end),
+                    _ => {
+                        warnings.push(ParseWarning {
+                            line: block_line + i + 1,
+                            message: format!("Invalid timing: {timing}"),
+                        });
+                        continue;
+                    }
+                };
+
+            let raw = lines[i + 1..text_end].join("\n");
+            let segment = Segment::from_markup(start, end, raw);
+            if !segment.text.is_empty() {
+                segments.push(segment);
+            }
+        }
+    }
+
+    Ok((segments, warnings))
+}
+
+// Accepts both "hh:mm:ss.ttt" and the hour-less "mm:ss.ttt".
+pub fn parse_vtt_timestamp(ts: &str) -> EngramResult<i64> {
+    let invalid =
+        || EngramError::SubtitleParseError(format!("Invalid timestamp: {ts}"));
+
+    let (clock, millis) = ts.trim().split_once('.').ok_or_else(invalid)?;
+    let parts: Vec<i64> = clock
+        .split(':')
+        .map(|part| part.parse().map_err(|_| invalid()))
+        .collect::<EngramResult<_>>()?;
+    let milliseconds: i64 = millis.parse().map_err(|_| invalid())?;
+
+    let (hours, minutes, seconds) = match parts.as_slice() {
+        [hours, minutes, seconds] => (*hours, *minutes, *seconds),
+        [minutes, seconds] => (0, *minutes, *seconds),
+        _ => return Err(invalid()),
+    };
+
+    Ok(hours * 3_600_000 + minutes * 60_000 + seconds * 1000 + milliseconds)
+}
+
+// WebVTT uses a dot before the milliseconds instead of a comma.
+pub fn format_vtt_timestamp(ms: i64) -> String {
+    format_timestamp(ms).replacen(',', ".", 1)
+}
+
+pub fn generate_vtt(segments: &[Segment]) -> String {
+    let cues = segments
+        .iter()
+        .map(|seg| {
+            format!(
+                "{} --> {}\n{}\n",
+                format_vtt_timestamp(seg.start),
+                format_vtt_timestamp(seg.end),
+                seg.raw_text()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!("WEBVTT\n\n{cues}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subtitles::cue_times;
+
+    #[test]
+    fn skips_malformed_vtt_timestamps() {
+        let content = "WEBVTT\n\n00:01.000 --> 00:02.000\nHello\n\n\
+                       cue\n00:0x.000 --> 00:04.000\nBroken\n\n\
+                       00:05.000 --> 00:06\nNo fraction\n\n\
+                       00:07.000 --> 00:08.000\nWorld\n";
+        let (segments, warnings) = parse_vtt_lenient(content).unwrap();
+
+        assert_eq!(
+            cue_times(&segments),
+            [(1000, 2000, "Hello"), (7000, 8000, "World")]
+        );
+        let lines: Vec<usize> = warnings.iter().map(|w| w.line).collect();
+        assert_eq!(lines, [7, 10]);
+    }
+
+    #[test]
+    fn rejects_vtt_without_header() {
+        assert!(parse_vtt("00:01.000 --> 00:02.000\nHello\n").is_err());
+    }
+
+    #[test]
+    fn handles_vtt_missing_blank_lines() {
+        let content = "WEBVTT\n\n1\n00:01.000 --> 00:02.000\nHello\n\
+                       2\n00:03.000 --> 00:04.000\nWorld\n\
+                       00:05.000 --> 00:06.000\nAgain\n";
+        let segments = parse_vtt(content).unwrap();
+
+        assert_eq!(
+            cue_times(&segments),
+            [
+                (1000, 2000, "Hello"),
+                (3000, 4000, "World"),
+                (5000, 6000, "Again")
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_vtt_notes_and_cue_settings() {
+        let content = "WEBVTT - Title\nKind: captions\n\n\
+                       NOTE a comment\n\n\
+                       STYLE\n::cue { color: red }\n\n\
+                       01:00:01.500 --> 01:00:02.000 align:start line:0\n\
+                       <v Bob>Hello</v> &amp; bye\n";
+        let segments = parse_vtt(content).unwrap();
+
+        assert_eq!(
+            cue_times(&segments),
+            [(3_601_500, 3_602_000, "Hello & bye")]
+        );
+    }
+
+    #[test]
+    fn strips_vtt_bom_and_carriage_returns() {
+        let content =
+            "\u{feff}WEBVTT\r\n\r\n00:01.000 --> 00:02.000\r\nHello\r\n";
+        let segments = parse_vtt(content).unwrap();
+
+        assert_eq!(cue_times(&segments), [(1000, 2000, "Hello")]);
+    }
+
+    #[test]
+    fn round_trips_vtt() {
+        let content = "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nHello\n\n\
+                       01:00:03.040 --> 01:00:04.500\nTwo\nlines\n";
+        let segments = parse_vtt(content).unwrap();
+
+        assert_eq!(generate_vtt(&segments), content);
+    }
+}
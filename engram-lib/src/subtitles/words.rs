@@ -0,0 +1,179 @@
+use serde_json::{Value, json};
+
+// A word and when it is spoken, in milliseconds. Only Whisper gives us
+// these.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Word {
+    pub start: i64,
+    pub end: i64,
+    pub text: String,
+}
+
+// Whisper tokens are pieces of words, as raw bytes since a character can be
+// split across two of them. A piece that starts with a space begins a new
+// word, anything else belongs to the previous one.
+pub fn join_tokens<'a>(
+    tokens: impl IntoIterator<Item = (&'a [u8], i64, i64)>,
+) -> Vec<Word> {
+    let mut pieces: Vec<(Vec<u8>, i64, i64)> = Vec::new();
+
+    for (bytes, start, end) in tokens {
+        match pieces.last_mut() {
+            Some((word, _, word_end))
+                if !bytes.first().is_some_and(u8::is_ascii_whitespace) =>
+            {
+                word.extend_from_slice(bytes);
+                *word_end = (*word_end).max(end);
+            }
+            _ => pieces.push((bytes.to_vec(), start, end)),
+        }
+    }
+
+    pieces
+        .into_iter()
+        .map(|(bytes, start, end)| Word {
+            start,
+            end,
+            text: String::from_utf8_lossy(&bytes).trim().to_string(),
+        })
+        .filter(|word| !word.text.is_empty())
+        .collect()
+}
+
+// The span from the first to the last word of where `query` is said. The
+// exact phrase is preferred, otherwise every word holding one of its terms
+// is covered. None when no word matches.
+pub fn match_span(words: &[Word], query: &str) -> Option<(i64, i64)> {
+    let terms = search_terms(query);
+    if terms.is_empty() {
+        return None;
+    }
+
+    // Terms of every word along with the word they came from, as the search
+    // index sees them: "don't" is "don" followed by "t".
+    let tokens: Vec<(String, usize)> = words
+        .iter()
+        .enumerate()
+        .flat_map(|(i, word)| {
+            search_terms(&word.text)
+                .into_iter()
+                .map(move |term| (term, i))
+        })
+        .collect();
+
+    let phrase = tokens.windows(terms.len()).find(|window| {
+        window
+            .iter()
+            .zip(&terms)
+            .all(|((token, _), term)| token == term)
+    });
+    let (first, last) = match phrase {
+        Some(window) => (window[0].1, window[window.len() - 1].1),
+        None => {
+            let mut matched = tokens
+                .iter()
+                .filter(|(token, _)| terms.contains(token))
+                .map(|(_, i)| *i);
+            let first = matched.next()?;
+            (first, matched.next_back().unwrap_or(first))
+        }
+    };
+
+    Some((words[first].start, words[last].end))
+}
+
+// Lowercase runs of letters and digits, which is how the search index
+// tokenizes text.
+fn search_terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+// Stored as `[[start, end, "text"], ...]`.
+pub fn words_to_json(words: &[Word]) -> Value {
+    Value::Array(
+        words
+            .iter()
+            .map(|word| json!([word.start, word.end, word.text]))
+            .collect(),
+    )
+}
+
+pub fn words_from_json(value: &Value) -> Vec<Word> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|word| {
+            Some(Word {
+                start: word[0].as_i64()?,
+                end: word[1].as_i64()?,
+                text: word[2].as_str()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(start: i64, end: i64, text: &str) -> Word {
+        Word {
+            start,
+            end,
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn joins_token_pieces_into_words() {
+        // The "é" of "café" is split across two tokens.
+        let tokens: [(&[u8], i64, i64); 6] = [
+            (b"Don", 0, 200),
+            (b"'t", 200, 300),
+            (b" go", 300, 500),
+            (b" caf\xc3", 600, 800),
+            (b"\xa9.", 800, 900),
+            (b" ", 900, 900),
+        ];
+
+        assert_eq!(
+            join_tokens(tokens),
+            [
+                word(0, 300, "Don't"),
+                word(300, 500, "go"),
+                word(600, 900, "café."),
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_phrases_and_scattered_terms() {
+        let words = [
+            word(0, 300, "Don't"),
+            word(300, 500, "go"),
+            word(500, 700, "there,"),
+            word(800, 1000, "go"),
+            word(1000, 1200, "home."),
+        ];
+
+        assert_eq!(match_span(&words, "go home"), Some((800, 1200)));
+        assert_eq!(match_span(&words, "DON'T GO"), Some((0, 500)));
+        // Not said as a phrase, so every word with one of the terms counts.
+        assert_eq!(match_span(&words, "home there"), Some((500, 1200)));
+        assert_eq!(match_span(&words, "there"), Some((500, 700)));
+        assert_eq!(match_span(&words, "elsewhere"), None);
+        assert_eq!(match_span(&words, "?!"), None);
+        assert_eq!(match_span(&[], "go"), None);
+    }
+
+    #[test]
+    fn round_trips_words_through_json() {
+        let words = vec![word(0, 300, "Don't"), word(300, 500, "go")];
+        assert_eq!(words_from_json(&words_to_json(&words)), words);
+        assert!(words_from_json(&json!([[0, "bad"]])).is_empty());
+    }
+}
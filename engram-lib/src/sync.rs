@@ -0,0 +1,403 @@
+use crate::subtitles::Segment;
+
+// What `media::extract_audio` produces.
+const SAMPLE_RATE: usize = 16_000;
+
+// Speech is detected on 10ms frames, which is also the resolution of the
+// offsets we find.
+const FRAME_MS: i64 = 10;
+const FRAME_SAMPLES: usize = SAMPLE_RATE * FRAME_MS as usize / 1000;
+
+// Pauses between words shorter than this still count as speech.
+const MAX_GAP_FRAMES: usize = 30;
+
+const MAX_OFFSET_MS: i64 = 60_000;
+
+// Local offsets are searched this far around the global one when looking
+// for drift.
+const WINDOW_SEARCH_MS: i64 = 1500;
+const WINDOWS: usize = 8;
+const MIN_WINDOW_SEGMENTS: usize = 10;
+
+// Releases made for 23.976, 24 and 25 fps play at slightly different speeds,
+// which is where most drift comes from.
+const FRAME_RATE_SCALES: &[f64] = &[
+    1.0,
+    25.0 / 23.976,
+    23.976 / 25.0,
+    25.0 / 24.0,
+    24.0 / 25.0,
+    24.0 / 23.976,
+    23.976 / 24.0,
+];
+
+// Maps a subtitle time `t` to `t * scale + offset`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncCorrection {
+    pub offset: i64,
+    pub scale: f64,
+}
+
+impl Default for SyncCorrection {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            scale: 1.0,
+        }
+    }
+}
+
+impl SyncCorrection {
+    pub fn apply(&self, ms: i64) -> i64 {
+        ((ms as f64 * self.scale).round() as i64 + self.offset).max(0)
+    }
+
+    pub fn apply_all(&self, segments: &mut [Segment]) {
+        for segment in segments {
+            segment.start = self.apply(segment.start);
+            segment.end = self.apply(segment.end);
+            for word in &mut segment.words {
+                word.start = self.apply(word.start);
+                word.end = self.apply(word.end);
+            }
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.offset == 0 && self.scale == 1.0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SyncResult {
+    pub correction: SyncCorrection,
+    // Share of subtitle time that lands on speech minus the share that lands
+    // on silence, from -1 to 1, after and before the correction.
+    pub score: f64,
+    pub original_score: f64,
+}
+
+// Finds the correction that lines `segments` up best with the speech in
+// `audio`, mono samples at 16kHz.
+pub fn estimate(segments: &[Segment], audio: &[f32]) -> SyncResult {
+    let speech = SpeechTrack::new(audio);
+    let original_score = speech.score(segments, &SyncCorrection::default());
+
+    let mut best = (SyncCorrection::default(), original_score);
+    for &scale in FRAME_RATE_SCALES {
+        let found =
+            speech.best_offset(segments, scale, -MAX_OFFSET_MS..=MAX_OFFSET_MS);
+        if found.1 > best.1 {
+            best = found;
+        }
+    }
+
+    if let Some(refined) = speech.fit_drift(segments, best.0) {
+        let score = speech.score(segments, &refined);
+        if score > best.1 {
+            best = (refined, score);
+        }
+    }
+
+    SyncResult {
+        correction: best.0,
+        score: best.1,
+        original_score,
+    }
+}
+
+struct SpeechTrack {
+    // Running sum of +1 for speech and -1 for silence per frame, so the
+    // score of any span is a subtraction.
+    prefix: Vec<i64>,
+}
+
+impl SpeechTrack {
+    fn new(audio: &[f32]) -> Self {
+        let speech = fill_gaps(detect_speech(audio), MAX_GAP_FRAMES);
+
+        let mut prefix = Vec::with_capacity(speech.len() + 1);
+        prefix.push(0);
+        for is_speech in speech {
+            let last = *prefix.last().unwrap();
+            prefix.push(last + if is_speech { 1 } else { -1 });
+        }
+
+        Self { prefix }
+    }
+
+    fn frames(&self) -> i64 {
+        self.prefix.len() as i64 - 1
+    }
+
+    // Time outside of the audio counts as silence.
+    fn span(&self, from: i64, to: i64) -> i64 {
+        let frames = self.frames();
+        let (a, b) = (from.clamp(0, frames), to.clamp(0, frames));
+        let outside = (to - from) - (b - a);
+        self.prefix[b as usize] - self.prefix[a as usize] - outside
+    }
+
+    fn score(&self, segments: &[Segment], correction: &SyncCorrection) -> f64 {
+        let (mut total, mut length) = (0, 0);
+        for segment in segments {
+            let from = correction.apply(segment.start) / FRAME_MS;
+            let to = correction.apply(segment.end) / FRAME_MS;
+            if to > from {
+                total += self.span(from, to);
+                length += to - from;
+            }
+        }
+
+        if length == 0 {
+            0.0
+        } else {
+            total as f64 / length as f64
+        }
+    }
+
+    fn best_offset(
+        &self,
+        segments: &[Segment],
+        scale: f64,
+        offsets: std::ops::RangeInclusive<i64>,
+    ) -> (SyncCorrection, f64) {
+        let mut best = (SyncCorrection { offset: 0, scale }, f64::MIN);
+
+        let mut offset = *offsets.start();
+        while offset <= *offsets.end() {
+            let correction = SyncCorrection { offset, scale };
+            let score = self.score(segments, &correction);
+            if score > best.1 {
+                best = (correction, score);
+            }
+            offset += FRAME_MS;
+        }
+
+        best
+    }
+
+    // Looks for the local offset in windows along the file on top of
+    // `correction` and fits a line through them. A slope means the subtitles
+    // run slightly faster or slower than the audio.
+    fn fit_drift(
+        &self,
+        segments: &[Segment],
+        correction: SyncCorrection,
+    ) -> Option<SyncCorrection> {
+        let size = (segments.len() / WINDOWS).max(MIN_WINDOW_SEGMENTS);
+
+        let mut points = Vec::new();
+        for window in segments.chunks(size) {
+            if window.len() < MIN_WINDOW_SEGMENTS {
+                continue;
+            }
+
+            let (local, score) = self.best_offset(
+                window,
+                correction.scale,
+                correction.offset - WINDOW_SEARCH_MS
+                    ..=correction.offset + WINDOW_SEARCH_MS,
+            );
+            if score <= 0.0 {
+                continue;
+            }
+
+            let middle = window[window.len() / 2].start;
+            points.push((
+                correction.apply(middle) as f64,
+                (local.offset - correction.offset) as f64,
+            ));
+        }
+
+        if points.len() < 3 {
+            return None;
+        }
+
+        // Least squares for delta = a + b * t.
+        let n = points.len() as f64;
+        let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let mean_d = points.iter().map(|(_, d)| d).sum::<f64>() / n;
+        let covariance: f64 = points
+            .iter()
+            .map(|(t, d)| (t - mean_t) * (d - mean_d))
+            .sum();
+        let variance: f64 =
+            points.iter().map(|(t, _)| (t - mean_t).powi(2)).sum();
+        if variance == 0.0 {
+            return None;
+        }
+
+        let b = covariance / variance;
+        let a = mean_d - b * mean_t;
+
+        // t' + a + b * t' folded into a single scale and offset.
+        Some(SyncCorrection {
+            offset: ((1.0 + b) * correction.offset as f64 + a).round() as i64,
+            scale: (1.0 + b) * correction.scale,
+        })
+    }
+}
+
+// Whether each frame is louder than the noise floor by a good margin. The
+// floor and the level of speech are taken from the quiet and loud ends of
+// the file so the threshold adapts to how it was mixed.
+fn detect_speech(audio: &[f32]) -> Vec<bool> {
+    let energies: Vec<f32> = audio
+        .chunks(FRAME_SAMPLES)
+        .map(|frame| {
+            let power =
+                frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32;
+            10.0 * (power + 1e-10).log10()
+        })
+        .collect();
+
+    if energies.is_empty() {
+        return Vec::new();
+    }
+
+    let mut sorted = energies.clone();
+    sorted.sort_by(f32::total_cmp);
+    let floor = sorted[sorted.len() / 10];
+    let loud = sorted[sorted.len() * 9 / 10];
+    let threshold = floor + (loud - floor) * 0.35;
+
+    energies.iter().map(|&energy| energy > threshold).collect()
+}
+
+fn fill_gaps(mut speech: Vec<bool>, max_gap: usize) -> Vec<bool> {
+    let mut last_speech: Option<usize> = None;
+    for i in 0..speech.len() {
+        if !speech[i] {
+            continue;
+        }
+        if let Some(last) = last_speech
+            && i - last - 1 <= max_gap
+        {
+            speech[last + 1..i].fill(true);
+        }
+        last_speech = Some(i);
+    }
+
+    speech
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Speech at known times, as (start, end) in milliseconds, over a quiet
+    // background.
+    fn speech_audio(spans: &[(i64, i64)], length: i64) -> Vec<f32> {
+        let samples = |ms: i64| ms as usize * SAMPLE_RATE / 1000;
+        let mut seed = 1u32;
+        let mut audio: Vec<f32> = (0..samples(length))
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 16) as f32 / 65_536.0 * 0.002 - 0.001
+            })
+            .collect();
+
+        for &(start, end) in spans {
+            let first = samples(start);
+            for (i, sample) in audio[first..samples(end)].iter_mut().enumerate()
+            {
+                let t = (first + i) as f32 / SAMPLE_RATE as f32;
+                *sample += 0.5 * (t * 200.0 * std::f32::consts::TAU).sin();
+            }
+        }
+
+        audio
+    }
+
+    // Lines of uneven length and spacing so only one offset fits.
+    fn speech_spans(length: i64) -> Vec<(i64, i64)> {
+        let mut spans = Vec::new();
+        let (mut time, mut step) = (2000, 7u32);
+        while time < length - 5000 {
+            step = step.wrapping_mul(31).wrapping_add(17) % 997;
+            let duration = 800 + (step as i64 % 17) * 100;
+            let gap = 500 + (step as i64 % 11) * 100;
+            spans.push((time, time + duration));
+            time += duration + gap;
+        }
+        spans
+    }
+
+    fn segments(
+        spans: &[(i64, i64)],
+        to_subtitle: impl Fn(i64) -> i64,
+    ) -> Vec<Segment> {
+        spans
+            .iter()
+            .map(|&(start, end)| Segment {
+                start: to_subtitle(start),
+                end: to_subtitle(end),
+                text: "line".into(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_a_constant_offset() {
+        let spans = speech_spans(120_000);
+        let audio = speech_audio(&spans, 120_000);
+        let late = segments(&spans, |ms| ms + 2340);
+
+        let result = estimate(&late, &audio);
+        assert_eq!(result.correction.scale, 1.0);
+        assert!((result.correction.offset + 2340).abs() <= FRAME_MS);
+        assert!(result.score > 0.9);
+        assert!(result.original_score < 0.5);
+    }
+
+    #[test]
+    fn fits_drift_between_frame_rates() {
+        let spans = speech_spans(600_000);
+        let audio = speech_audio(&spans, 600_000);
+        // Subtitles that run 0.25% fast and start 700ms early, which none of
+        // the frame rate scales cover on its own.
+        let fast = segments(&spans, |ms| ((ms - 700) as f64 / 1.0025) as i64);
+
+        let result = estimate(&fast, &audio);
+        assert!((result.correction.scale - 1.0025).abs() < 0.0002);
+        assert!((result.correction.offset - 700).abs() <= 50);
+        assert!(result.score > 0.9);
+    }
+
+    #[test]
+    fn applies_corrections() {
+        let correction = SyncCorrection {
+            offset: -500,
+            scale: 1.5,
+        };
+        assert_eq!(correction.apply(1000), 1000);
+        assert_eq!(correction.apply(100), 0);
+        assert!(SyncCorrection::default().is_identity());
+
+        let mut segments = vec![Segment {
+            start: 1000,
+            end: 2000,
+            words: vec![crate::subtitles::Word {
+                start: 1000,
+                end: 1500,
+                text: "line".into(),
+            }],
+            ..Default::default()
+        }];
+        correction.apply_all(&mut segments);
+        assert_eq!((segments[0].start, segments[0].end), (1000, 2500));
+        assert_eq!(
+            (segments[0].words[0].start, segments[0].words[0].end),
+            (1000, 1750)
+        );
+    }
+
+    #[test]
+    fn fills_short_pauses() {
+        let speech = |s: &str| s.chars().map(|c| c == '#').collect::<Vec<_>>();
+        assert_eq!(fill_gaps(speech("#..#...#.."), 2), speech("####...#.."));
+        assert_eq!(fill_gaps(speech("..."), 2), speech("..."));
+    }
+}
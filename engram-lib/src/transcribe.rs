@@ -1,14 +1,16 @@
-use std::fs;
-use std::path::PathBuf;
-use ureq;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
 use whisper_rs::{
     FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters,
 };
 
+use crate::EngramResult;
+use crate::cancel::CancellationToken;
 use crate::errors::EngramError;
+use crate::models::ModelManager;
 use crate::subtitles;
-use crate::{EngramResult, get_engram_dir};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TranscriberModel {
     Tiny,
     Base,
@@ -17,53 +19,155 @@ pub enum TranscriberModel {
     Large,
 }
 
-pub struct Transcriber {
-    ctx: WhisperContext,
+impl TranscriberModel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TranscriberModel::Tiny => "tiny",
+            TranscriberModel::Base => "base",
+            TranscriberModel::Small => "small",
+            TranscriberModel::Medium => "medium",
+            TranscriberModel::Large => "large",
+        }
+    }
 }
 
-impl Transcriber {
-    pub fn load_model(model: TranscriberModel) -> EngramResult<PathBuf> {
-        let link = match model {
-            TranscriberModel::Tiny => {
-                "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-tiny-q5_1.bin?download=true"
-            }
-            TranscriberModel::Base => {
-                "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-base-q5_1.bin?download=true"
-            }
-            TranscriberModel::Small => {
-                "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-small-q5_1.bin?download=true"
-            }
-            TranscriberModel::Medium => {
-                "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-medium-q5_0.bin?download=true"
-            }
-            TranscriberModel::Large => {
-                "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-large-v3-q5_0.bin?download=true"
-            }
-        };
+impl std::str::FromStr for TranscriberModel {
+    type Err = String;
 
-        let output = get_engram_dir()?.join(format!("{}.bin", model as u8));
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tiny" => Ok(TranscriberModel::Tiny),
+            "base" => Ok(TranscriberModel::Base),
+            "small" => Ok(TranscriberModel::Small),
+            "medium" => Ok(TranscriberModel::Medium),
+            "large" => Ok(TranscriberModel::Large),
+            _ => Err(format!("Unknown model: {s}")),
+        }
+    }
+}
 
-        if !output.exists() {
-            println!("Downloading model from {}.", link);
-            let tmp = output.with_extension("tmp");
-            let mut file = fs::File::create(&tmp)?;
+// How Whisper decodes the audio.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscribeOptions {
+    // ISO 639-1 code of the spoken language, detected from the first 30
+    // seconds when None.
+    pub language: Option<String>,
+    // Whisper picks a number based on the CPU when None.
+    pub threads: Option<usize>,
+    // Beam search with this width instead of greedy decoding. Slower but
+    // more accurate.
+    pub beam_size: Option<usize>,
+    // Decode again at higher temperatures when the output looks like a
+    // repetition loop or gibberish.
+    pub temperature_fallback: bool,
+    // Text Whisper is primed with, such as character names it would
+    // otherwise misspell.
+    pub initial_prompt: Option<String>,
+    // Write English subtitles whatever language is spoken.
+    pub translate: bool,
+}
 
-            std::io::copy(
-                &mut ureq::get(link).call()?.into_body().into_reader(),
-                &mut file,
-            )?;
+impl Default for TranscribeOptions {
+    fn default() -> Self {
+        Self {
+            language: Some("en".into()),
+            threads: None,
+            beam_size: None,
+            temperature_fallback: true,
+            initial_prompt: None,
+            translate: false,
+        }
+    }
+}
 
-            fs::rename(&tmp, &output)?;
+impl TranscribeOptions {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "language": self.language,
+            "threads": self.threads,
+            "beam_size": self.beam_size,
+            "temperature_fallback": self.temperature_fallback,
+            "initial_prompt": self.initial_prompt,
+            "translate": self.translate,
+        })
+    }
+
+    // Missing keys keep their default, so options stored by an older
+    // version still load.
+    pub fn from_json(value: &Value) -> Self {
+        let default = Self::default();
+        let number = |key: &str| value[key].as_u64().map(|n| n as usize);
+
+        Self {
+            // Null means detected, which is not the same as missing.
+            language: match value.get("language") {
+                Some(language) => language.as_str().map(String::from),
+                None => default.language,
+            },
+            threads: number("threads"),
+            beam_size: number("beam_size"),
+            temperature_fallback: value["temperature_fallback"]
+                .as_bool()
+                .unwrap_or(default.temperature_fallback),
+            initial_prompt: value["initial_prompt"].as_str().map(String::from),
+            translate: value["translate"]
+                .as_bool()
+                .unwrap_or(default.translate),
         }
+    }
+
+    fn params(&self) -> FullParams<'_, '_> {
+        let strategy = match self.beam_size {
+            Some(beam_size) => SamplingStrategy::BeamSearch {
+                beam_size: beam_size as i32,
+                patience: -1.0,
+            },
+            None => SamplingStrategy::Greedy { best_of: 1 },
+        };
+        let mut params = FullParams::new(strategy);
+
+        // Needed for the timing of each word.
+        params.set_token_timestamps(true);
+        // Whisper still calls Javanese by its old code.
+        params.set_language(Some(match self.language.as_deref() {
+            Some("jv") => "jw",
+            Some(language) => language,
+            None => "auto",
+        }));
+        params.set_translate(self.translate);
+        if let Some(threads) = self.threads {
+            params.set_n_threads(threads as i32);
+        }
+        if !self.temperature_fallback {
+            params.set_temperature_inc(0.0);
+        }
+        // Whisper takes a C string, which cannot hold a nul byte.
+        if let Some(prompt) = &self.initial_prompt {
+            params.set_initial_prompt(&prompt.replace('\0', ""));
+        }
+
+        params
+    }
+}
 
-        Ok(output)
+pub struct Transcriber {
+    ctx: WhisperContext,
+}
+
+impl Transcriber {
+    // Downloads the model on first use, see `ModelManager`.
+    pub fn load_model(model: TranscriberModel) -> EngramResult<PathBuf> {
+        ModelManager::open()?.install(model, |_, _| {})
     }
 
     pub fn new(model: TranscriberModel) -> EngramResult<Self> {
-        let model_path = Self::load_model(model)?;
+        Self::from_file(&Self::load_model(model)?)
+    }
 
+    // Any GGML model on disk, such as an imported fine-tune.
+    pub fn from_file(path: &Path) -> EngramResult<Self> {
         let ctx = WhisperContext::new_with_params(
-            model_path.to_string_lossy().as_ref(),
+            path.to_string_lossy().as_ref(),
             WhisperContextParameters::default(),
         )?;
 
@@ -74,25 +178,119 @@ impl Transcriber {
         &self,
         audio: &[f32],
     ) -> EngramResult<Box<[subtitles::Segment]>> {
+        self.transcribe_cancellable(
+            audio,
+            &TranscribeOptions::default(),
+            &CancellationToken::new(),
+            |_| {},
+        )
+    }
+
+    // `on_progress` receives the percentage of the audio processed so far.
+    pub fn transcribe_cancellable<F>(
+        &self,
+        audio: &[f32],
+        options: &TranscribeOptions,
+        cancel: &CancellationToken,
+        on_progress: F,
+    ) -> EngramResult<Box<[subtitles::Segment]>>
+    where
+        F: FnMut(i32) + 'static,
+    {
         let mut state = self
             .ctx
             .create_state()
             .map_err(|e| EngramError::WhisperError(e))?;
-        let params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        let mut params = options.params();
 
-        state
-            .full(params, audio)
-            .map_err(|e| EngramError::WhisperError(e))?;
+        let token = cancel.clone();
+        params.set_abort_callback_safe(move || token.is_cancelled());
+        params.set_progress_callback_safe(on_progress);
+
+        let result = state.full(params, audio);
+
+        // An aborted run surfaces as a generic Whisper error, report it as a
+        // cancellation instead.
+        cancel.check()?;
+        result.map_err(|e| EngramError::WhisperError(e))?;
 
+        // The language that was asked for or detected, English when
+        // translating.
+        let language = if options.translate {
+            Some("en")
+        } else {
+            whisper_rs::get_lang_str(state.full_lang_id_from_state())
+        }
+        .and_then(subtitles::normalize_language)
+        .map(String::from);
+
+        // Tokens from the end of text one on are markers such as timestamps
+        // rather than text.
+        let text_tokens = self.ctx.token_eot();
+
+        // Whisper reports timestamps in centiseconds.
         let result = state
             .as_iter()
-            .map(|segment| subtitles::Segment {
-                start: segment.start_timestamp(),
-                end: segment.end_timestamp(),
-                text: segment.to_string(),
+            .map(|segment| {
+                let tokens: Vec<(&[u8], i64, i64)> = (0..segment.n_tokens())
+                    .filter_map(|i| segment.get_token(i))
+                    .filter(|token| token.token_id() < text_tokens)
+                    .filter_map(|token| {
+                        let data = token.token_data();
+                        Some((
+                            token.to_bytes().ok()?,
+                            data.t0 * 10,
+                            data.t1 * 10,
+                        ))
+                    })
+                    .collect();
+
+                subtitles::Segment {
+                    start: segment.start_timestamp() * 10,
+                    end: segment.end_timestamp() * 10,
+                    text: segment.to_string().trim().to_string(),
+                    language: language.clone(),
+                    words: subtitles::join_tokens(tokens),
+                    ..Default::default()
+                }
             })
             .collect::<Vec<subtitles::Segment>>();
 
         Ok(result.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_options_through_json() {
+        let options = TranscribeOptions {
+            language: None,
+            threads: Some(4),
+            beam_size: Some(5),
+            temperature_fallback: false,
+            initial_prompt: Some("Frodo, Samwise".into()),
+            translate: true,
+        };
+        assert_eq!(TranscribeOptions::from_json(&options.to_json()), options);
+
+        let default = TranscribeOptions::default();
+        assert_eq!(TranscribeOptions::from_json(&default.to_json()), default);
+    }
+
+    #[test]
+    fn tells_detected_language_from_missing_language() {
+        // Null asks Whisper to detect the language.
+        let detected =
+            TranscribeOptions::from_json(&json!({ "language": null }));
+        assert_eq!(detected.language, None);
+
+        // Options stored before the key existed keep the default.
+        let older = TranscribeOptions::from_json(&json!({ "threads": 2 }));
+        assert_eq!(older.language, TranscribeOptions::default().language);
+        assert_eq!(older.threads, Some(2));
+        assert!(older.temperature_fallback);
+    }
+}
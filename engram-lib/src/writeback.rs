@@ -0,0 +1,184 @@
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{EngramResult, get_engram_dir, subtitles};
+
+pub const DEFAULT_TEMPLATE: &str = "{stem}.engram.{ext}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SidecarFormat {
+    Srt,
+    Vtt,
+}
+
+impl SidecarFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SidecarFormat::Srt => "srt",
+            SidecarFormat::Vtt => "vtt",
+        }
+    }
+
+    pub fn generate(&self, segments: &[subtitles::Segment]) -> String {
+        match self {
+            SidecarFormat::Srt => subtitles::generate_srt(segments),
+            SidecarFormat::Vtt => subtitles::generate_vtt(segments),
+        }
+    }
+}
+
+impl std::str::FromStr for SidecarFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "srt" => Ok(SidecarFormat::Srt),
+            "vtt" => Ok(SidecarFormat::Vtt),
+            _ => Err(format!("Unknown subtitle format: {s}")),
+        }
+    }
+}
+
+// Where and how transcriptions are written out as subtitle files players
+// can pick up.
+#[derive(Debug, Clone)]
+pub struct SidecarOutput {
+    pub format: SidecarFormat,
+    // Relative to the media's directory. {stem} is the media name without
+    // its extension, {ext} the subtitle extension and {model} the Whisper
+    // model that made the transcription.
+    pub template: String,
+}
+
+impl Default for SidecarOutput {
+    fn default() -> Self {
+        Self {
+            format: SidecarFormat::Srt,
+            template: DEFAULT_TEMPLATE.into(),
+        }
+    }
+}
+
+impl SidecarOutput {
+    pub fn path_for(&self, media: &Path, model: &str) -> PathBuf {
+        let stem = media
+            .file_stem()
+            .map(|s| s.to_string_lossy())
+            .unwrap_or_default();
+        let name = self
+            .template
+            .replace("{stem}", &stem)
+            .replace("{ext}", self.format.extension())
+            .replace("{model}", model);
+
+        media.parent().unwrap_or(Path::new(".")).join(name)
+    }
+}
+
+// Writes to `target`, or to its `mirror_path` when the media's directory
+// cannot be written to. Returns where the file ended up.
+pub fn write_sidecar(target: &Path, contents: &str) -> EngramResult<PathBuf> {
+    write_or_mirror(target, contents, write_file)
+}
+
+fn write_or_mirror(
+    target: &Path,
+    contents: &str,
+    write: impl Fn(&Path, &str) -> std::io::Result<()>,
+) -> EngramResult<PathBuf> {
+    match write(target, contents) {
+        Ok(()) => Ok(target.to_path_buf()),
+        Err(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem
+            ) =>
+        {
+            let mirrored = mirror_path(target)?;
+            write(&mirrored, contents)?;
+            Ok(mirrored)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn write_file(path: &Path, contents: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, contents)
+}
+
+// The same path under the engram directory, for media on read-only drives.
+// /mnt/films/movie.engram.srt becomes <engram>/subtitles/mnt/films/....
+pub fn mirror_path(path: &Path) -> EngramResult<PathBuf> {
+    let mut mirrored = get_engram_dir()?.join("subtitles");
+
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => mirrored.push(
+                prefix
+                    .as_os_str()
+                    .to_string_lossy()
+                    .replace([':', '\\', '?'], ""),
+            ),
+            Component::Normal(name) => mirrored.push(name),
+            _ => {}
+        }
+    }
+
+    Ok(mirrored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io;
+
+    #[test]
+    fn fills_in_templates() {
+        let media = Path::new("/films/movie.2024.mkv");
+        assert_eq!(
+            SidecarOutput::default().path_for(media, "base"),
+            Path::new("/films/movie.2024.engram.srt")
+        );
+
+        let output = SidecarOutput {
+            format: SidecarFormat::Vtt,
+            template: "Subs/{stem}.{model}.{ext}".into(),
+        };
+        assert_eq!(
+            output.path_for(media, "large-v3"),
+            Path::new("/films/Subs/movie.2024.large-v3.vtt")
+        );
+    }
+
+    #[test]
+    fn mirrors_read_only_directories() {
+        let target = Path::new("/mnt/films/movie.engram.srt");
+        let written = RefCell::new(Vec::new());
+        let write = |path: &Path, _: &str| {
+            if path.starts_with("/mnt") {
+                return Err(io::Error::from(ErrorKind::ReadOnlyFilesystem));
+            }
+            written.borrow_mut().push(path.to_path_buf());
+            Ok(())
+        };
+
+        let path = write_or_mirror(target, "", write).unwrap();
+        assert_eq!(path, mirror_path(target).unwrap());
+        assert!(path.ends_with("subtitles/mnt/films/movie.engram.srt"));
+        assert_eq!(written.into_inner(), [path]);
+    }
+
+    #[test]
+    fn reports_other_write_errors() {
+        let write = |_: &Path, _: &str| Err(io::Error::from(ErrorKind::Other));
+        assert!(write_or_mirror(Path::new("/films/a.srt"), "", write).is_err());
+
+        let path =
+            write_or_mirror(Path::new("/films/a.srt"), "", |_, _| Ok(()));
+        assert_eq!(path.unwrap(), Path::new("/films/a.srt"));
+    }
+}
@@ -1,6 +1,14 @@
 use crate::pages::{
     home::HomePage, results::ResultsPage, settings::SettingsPage,
 };
+use engram_lib::cancel::CancellationToken;
+use engram_lib::export::batch::{
+    self, BatchOptions, BatchReport, ExportPreset,
+};
+use engram_lib::export::clip::ClipOptions;
+use engram_lib::indexer::{IndexEvent, IndexStage, Indexer, IndexerConfig};
+use engram_lib::search::SearchResult;
+use iced::futures::channel::{mpsc, oneshot};
 use iced::{Element, Task};
 
 #[derive(Debug, Clone, PartialEq)]
@@ -17,6 +25,35 @@ pub struct App {
 
     pub indexed_files: usize,
     pub indexed_size: String,
+    pub indexing: Option<IndexingState>,
+    // What went wrong during the last indexing run, shown in the footer.
+    pub index_errors: Option<String>,
+}
+
+pub struct IndexingState {
+    cancel: CancellationToken,
+    progress: String,
+    stage: Option<String>,
+    failed: usize,
+    last_error: Option<String>,
+}
+
+impl IndexingState {
+    pub fn status(&self) -> String {
+        let status = match &self.stage {
+            Some(stage) => format!("{} ({})", self.progress, stage),
+            None => self.progress.clone(),
+        };
+        match self.errors() {
+            Some(errors) => format!("{status}, {errors}"),
+            None => status,
+        }
+    }
+
+    fn errors(&self) -> Option<String> {
+        let error = self.last_error.as_ref()?;
+        Some(format!("{} FAILED, LAST: {error}", self.failed))
+    }
 }
 
 impl App {
@@ -36,10 +73,12 @@ impl Default for App {
         Self {
             current_page: Page::Home,
             home_page: HomePage::default(),
-            settings_page: SettingsPage::default(),
+            settings_page: SettingsPage,
             results_page: ResultsPage::default(),
             indexed_files: 152,
             indexed_size: "251GB".to_string(),
+            indexing: None,
+            index_errors: None,
         }
     }
 }
@@ -49,8 +88,15 @@ pub enum AppMessage {
     HomeMessage(crate::pages::home::Message),
     SettingsMessage(crate::pages::settings::Message),
     ResultsMessage(crate::pages::results::Message),
+    Indexing(IndexEvent),
+    IndexingFailed(String),
+    SearchFinished(Result<Vec<SearchResult>, String>),
+    ExportFinished(Result<BatchReport, String>),
 }
 
+// Results shown on the results page.
+const SEARCH_LIMIT: usize = 100;
+
 impl App {
     pub fn update(&mut self, message: AppMessage) -> Task<AppMessage> {
         match message {
@@ -59,12 +105,20 @@ impl App {
                     self.current_page = Page::Settings;
                     Task::none()
                 }
-                crate::pages::home::Message::TriggerReIndex => Task::none(),
+                crate::pages::home::Message::TriggerReIndex => {
+                    match &self.indexing {
+                        Some(state) => {
+                            state.cancel.cancel();
+                            Task::none()
+                        }
+                        None => self.start_indexing(),
+                    }
+                }
                 crate::pages::home::Message::SearchSubmit => {
                     let query = self.home_page.search_query.clone();
                     self.current_page = Page::SearchResults;
-                    self.results_page.query = query;
-                    Task::none()
+                    self.results_page.query = query.clone();
+                    self.search(query)
                 }
                 crate::pages::home::Message::SearchChanged(_)
                 | crate::pages::home::Message::OpenOptions => {
@@ -82,7 +136,196 @@ impl App {
                     self.current_page = Page::Home;
                     Task::none()
                 }
+                crate::pages::results::Message::ExportAll => {
+                    self.export_results()
+                }
             },
+            AppMessage::SearchFinished(result) => {
+                match result {
+                    Ok(results) => {
+                        self.results_page.results = results;
+                        self.results_page.status = None;
+                    }
+                    Err(error) => {
+                        self.results_page.results.clear();
+                        self.results_page.status =
+                            Some(format!("SEARCH FAILED: {error}"));
+                    }
+                }
+                Task::none()
+            }
+            AppMessage::ExportFinished(result) => {
+                self.results_page.exporting = false;
+                self.results_page.status = Some(match result {
+                    Ok(report) => format!(
+                        "EXPORTED {}/{} TO {}",
+                        report.succeeded(),
+                        report.items.len(),
+                        report
+                            .manifest
+                            .parent()
+                            .map(|dir| dir.display().to_string())
+                            .unwrap_or_default()
+                    ),
+                    Err(error) => format!("EXPORT FAILED: {error}"),
+                });
+                Task::none()
+            }
+            AppMessage::Indexing(event) => {
+                self.handle_index_event(event);
+                Task::none()
+            }
+            AppMessage::IndexingFailed(error) => {
+                self.index_errors = Some(format!("INDEXING FAILED: {error}"));
+                self.indexing = None;
+                Task::none()
+            }
+        }
+    }
+
+    fn start_indexing(&mut self) -> Task<AppMessage> {
+        let cancel = CancellationToken::new();
+        let token = cancel.clone();
+        let (tx, rx) = mpsc::unbounded();
+
+        // Indexing is blocking and can take hours, keep it off the UI thread.
+        std::thread::spawn(move || {
+            let events = tx.clone();
+            let result = Indexer::open(IndexerConfig::default()).and_then(
+                |mut indexer| {
+                    indexer.on_event(move |event| {
+                        let _ =
+                            events.unbounded_send(AppMessage::Indexing(event));
+                    });
+                    indexer.set_cancel_token(token);
+                    indexer.run()
+                },
+            );
+
+            if let Err(e) = result {
+                let _ = tx
+                    .unbounded_send(AppMessage::IndexingFailed(e.to_string()));
+            }
+        });
+
+        self.index_errors = None;
+        self.indexing = Some(IndexingState {
+            cancel,
+            progress: "STARTING".to_string(),
+            stage: None,
+            failed: 0,
+            last_error: None,
+        });
+
+        Task::run(rx, |message| message)
+    }
+
+    fn search(&mut self, query: String) -> Task<AppMessage> {
+        let (tx, rx) = oneshot::channel();
+        self.results_page.results.clear();
+        self.results_page.status = Some("SEARCHING".to_string());
+
+        std::thread::spawn(move || {
+            // Read-only, so searching works while the library is indexed.
+            let result = Indexer::open_search_index()
+                .and_then(|index| index.search(&query, SEARCH_LIMIT))
+                .map_err(|e| e.to_string());
+            let _ = tx.send(result);
+        });
+
+        Task::perform(rx, |result| {
+            AppMessage::SearchFinished(
+                result.unwrap_or_else(|_| {
+                    Err("Search was interrupted".to_string())
+                }),
+            )
+        })
+    }
+
+    fn export_results(&mut self) -> Task<AppMessage> {
+        let results = self.results_page.results.clone();
+        let (tx, rx) = oneshot::channel();
+        self.results_page.exporting = true;
+        self.results_page.status =
+            Some(format!("EXPORTING {} CLIPS", results.len()));
+
+        std::thread::spawn(move || {
+            let result = batch::default_export_dir()
+                .and_then(|dir| {
+                    let options = BatchOptions::new(
+                        ExportPreset::Clip(ClipOptions::default()),
+                        dir,
+                    );
+                    batch::export_batch(&results, &options)
+                })
+                .map_err(|e| e.to_string());
+            let _ = tx.send(result);
+        });
+
+        Task::perform(rx, |result| {
+            AppMessage::ExportFinished(
+                result.unwrap_or_else(|_| {
+                    Err("Export was interrupted".to_string())
+                }),
+            )
+        })
+    }
+
+    fn handle_index_event(&mut self, event: IndexEvent) {
+        if let IndexEvent::Finished(stats) = &event {
+            self.indexed_files = stats.indexed + stats.skipped;
+            self.index_errors =
+                self.indexing.take().and_then(|state| state.errors());
+            return;
+        }
+
+        let Some(state) = self.indexing.as_mut() else {
+            return;
+        };
+
+        match event {
+            IndexEvent::Discovered { total } => {
+                state.progress = format!("FOUND {total} FILES");
+            }
+            IndexEvent::FileStarted {
+                path,
+                position,
+                total,
+            } => {
+                let name = path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                state.progress =
+                    format!("{}/{}: {}", position + 1, total, name);
+                state.stage = None;
+            }
+            IndexEvent::Stage { stage, .. } => {
+                state.stage = Some(match stage {
+                    IndexStage::ReadingSubtitles => "READING SUBTITLES".into(),
+                    IndexStage::ExtractingSubtitles => {
+                        "EXTRACTING SUBTITLES".into()
+                    }
+                    IndexStage::RecognizingText => "RECOGNIZING TEXT".into(),
+                    IndexStage::ExtractingAudio => "EXTRACTING AUDIO".into(),
+                    IndexStage::DownloadingModel(percent) => {
+                        format!("DOWNLOADING MODEL {percent}%")
+                    }
+                    IndexStage::Transcribing(percent) => {
+                        format!("TRANSCRIBING {percent}%")
+                    }
+                    IndexStage::Indexing => "INDEXING".into(),
+                });
+            }
+            IndexEvent::FileFailed { path, error } => {
+                let name = path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                state.failed += 1;
+                state.last_error = Some(format!("{name}: {error}"));
+            }
+            _ => {}
         }
     }
 
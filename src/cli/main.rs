@@ -0,0 +1,413 @@
+mod output;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use engram_lib::EngramResult;
+use engram_lib::indexer::{IndexEvent, Indexer, IndexerConfig};
+use engram_lib::models::ModelManager;
+use engram_lib::subtitles;
+use engram_lib::sync::SyncCorrection;
+use engram_lib::transcribe::{TranscribeOptions, TranscriberModel};
+use engram_lib::writeback::{self, SidecarFormat, SidecarOutput};
+
+use output::Output;
+
+#[derive(Debug, Parser)]
+#[command(name = "engram-cli", version, about = "Local clip search")]
+struct Cli {
+    /// Print machine-readable JSON instead of plain text.
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Index every media file under the given directories.
+    Index {
+        /// Directories to scan. Defaults to the home directory.
+        roots: Vec<PathBuf>,
+
+        /// Whisper model used for files without subtitles.
+        #[arg(long, default_value = "base")]
+        model: TranscriberModel,
+
+        /// Imported model to use instead, see `models import`.
+        #[arg(long, value_name = "NAME")]
+        custom_model: Option<String>,
+
+        #[command(flatten)]
+        transcribe: TranscribeArgs,
+
+        /// Skip reading image based subtitle tracks with Tesseract.
+        #[arg(long)]
+        no_ocr: bool,
+
+        /// Save Whisper transcriptions next to the media as srt or vtt.
+        #[arg(long, value_name = "FORMAT")]
+        write_subtitles: Option<SidecarFormat>,
+
+        /// File name for saved transcriptions, relative to the media. Takes
+        /// {stem}, {ext} and {model}.
+        #[arg(long, default_value = writeback::DEFAULT_TEMPLATE)]
+        subtitle_template: String,
+    },
+    /// Search the indexed subtitles.
+    Search {
+        query: String,
+
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+
+        /// Only search subtitle tracks in this language, as a code such as
+        /// "en" or "fre" or a name such as "French".
+        #[arg(long)]
+        language: Option<String>,
+    },
+    /// Print the stored transcript of a file.
+    Show { file: PathBuf },
+    /// Write the stored transcript of a file to disk or stdout.
+    Export {
+        file: PathBuf,
+
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Srt)]
+        format: ExportFormat,
+
+        /// Output path. Prints to stdout when omitted.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Remove files that no longer exist from the database and index.
+    Prune,
+    /// Decode a subtitle file with a fixed encoding instead of detecting it.
+    Encoding {
+        subtitle: PathBuf,
+
+        /// Label such as windows-1251 or shift_jis. Detects it again when
+        /// omitted.
+        encoding: Option<String>,
+    },
+    /// Line a subtitle file up with the speech in its video.
+    Sync {
+        subtitle: PathBuf,
+
+        /// Shift by this many milliseconds instead of detecting the offset.
+        #[arg(long, allow_hyphen_values = true, conflicts_with = "reset")]
+        offset: Option<i64>,
+
+        /// Go back to the original timing.
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Show or set the Whisper options for files under a directory. These
+    /// take precedence over the ones passed to `index`.
+    Transcription {
+        root: PathBuf,
+
+        #[command(flatten)]
+        options: TranscribeArgs,
+
+        /// Go back to the options passed to `index`.
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Manage the Whisper models used for transcription.
+    Models {
+        #[command(subcommand)]
+        command: ModelsCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ModelsCommand {
+    /// Show every model with its size and whether it is installed.
+    List,
+    /// Download a model and check it against its published checksum.
+    Download { model: TranscriberModel },
+    /// Install a GGML file from disk, such as a fine-tuned model or one
+    /// downloaded on another machine.
+    Import {
+        file: PathBuf,
+
+        /// Name to use it by with `index --custom-model`. Defaults to the
+        /// file name. Ignored for the official models, which are recognized
+        /// by their checksum.
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+struct TranscribeArgs {
+    /// Spoken language as a code such as "fr" or a name such as "French",
+    /// or "auto" to detect it. Defaults to English.
+    #[arg(long, value_parser = parse_language)]
+    language: Option<String>,
+
+    /// CPU threads Whisper uses.
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Use beam search with this width instead of greedy decoding.
+    #[arg(long)]
+    beam_size: Option<usize>,
+
+    /// Keep the first decoding even when it looks like a repetition loop.
+    #[arg(long)]
+    no_temperature_fallback: bool,
+
+    /// Text to prime Whisper with, such as character names.
+    #[arg(long)]
+    prompt: Option<String>,
+
+    /// Transcribe into English whatever language is spoken.
+    #[arg(long)]
+    translate: bool,
+}
+
+impl TranscribeArgs {
+    fn is_empty(&self) -> bool {
+        self.language.is_none()
+            && self.threads.is_none()
+            && self.beam_size.is_none()
+            && !self.no_temperature_fallback
+            && self.prompt.is_none()
+            && !self.translate
+    }
+
+    // The flags that were passed on top of `options`.
+    fn apply(self, mut options: TranscribeOptions) -> TranscribeOptions {
+        if let Some(language) = self.language {
+            options.language = (language != "auto").then_some(language);
+        }
+        if let Some(threads) = self.threads {
+            options.threads = Some(threads);
+        }
+        if let Some(beam_size) = self.beam_size {
+            options.beam_size = Some(beam_size);
+        }
+        if self.no_temperature_fallback {
+            options.temperature_fallback = false;
+        }
+        if let Some(prompt) = self.prompt {
+            options.initial_prompt = Some(prompt);
+        }
+        if self.translate {
+            options.translate = true;
+        }
+
+        options
+    }
+}
+
+fn parse_language(language: &str) -> Result<String, String> {
+    if language.eq_ignore_ascii_case("auto") {
+        return Ok("auto".into());
+    }
+
+    subtitles::normalize_language(language)
+        .map(String::from)
+        .ok_or_else(|| format!("Unknown language: {language}"))
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Srt,
+    Vtt,
+    Json,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let out = Output::new(cli.json);
+
+    match run(cli.command, &out) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            out.error(&e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command, out: &Output) -> EngramResult<()> {
+    match command {
+        Command::Index {
+            roots,
+            model,
+            custom_model,
+            transcribe,
+            no_ocr,
+            write_subtitles,
+            subtitle_template,
+        } => {
+            let mut config = IndexerConfig {
+                model,
+                custom_model,
+                transcribe_options: transcribe
+                    .apply(TranscribeOptions::default()),
+                ocr: !no_ocr,
+                write_sidecars: write_subtitles.map(|format| SidecarOutput {
+                    format,
+                    template: subtitle_template,
+                }),
+                ..Default::default()
+            };
+            // Stored paths should not depend on where the CLI was run from.
+            if !roots.is_empty() {
+                config.roots = roots
+                    .into_iter()
+                    .map(|root| root.canonicalize().unwrap_or(root))
+                    .collect();
+            }
+
+            let mut indexer = Indexer::open(config)?;
+            let printer = *out;
+            indexer.on_event(move |event| {
+                // The final stats are printed once `run` returns.
+                if !matches!(event, IndexEvent::Finished(_)) {
+                    printer.index_event(&event);
+                }
+            });
+
+            let stats = indexer.run()?;
+            out.index_stats(&stats);
+        }
+        Command::Search {
+            query,
+            limit,
+            language,
+        } => {
+            let index = Indexer::open_search_index()?;
+            let results =
+                index.search_language(&query, limit, language.as_deref())?;
+            out.search_results(&results);
+        }
+        Command::Show { file } => {
+            let indexer = Indexer::open(IndexerConfig::default())?;
+            let segments = load_segments(&indexer, &file)?;
+            out.segments(&file, &segments);
+        }
+        Command::Export {
+            file,
+            format,
+            output,
+        } => {
+            let indexer = Indexer::open(IndexerConfig::default())?;
+            let segments = load_segments(&indexer, &file)?;
+
+            let contents = match format {
+                ExportFormat::Srt => subtitles::generate_srt(&segments),
+                ExportFormat::Vtt => subtitles::generate_vtt(&segments),
+                ExportFormat::Json => {
+                    format!("{}\n", output::segments_json(&file, &segments))
+                }
+            };
+
+            match output {
+                Some(path) => std::fs::write(path, contents)?,
+                None => print!("{contents}"),
+            }
+        }
+        Command::Prune => {
+            let mut indexer = Indexer::open(IndexerConfig::default())?;
+            let removed = indexer.prune_missing()?;
+            out.pruned(&removed);
+        }
+        Command::Encoding { subtitle, encoding } => {
+            let subtitle = subtitle.canonicalize().unwrap_or(subtitle);
+            let mut indexer = Indexer::open(IndexerConfig::default())?;
+            let reindexed = indexer
+                .set_encoding_override(&subtitle, encoding.as_deref())?;
+            out.encoding_override(&subtitle, encoding.as_deref(), reindexed);
+        }
+        Command::Sync {
+            subtitle,
+            offset,
+            reset,
+        } => {
+            let subtitle = subtitle.canonicalize().unwrap_or(subtitle);
+            let mut indexer = Indexer::open(IndexerConfig::default())?;
+
+            if reset || offset.is_some() {
+                let correction = offset.map(|offset| SyncCorrection {
+                    offset,
+                    ..Default::default()
+                });
+                let reindexed =
+                    indexer.set_sync_correction(&subtitle, correction)?;
+                out.sync_correction(&subtitle, correction.as_ref(), reindexed);
+            } else {
+                let (result, reindexed) = indexer.sync_subtitle(&subtitle)?;
+                out.sync_result(&subtitle, &result, reindexed);
+            }
+        }
+        Command::Transcription {
+            root,
+            options,
+            reset,
+        } => {
+            let root = root.canonicalize().unwrap_or(root);
+            let indexer = Indexer::open(IndexerConfig::default())?;
+
+            if reset {
+                indexer.set_root_transcribe_options(&root, None)?;
+            } else if !options.is_empty() {
+                let options = options.apply(indexer.transcribe_options(&root)?);
+                indexer.set_root_transcribe_options(&root, Some(&options))?;
+            }
+            out.transcribe_options(&root, &indexer.transcribe_options(&root)?);
+        }
+        Command::Models { command } => {
+            let mut models = ModelManager::open()?;
+            match command {
+                ModelsCommand::List => {
+                    // Offline the sizes of models that are not installed
+                    // stay approximate.
+                    let _ = models.refresh_checksums();
+                    out.models(&models.list()?);
+                }
+                ModelsCommand::Download { model } => {
+                    let mut last = None;
+                    let path = models.install(model, |done, total| {
+                        out.download_progress(done, total, &mut last);
+                    })?;
+                    out.model_installed(model.name(), &path);
+                }
+                ModelsCommand::Import { file, name } => {
+                    // Needed to recognize the official models.
+                    let _ = models.refresh_checksums();
+                    let entry = models.import(&file, name.as_deref())?;
+                    out.model_installed(&entry.name, &entry.path);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn load_segments(
+    indexer: &Indexer,
+    file: &std::path::Path,
+) -> EngramResult<Vec<subtitles::Segment>> {
+    // Paths are stored exactly as discovered, so try the canonical form too.
+    let candidates = [Some(file.to_path_buf()), file.canonicalize().ok()];
+
+    for path in candidates.into_iter().flatten() {
+        if let Some(segments) =
+            indexer.db().load_segments(&path.to_string_lossy())?
+        {
+            return Ok(segments);
+        }
+    }
+
+    Err(engram_lib::errors::EngramError::SearchError(format!(
+        "{} has not been indexed",
+        file.display()
+    )))
+}
@@ -0,0 +1,529 @@
+use std::path::Path;
+
+use engram_lib::errors::EngramError;
+use engram_lib::indexer::{IndexEvent, IndexStage, IndexStats, SubtitleSource};
+use engram_lib::models::ModelEntry;
+use engram_lib::search::SearchResult;
+use engram_lib::subtitles::{self, Segment};
+use engram_lib::sync::{SyncCorrection, SyncResult};
+use engram_lib::transcribe::TranscribeOptions;
+use serde_json::{Value, json};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    json: bool,
+}
+
+impl Output {
+    pub fn new(json: bool) -> Self {
+        Self { json }
+    }
+
+    pub fn error(&self, error: &EngramError) {
+        if self.json {
+            eprintln!("{}", json!({ "error": error.to_string() }));
+        } else {
+            eprintln!("error: {error}");
+        }
+    }
+
+    // Progress goes to stderr so stdout stays clean for the final result.
+    pub fn index_event(&self, event: &IndexEvent) {
+        if self.json {
+            eprintln!("{}", index_event_json(event));
+            return;
+        }
+
+        match event {
+            IndexEvent::Discovered { total } => {
+                eprintln!("Found {total} media files");
+            }
+            IndexEvent::FileStarted {
+                path,
+                position,
+                total,
+            } => {
+                eprintln!("[{}/{}] {}", position + 1, total, path.display());
+            }
+            IndexEvent::Stage { stage, .. } => match stage {
+                // Whisper reports progress very often, only keep milestones.
+                IndexStage::Transcribing(percent)
+                | IndexStage::DownloadingModel(percent)
+                    if percent % 10 != 0 => {}
+                stage => eprintln!("    {}", stage_name(*stage)),
+            },
+            IndexEvent::FileSkipped { .. } => eprintln!("    up to date"),
+            IndexEvent::SubtitleWarnings { path, warnings } => {
+                for warning in warnings {
+                    eprintln!("    {}: {warning}", path.display());
+                }
+            }
+            IndexEvent::SidecarWritten { subtitle, .. } => {
+                eprintln!("    wrote {}", subtitle.display());
+            }
+            IndexEvent::FileFinished {
+                source, segments, ..
+            } => {
+                eprintln!(
+                    "    {} segments from {}",
+                    segments,
+                    source_name(*source)
+                );
+            }
+            IndexEvent::FileFailed { error, .. } => {
+                eprintln!("    failed: {error}");
+            }
+            IndexEvent::Finished(_) => {}
+        }
+    }
+
+    pub fn index_stats(&self, stats: &IndexStats) {
+        if self.json {
+            println!("{}", stats_json(stats));
+            return;
+        }
+
+        println!(
+            "Discovered {}, indexed {}, up to date {}, failed {}{}",
+            stats.discovered,
+            stats.indexed,
+            stats.skipped,
+            stats.failed.len(),
+            if stats.cancelled { " (cancelled)" } else { "" }
+        );
+
+        for (path, error) in &stats.failed {
+            println!("  {}: {}", path.display(), error);
+        }
+    }
+
+    pub fn search_results(&self, results: &[SearchResult]) {
+        if self.json {
+            let results: Vec<Value> = results
+                .iter()
+                .map(|r| {
+                    json!({
+                        "file": r.file.to_string_lossy(),
+                        "score": r.score,
+                        "segment": segment_json(&r.segment),
+                        "match_start_ms": r.span().0,
+                        "match_end_ms": r.span().1,
+                    })
+                })
+                .collect();
+            println!("{}", Value::Array(results));
+            return;
+        }
+
+        if results.is_empty() {
+            println!("No results");
+        }
+
+        for result in results {
+            let language = result
+                .segment
+                .language
+                .as_deref()
+                .map(|code| format!(" ({code})"))
+                .unwrap_or_default();
+            // Only the matched words when we know when they are said.
+            let (start, end) = result.span();
+            println!(
+                "{} [{} --> {}]{} {}",
+                result.file.display(),
+                subtitles::format_timestamp(start),
+                subtitles::format_timestamp(end),
+                language,
+                result.segment.text.replace('\n', " ")
+            );
+        }
+    }
+
+    pub fn segments(&self, file: &Path, segments: &[Segment]) {
+        if self.json {
+            println!("{}", segments_json(file, segments));
+            return;
+        }
+
+        for segment in segments {
+            println!(
+                "[{} --> {}] {}",
+                subtitles::format_timestamp(segment.start),
+                subtitles::format_timestamp(segment.end),
+                segment.text.replace('\n', " ")
+            );
+        }
+    }
+
+    pub fn pruned(&self, removed: &[String]) {
+        if self.json {
+            println!("{}", json!({ "removed": removed }));
+            return;
+        }
+
+        for path in removed {
+            println!("Removed {path}");
+        }
+        println!("Pruned {} files", removed.len());
+    }
+
+    pub fn encoding_override(
+        &self,
+        subtitle: &Path,
+        encoding: Option<&str>,
+        reindexed: usize,
+    ) {
+        if self.json {
+            println!(
+                "{}",
+                json!({
+                    "file": subtitle.to_string_lossy(),
+                    "encoding": encoding,
+                    "reindexed": reindexed,
+                })
+            );
+            return;
+        }
+
+        match encoding {
+            Some(encoding) => {
+                println!("{} is decoded as {encoding}", subtitle.display())
+            }
+            None => println!("{} is detected again", subtitle.display()),
+        }
+        println!("Reindexed {reindexed} files");
+    }
+
+    pub fn sync_result(
+        &self,
+        subtitle: &Path,
+        result: &SyncResult,
+        reindexed: usize,
+    ) {
+        if self.json {
+            println!(
+                "{}",
+                json!({
+                    "file": subtitle.to_string_lossy(),
+                    "correction": correction_json(&result.correction),
+                    "score": result.score,
+                    "original_score": result.original_score,
+                    "reindexed": reindexed,
+                })
+            );
+            return;
+        }
+
+        if result.correction.is_identity() {
+            println!("{} is already in sync", subtitle.display());
+        } else {
+            println!(
+                "{}: {} (match {:.0}% -> {:.0}%)",
+                subtitle.display(),
+                describe_correction(&result.correction),
+                result.original_score.max(0.0) * 100.0,
+                result.score.max(0.0) * 100.0
+            );
+        }
+        println!("Reindexed {reindexed} files");
+    }
+
+    pub fn sync_correction(
+        &self,
+        subtitle: &Path,
+        correction: Option<&SyncCorrection>,
+        reindexed: usize,
+    ) {
+        if self.json {
+            println!(
+                "{}",
+                json!({
+                    "file": subtitle.to_string_lossy(),
+                    "correction": correction.map(correction_json),
+                    "reindexed": reindexed,
+                })
+            );
+            return;
+        }
+
+        match correction {
+            Some(correction) => println!(
+                "{}: {}",
+                subtitle.display(),
+                describe_correction(correction)
+            ),
+            None => {
+                println!("{} uses its original timing", subtitle.display())
+            }
+        }
+        println!("Reindexed {reindexed} files");
+    }
+
+    pub fn transcribe_options(&self, root: &Path, options: &TranscribeOptions) {
+        if self.json {
+            println!(
+                "{}",
+                json!({
+                    "root": root.to_string_lossy(),
+                    "options": options.to_json(),
+                })
+            );
+            return;
+        }
+
+        let optional = |value: Option<String>, none: &str| {
+            value.unwrap_or_else(|| none.to_string())
+        };
+        println!("Whisper options for {}:", root.display());
+        println!(
+            "  language:             {}",
+            optional(options.language.clone(), "detected")
+        );
+        println!(
+            "  threads:              {}",
+            optional(options.threads.map(|n| n.to_string()), "automatic")
+        );
+        println!(
+            "  beam size:            {}",
+            optional(options.beam_size.map(|n| n.to_string()), "greedy")
+        );
+        println!("  temperature fallback: {}", options.temperature_fallback);
+        println!(
+            "  prompt:               {}",
+            optional(options.initial_prompt.clone(), "none")
+        );
+        println!("  translate:            {}", options.translate);
+    }
+
+    pub fn models(&self, models: &[ModelEntry]) {
+        if self.json {
+            let models: Vec<Value> = models
+                .iter()
+                .map(|model| {
+                    json!({
+                        "name": model.name,
+                        "path": model.path.to_string_lossy(),
+                        "size": model.size,
+                        "installed": model.installed,
+                        "verified": model.verified,
+                        "custom": model.custom,
+                    })
+                })
+                .collect();
+            println!("{}", json!({ "models": models }));
+            return;
+        }
+
+        for model in models {
+            let status = match (model.installed, model.verified) {
+                (false, _) => "not installed",
+                (true, true) => "installed",
+                (true, false) => "installed, not verified",
+            };
+            println!(
+                "{:<12} {:>9}  {}{}",
+                model.name,
+                format_size(model.size),
+                status,
+                if model.custom { " (imported)" } else { "" }
+            );
+        }
+    }
+
+    // Every 10%, or every 10MB when the size is unknown. `last` carries the
+    // last step printed between calls.
+    pub fn download_progress(
+        &self,
+        done: u64,
+        total: Option<u64>,
+        last: &mut Option<u64>,
+    ) {
+        let step = match total {
+            Some(total) if total > 0 => done * 10 / total,
+            _ => done / 10_000_000,
+        };
+        if *last == Some(step) {
+            return;
+        }
+        *last = Some(step);
+
+        if self.json {
+            eprintln!(
+                "{}",
+                json!({ "event": "download", "bytes": done, "total": total })
+            );
+        } else {
+            match total {
+                Some(total) => eprintln!(
+                    "Downloaded {} of {}",
+                    format_size(done),
+                    format_size(total)
+                ),
+                None => eprintln!("Downloaded {}", format_size(done)),
+            }
+        }
+    }
+
+    pub fn model_installed(&self, name: &str, path: &Path) {
+        if self.json {
+            println!(
+                "{}",
+                json!({ "model": name, "path": path.to_string_lossy() })
+            );
+        } else {
+            println!("Installed {name} at {}", path.display());
+        }
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes >= 1_000_000_000 {
+        format!("{:.2} GB", bytes as f64 / 1e9)
+    } else {
+        format!("{:.0} MB", bytes as f64 / 1e6)
+    }
+}
+
+fn correction_json(correction: &SyncCorrection) -> Value {
+    json!({ "offset_ms": correction.offset, "scale": correction.scale })
+}
+
+fn describe_correction(correction: &SyncCorrection) -> String {
+    let mut parts = vec![format!("shifted by {}ms", correction.offset)];
+    if correction.scale != 1.0 {
+        parts.push(format!("stretched by {:.4}", correction.scale));
+    }
+    parts.join(", ")
+}
+
+pub fn segment_json(segment: &Segment) -> Value {
+    let mut value = json!({
+        "start_ms": segment.start,
+        "end_ms": segment.end,
+        "text": segment.text,
+        "language": segment.language,
+    });
+    if !segment.words.is_empty() {
+        value["words"] = segment
+            .words
+            .iter()
+            .map(|word| {
+                json!({
+                    "start_ms": word.start,
+                    "end_ms": word.end,
+                    "text": word.text,
+                })
+            })
+            .collect();
+    }
+    value
+}
+
+pub fn segments_json(file: &Path, segments: &[Segment]) -> Value {
+    json!({
+        "file": file.to_string_lossy(),
+        "segments": segments.iter().map(segment_json).collect::<Vec<_>>(),
+    })
+}
+
+fn stats_json(stats: &IndexStats) -> Value {
+    json!({
+        "discovered": stats.discovered,
+        "indexed": stats.indexed,
+        "skipped": stats.skipped,
+        "cancelled": stats.cancelled,
+        "failed": stats
+            .failed
+            .iter()
+            .map(|(path, error)| {
+                json!({ "file": path.to_string_lossy(), "error": error })
+            })
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn index_event_json(event: &IndexEvent) -> Value {
+    match event {
+        IndexEvent::Discovered { total } => {
+            json!({ "event": "discovered", "total": total })
+        }
+        IndexEvent::FileStarted {
+            path,
+            position,
+            total,
+        } => json!({
+            "event": "file_started",
+            "file": path.to_string_lossy(),
+            "position": position,
+            "total": total,
+        }),
+        IndexEvent::Stage { path, stage } => {
+            let mut value = json!({
+                "event": "stage",
+                "file": path.to_string_lossy(),
+                "stage": stage_name(*stage),
+            });
+            if let IndexStage::Transcribing(percent)
+            | IndexStage::DownloadingModel(percent) = stage
+            {
+                value["percent"] = json!(percent);
+            }
+            value
+        }
+        IndexEvent::FileSkipped { path } => {
+            json!({ "event": "file_skipped", "file": path.to_string_lossy() })
+        }
+        IndexEvent::SubtitleWarnings { path, warnings } => json!({
+            "event": "subtitle_warnings",
+            "file": path.to_string_lossy(),
+            "warnings": warnings
+                .iter()
+                .map(|w| json!({ "line": w.line, "message": w.message }))
+                .collect::<Vec<_>>(),
+        }),
+        IndexEvent::SidecarWritten { path, subtitle } => json!({
+            "event": "sidecar_written",
+            "file": path.to_string_lossy(),
+            "subtitle": subtitle.to_string_lossy(),
+        }),
+        IndexEvent::FileFinished {
+            path,
+            source,
+            segments,
+        } => json!({
+            "event": "file_finished",
+            "file": path.to_string_lossy(),
+            "source": source_name(*source),
+            "segments": segments,
+        }),
+        IndexEvent::FileFailed { path, error } => json!({
+            "event": "file_failed",
+            "file": path.to_string_lossy(),
+            "error": error,
+        }),
+        IndexEvent::Finished(stats) => {
+            json!({ "event": "finished", "stats": stats_json(stats) })
+        }
+    }
+}
+
+fn stage_name(stage: IndexStage) -> &'static str {
+    match stage {
+        IndexStage::ReadingSubtitles => "reading_subtitles",
+        IndexStage::ExtractingSubtitles => "extracting_subtitles",
+        IndexStage::RecognizingText => "recognizing_text",
+        IndexStage::ExtractingAudio => "extracting_audio",
+        IndexStage::DownloadingModel(_) => "downloading_model",
+        IndexStage::Transcribing(_) => "transcribing",
+        IndexStage::Indexing => "indexing",
+    }
+}
+
+fn source_name(source: SubtitleSource) -> &'static str {
+    match source {
+        SubtitleSource::Sidecar => "sidecar",
+        SubtitleSource::Embedded => "embedded",
+        SubtitleSource::Recognized => "recognized",
+        SubtitleSource::Transcribed => "transcribed",
+    }
+}
@@ -52,10 +52,17 @@ impl HomePage {
                 .padding(8)
                 .style(styles::header_button_style),
             Space::with_width(20),
-            button(text("< RE-INDEX >").size(14))
-                .on_press(Message::TriggerReIndex)
-                .padding(8)
-                .style(styles::header_button_style),
+            button(
+                text(if app.indexing.is_some() {
+                    "< CANCEL >"
+                } else {
+                    "< RE-INDEX >"
+                })
+                .size(14)
+            )
+            .on_press(Message::TriggerReIndex)
+            .padding(8)
+            .style(styles::header_button_style),
         ]
         .align_y(Vertical::Top);
 
@@ -105,12 +112,20 @@ impl HomePage {
             .center_x(Length::Fill),
         ];
 
-        let footer = row![
-            text(format!(
+        let status = match (&app.indexing, &app.index_errors) {
+            (Some(state), _) => format!("[ INDEXING {} ]", state.status()),
+            (None, Some(errors)) => format!(
+                "[ INDEXED: {}, SIZE: {}, {} ]",
+                app.indexed_files, app.indexed_size, errors
+            ),
+            (None, None) => format!(
                 "[ INDEXED: {}, SIZE: {} ]",
                 app.indexed_files, app.indexed_size
-            ))
-            .size(14),
+            ),
+        };
+
+        let footer = row![
+            text(status).size(14),
             Space::with_width(Length::Fill),
             text("[ MADE BY GRAPESALT ]").size(14),
         ]
@@ -1,33 +1,54 @@
 use crate::styles;
+use engram_lib::search::SearchResult;
+use engram_lib::subtitles;
 use iced::{
     Element, Length, Task,
     alignment::Vertical,
-    widget::{Space, button, column, container, row, text},
+    widget::{Space, button, column, container, row, scrollable, text},
 };
 
 #[derive(Debug, Default)]
 pub struct ResultsPage {
     pub query: String,
+    pub results: Vec<SearchResult>,
+    // Shown in the footer while searching or exporting and after errors.
+    pub status: Option<String>,
+    pub exporting: bool,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     Back,
+    ExportAll,
 }
 
 impl ResultsPage {
     #[allow(dead_code)]
     pub fn update(&mut self, message: Message) -> Task<Message> {
         match message {
-            Message::Back => Task::none(),
+            Message::Back | Message::ExportAll => Task::none(),
         }
     }
 
     pub fn view(&self) -> Element<'_, Message> {
+        let export_label = if self.exporting {
+            "< EXPORTING >"
+        } else {
+            "< EXPORT ALL >"
+        };
+        let mut export = button(text(export_label).size(14))
+            .padding(8)
+            .style(styles::header_button_style);
+        if !self.exporting && !self.results.is_empty() {
+            export = export.on_press(Message::ExportAll);
+        }
+
         let header = row![
             Space::with_width(20),
             text(format!("SHOWING RESULTS FOR \"{}\"", self.query)).size(18),
             Space::with_width(Length::Fill),
+            export,
+            Space::with_width(20),
             button(text("< BACK >").size(14))
                 .on_press(Message::Back)
                 .padding(8)
@@ -35,15 +56,44 @@ impl ResultsPage {
         ]
         .align_y(Vertical::Center);
 
+        let results = column(self.results.iter().map(|result| {
+            let name = result
+                .file
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            row![
+                text(format!(
+                    "[{}]",
+                    subtitles::format_timestamp(result.span().0)
+                ))
+                .size(14),
+                Space::with_width(20),
+                text(name).size(14).width(Length::FillPortion(1)),
+                Space::with_width(20),
+                text(result.segment.text.replace('\n', " "))
+                    .size(14)
+                    .width(Length::FillPortion(2)),
+            ]
+            .into()
+        }))
+        .spacing(10)
+        .padding(20);
+
+        let status = self
+            .status
+            .clone()
+            .unwrap_or_else(|| format!("{} RESULTS", self.results.len()));
+
         let footer = row![
-            text(format!("[ INDEXED: 2, SIZE: 0.1GB ]")).size(14),
+            text(format!("[ {status} ]")).size(14),
             Space::with_width(Length::Fill),
-            text("<- 0 1 2 ->").size(14),
         ]
         .padding(20);
 
         container(
-            column![header, Space::with_height(Length::Fill), footer,]
+            column![header, scrollable(results).height(Length::Fill), footer,]
                 .padding(20)
                 .width(Length::Fill)
                 .height(Length::Fill),
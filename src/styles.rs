@@ -47,7 +47,7 @@ pub fn body_button_style(
 
     button::Style {
         background: Some(Background::Color(background_color)),
-        text_color: text_color,
+        text_color,
         border: Border {
             radius: 0.0.into(),
             width: 1.0,